@@ -0,0 +1,60 @@
+//! Compares sequential and parallel chunk decompression of a single bundle.
+//!
+//! The bundle is read from the path in `POBBIN_BUNDLE`, e.g.:
+//! `POBBIN_BUNDLE=./Bundles2/_.index.bin cargo bench --features parallel`
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+#[cfg(feature = "pure-rust")]
+#[allow(dead_code, unused_imports)]
+#[path = "../src/bundle/oodle/mod.rs"]
+mod oodle;
+#[allow(dead_code)]
+#[path = "../src/bundle/ooz.rs"]
+mod ooz;
+#[allow(dead_code)]
+#[path = "../src/bundle/parse.rs"]
+mod parse;
+
+fn decompress(c: &mut Criterion) {
+    let Ok(path) = std::env::var("POBBIN_BUNDLE") else {
+        eprintln!("POBBIN_BUNDLE is not set, skipping decompression benchmark");
+        return;
+    };
+
+    let data = std::fs::read(path).expect("bundle can not be read");
+    let (compressed, head) = parse::Head::parse(&data).expect("bundle header can not be parsed");
+    let payload = &head.payload;
+    let chunks = ooz::split_chunks(compressed, &payload.chunk_sizes).expect("bundle is truncated");
+
+    let mut group = c.benchmark_group("decompress");
+    group.throughput(Throughput::Bytes(payload.uncompressed_size));
+
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            ooz::decompress_sequential(
+                &chunks,
+                payload.chunk_unpacked_size as usize,
+                0,
+                payload.uncompressed_size as usize,
+            )
+            .unwrap()
+        })
+    });
+
+    group.bench_function("parallel", |b| {
+        b.iter(|| {
+            ooz::decompress_parallel(
+                &chunks,
+                payload.chunk_unpacked_size as usize,
+                0,
+                payload.uncompressed_size as usize,
+            )
+            .unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, decompress);
+criterion_main!(benches);
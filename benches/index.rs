@@ -0,0 +1,156 @@
+//! Measures loading the index, from the bundles and from a snapshot,
+//! and the memory retained by the parsed index.
+//!
+//! As a baseline, the memory of the files and paths in the layout of earlier versions is
+//! measured as well: a hash map of file locations with an owned bundle name per file and
+//! a separate allocation per path. The directory tree is not part of the baseline.
+//!
+//! The index is read from the game directory in `POBBIN_GAME`, e.g.:
+//! `POBBIN_GAME=~/Games/PathOfExile cargo bench --bench index`
+//!
+//! Without `POBBIN_GAME` a synthetic index with 500k files in 10k bundles is generated,
+//! with path and bundle name lengths similar to the game.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// Keeps track of the amount of currently allocated bytes.
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Location of a file in the layout of earlier versions.
+#[allow(dead_code)]
+struct BaselineFileRef {
+    bundle_name: String,
+    file_offset: u32,
+    file_size: u32,
+}
+
+/// Files and paths of the index in the layout of earlier versions.
+#[allow(dead_code)]
+struct Baseline {
+    refs: HashMap<u64, BaselineFileRef>,
+    paths: Vec<String>,
+}
+
+impl Baseline {
+    fn new<F>(index: &pobbin_assets::IndexBundle<F>) -> Self {
+        let mut refs = HashMap::new();
+        let mut paths = Vec::new();
+        for (path, fref) in index.iter() {
+            let baseline = BaselineFileRef {
+                bundle_name: index.bundle_name(&fref).to_owned(),
+                file_offset: fref.file_offset,
+                file_size: fref.file_size,
+            };
+            refs.insert(index.hasher().file(path), baseline);
+            paths.push(path.to_owned());
+        }
+        Self { refs, paths }
+    }
+}
+
+/// Returns the amount of bytes retained by the result of `f`.
+fn retained<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let value = f();
+    (value, ALLOCATED.load(Ordering::Relaxed) - before)
+}
+
+fn mib(bytes: usize) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// Writes a synthetic index with empty files to a temporary directory.
+fn synthetic() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("Bundles2")).unwrap();
+
+    let mut index = pobbin_assets::IndexWriter::new(pobbin_assets::PathHasher::Murmur64A);
+    for bundle in 0..10_000 {
+        let (group, sub) = (bundle % 97, bundle % 13);
+
+        let mut writer = pobbin_assets::BundleWriter::new();
+        for file in 0..50 {
+            let path = format!(
+                "Art/Textures/Interface/Group{group}/Sub{sub}/texture_file_{bundle}_{file}.dds"
+            );
+            writer.add(path, &[]);
+        }
+
+        let name = format!("Folder/Textures/Interface/Group{group}/Bundle_Name_{bundle}");
+        index.add_bundle(name, &writer);
+    }
+
+    let path = dir.path().join("Bundles2/_.index.bin");
+    index
+        .write(&mut std::fs::File::create(path).unwrap())
+        .unwrap();
+
+    dir
+}
+
+fn index(c: &mut Criterion) {
+    let dir;
+    let path = match std::env::var_os("POBBIN_GAME") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            dir = synthetic();
+            dir.path().to_owned()
+        }
+    };
+
+    let bundle = pobbin_assets::Bundle::new(pobbin_assets::LocalBundleFs::new(path));
+
+    let (index, size) = retained(|| bundle.index().expect("index can not be parsed"));
+    let (baseline, baseline_size) = retained(|| Baseline::new(&index));
+    eprintln!(
+        "index with {} paths retains {:.1} MiB, files and paths of the baseline retain {:.1} MiB",
+        index.paths().count(),
+        mib(size),
+        mib(baseline_size),
+    );
+    drop(baseline);
+
+    let snapshots = tempfile::tempdir().unwrap();
+    let snapshot = snapshots.path().join("index");
+    index.save_snapshot(&snapshot).unwrap();
+    drop(index);
+
+    let fs = pobbin_assets::LocalBundleFs::new("");
+    let load = || pobbin_assets::IndexBundle::load_snapshot(&fs, &snapshot).unwrap();
+    let (index, size) = retained(load);
+    eprintln!("index loaded from a snapshot retains {:.1} MiB", mib(size));
+    drop(index);
+
+    c.bench_function("index", |b| b.iter(|| bundle.index().unwrap()));
+    c.bench_function("index_snapshot", |b| b.iter(load));
+}
+
+criterion_group!(benches, index);
+criterion_main!(benches);
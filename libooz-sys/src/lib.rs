@@ -2,6 +2,47 @@ use std::mem::MaybeUninit;
 
 extern "C" {
     fn Ooz_Decompress(src_buf: *const u8, src_len: u32, dst: *mut u8, dst_size: usize) -> i32;
+    fn Ooz_Compress(
+        codec: i32,
+        src_buf: *const u8,
+        src_len: usize,
+        dst_buf: *mut u8,
+        dst_capacity: usize,
+        level: i32,
+    ) -> i32;
+}
+
+/// Oodle compressors supported by [`compress`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Compressor {
+    Kraken = 8,
+    Mermaid = 9,
+    #[default]
+    Leviathan = 13,
+}
+
+/// Upper bound of the compressed size of `len` bytes.
+///
+/// Incompressible data grows by a few bytes for every 256 KiB block.
+pub fn compress_bound(len: usize) -> usize {
+    len + 274 * len.div_ceil(0x40000) + 64
+}
+
+/// Compresses `src` into `dst` and returns the compressed size or a negative error.
+///
+/// `dst` should be at least [`compress_bound`] bytes large.
+pub fn compress(compressor: Compressor, level: i32, src: &[u8], dst: &mut [u8]) -> i32 {
+    unsafe {
+        Ooz_Compress(
+            compressor as i32,
+            src.as_ptr(),
+            src.len(),
+            dst.as_mut_ptr(),
+            dst.len(),
+            level,
+        )
+    }
 }
 
 pub fn decompress(src: &[u8], dst: &mut [u8]) -> i32 {
@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use super::parse::Head;
+
+/// Hit and miss counters of the decompressed chunk cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkCacheStats {
+    /// Chunks served from the cache.
+    pub hits: u64,
+    /// Chunks which had to be decompressed.
+    pub misses: u64,
+    /// Chunks evicted to stay within the byte budget.
+    pub evictions: u64,
+    /// Bundle headers served from the cache.
+    pub head_hits: u64,
+    /// Bundle headers which had to be read.
+    pub head_misses: u64,
+    /// Total size of all currently cached chunks.
+    pub size: usize,
+}
+
+/// Byte-budgeted LRU cache of decompressed chunks and parsed bundle headers.
+pub(crate) struct ChunkCache {
+    max_size: usize,
+    inner: Mutex<Inner>,
+}
+
+/// Bundle name and chunk index.
+type ChunkKey = (String, usize);
+
+struct Entry {
+    data: Arc<[u8]>,
+    last_access: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    chunks: HashMap<ChunkKey, Entry>,
+    /// Chunk keys ordered by their last access.
+    lru: BTreeMap<u64, ChunkKey>,
+    heads: HashMap<String, Arc<Head>>,
+    tick: u64,
+    stats: ChunkCacheStats,
+}
+
+impl ChunkCache {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            inner: Default::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ChunkCacheStats {
+        self.lock().stats
+    }
+
+    pub fn head(&self, bundle: &str) -> Option<Arc<Head>> {
+        let mut inner = self.lock();
+
+        let head = inner.heads.get(bundle).cloned();
+        match head {
+            Some(_) => inner.stats.head_hits += 1,
+            None => inner.stats.head_misses += 1,
+        }
+        head
+    }
+
+    pub fn insert_head(&self, bundle: &str, head: Arc<Head>) {
+        self.lock().heads.insert(bundle.to_owned(), head);
+    }
+
+    pub fn contains(&self, bundle: &str, chunk: usize) -> bool {
+        self.lock().chunks.contains_key(&(bundle.to_owned(), chunk))
+    }
+
+    pub fn get(&self, bundle: &str, chunk: usize) -> Option<Arc<[u8]>> {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+
+        inner.tick += 1;
+        let key = (bundle.to_owned(), chunk);
+        let entry = inner.chunks.get_mut(&key)?;
+
+        if let Some(key) = inner.lru.remove(&entry.last_access) {
+            inner.lru.insert(inner.tick, key);
+        }
+        entry.last_access = inner.tick;
+
+        inner.stats.hits += 1;
+        tracing::trace!("chunk cache hit for chunk {chunk} of bundle '{bundle}'");
+
+        Some(Arc::clone(&entry.data))
+    }
+
+    pub fn insert(&self, bundle: &str, chunk: usize, data: &[u8]) {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+
+        inner.stats.misses += 1;
+        tracing::trace!("chunk cache miss for chunk {chunk} of bundle '{bundle}'");
+
+        if data.len() > self.max_size {
+            return;
+        }
+
+        while inner.stats.size + data.len() > self.max_size {
+            let Some((_, key)) = inner.lru.pop_first() else {
+                break;
+            };
+            if let Some(entry) = inner.chunks.remove(&key) {
+                inner.stats.size -= entry.data.len();
+                inner.stats.evictions += 1;
+            }
+        }
+
+        inner.tick += 1;
+        let key = (bundle.to_owned(), chunk);
+        inner.lru.insert(inner.tick, key.clone());
+
+        let entry = Entry {
+            data: data.into(),
+            last_access: inner.tick,
+        };
+        if let Some(old) = inner.chunks.insert(key, entry) {
+            inner.lru.remove(&old.last_access);
+            inner.stats.size -= old.data.len();
+        }
+        inner.stats.size += data.len();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // The cache is always left in a consistent state, a poisoned lock can be ignored.
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BundleWriter;
+
+    fn head() -> Arc<Head> {
+        let mut writer = BundleWriter::new();
+        writer.add("a", b"data");
+        let mut bundle = Vec::new();
+        writer.write(&mut bundle).unwrap();
+        Arc::new(Head::parse(&bundle).unwrap().1)
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let cache = ChunkCache::new(100);
+
+        assert_eq!(cache.get("a", 0), None);
+        cache.insert("a", 0, &[1; 10]);
+        assert!(cache.contains("a", 0));
+        assert!(!cache.contains("a", 1));
+        assert!(!cache.contains("b", 0));
+        assert_eq!(cache.get("a", 0).as_deref(), Some(&[1; 10][..]));
+        assert_eq!(cache.get("b", 0), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 1, 0));
+        assert_eq!(stats.size, 10);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_chunks() {
+        let cache = ChunkCache::new(30);
+        for chunk in 0..3 {
+            cache.insert("a", chunk, &[chunk as u8; 10]);
+        }
+        // Chunk 0 is now used more recently than chunk 1.
+        cache.get("a", 0).unwrap();
+
+        cache.insert("a", 3, &[3; 10]);
+        assert!(cache.contains("a", 0));
+        assert!(!cache.contains("a", 1));
+        assert!(cache.contains("a", 2));
+        assert!(cache.contains("a", 3));
+
+        cache.insert("b", 0, &[4; 25]);
+        assert_eq!(cache.stats().size, 25);
+        assert!(cache.contains("b", 0));
+
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 4);
+        assert!(stats.size <= 30);
+    }
+
+    #[test]
+    fn replacing_a_chunk_keeps_the_size() {
+        let cache = ChunkCache::new(30);
+        cache.insert("a", 0, &[0; 10]);
+        cache.insert("a", 0, &[1; 20]);
+
+        let stats = cache.stats();
+        assert_eq!((stats.size, stats.evictions), (20, 0));
+        assert_eq!(cache.get("a", 0).as_deref(), Some(&[1; 20][..]));
+    }
+
+    #[test]
+    fn skips_chunks_larger_than_the_budget() {
+        let cache = ChunkCache::new(10);
+        cache.insert("a", 0, &[0; 10]);
+        cache.insert("a", 1, &[1; 11]);
+
+        assert!(cache.contains("a", 0));
+        assert!(!cache.contains("a", 1));
+        assert_eq!(cache.stats().size, 10);
+    }
+
+    #[test]
+    fn caches_heads() {
+        let cache = ChunkCache::new(0);
+        let head = head();
+
+        assert!(cache.head("a").is_none());
+        cache.insert_head("a", Arc::clone(&head));
+        assert!(Arc::ptr_eq(&cache.head("a").unwrap(), &head));
+        assert!(cache.head("b").is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.head_hits, stats.head_misses), (1, 2));
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use sha2::{Digest, Sha256};
+
+use super::{BundleFs, BundleResult, IndexBundle};
+
+/// Differences between the files of two indexes, e.g. of two patch versions.
+///
+/// All files are reported by their path and sorted.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct IndexDiff {
+    /// Files which only exist in the new index.
+    pub added: Vec<String>,
+    /// Files which only exist in the old index.
+    pub removed: Vec<String>,
+    /// Files which were moved to a different path with unchanged contents, as `(from, to)`.
+    ///
+    /// Moved files are neither reported as added nor as removed.
+    pub moved: Vec<(String, String)>,
+    /// Files which are stored in a different bundle, their contents may be unchanged.
+    pub rebundled: Vec<String>,
+    /// Files which exist in both indexes but with different contents.
+    pub changed: Vec<String>,
+}
+
+impl IndexDiff {
+    /// Compares all files of two indexes.
+    ///
+    /// Contents of files which exist in both indexes are compared by their SHA-256,
+    /// this reads every file of both indexes, see [`IndexDiff::filtered`].
+    /// Added and removed files of the same size are compared as well to detect moves.
+    pub fn new<F, G>(from: &IndexBundle<F>, to: &IndexBundle<G>) -> BundleResult<Self>
+    where
+        F: BundleFs,
+        G: BundleFs,
+    {
+        Self::filtered(from, to, |_| true)
+    }
+
+    /// Compares all files of two indexes for which `filter` returns `true`.
+    pub fn filtered<F, G>(
+        from: &IndexBundle<F>,
+        to: &IndexBundle<G>,
+        filter: impl Fn(&str) -> bool,
+    ) -> BundleResult<Self>
+    where
+        F: BundleFs,
+        G: BundleFs,
+    {
+        let mut old = from
+            .iter()
+            .filter(|(path, _)| filter(path))
+            .collect::<HashMap<_, _>>();
+
+        let mut diff = Self::default();
+        let mut added = Vec::new();
+        let mut compare = Vec::new();
+        for (path, new) in to.iter().filter(|(path, _)| filter(path)) {
+            let Some(old) = old.remove(path) else {
+                added.push((path, new));
+                continue;
+            };
+
+            if from.bundle_name(&old) != to.bundle_name(&new) {
+                diff.rebundled.push(path.to_owned());
+            }
+
+            // Different sizes can be detected without reading the file.
+            match old.file_size == new.file_size {
+                true => compare.push(path),
+                false => diff.changed.push(path.to_owned()),
+            }
+        }
+
+        tracing::debug!("comparing the contents of {} files", compare.len());
+        let mut hashes = HashMap::with_capacity(compare.len());
+        for file in from.read_many(compare.iter().copied()) {
+            let (path, content) = file?;
+            hashes.insert(path, Sha256::digest(content));
+        }
+        for file in to.read_many(compare.iter().copied()) {
+            let (path, content) = file?;
+            if hashes.get(path) != Some(&Sha256::digest(content)) {
+                diff.changed.push(path.to_owned());
+            }
+        }
+
+        // Only removed and added files of the same size can be moves.
+        let removed_sizes = old.values().map(|f| f.file_size).collect::<HashSet<_>>();
+        let added_sizes = added
+            .iter()
+            .map(|(_, f)| f.file_size)
+            .collect::<HashSet<_>>();
+        let removed = old
+            .iter()
+            .filter(|(_, f)| added_sizes.contains(&f.file_size))
+            .map(|(path, _)| *path);
+        let candidates = added
+            .iter()
+            .filter(|(_, f)| removed_sizes.contains(&f.file_size))
+            .map(|(path, _)| *path);
+
+        // Removed and added paths by the hash of their contents.
+        let mut moves = BTreeMap::<_, (Vec<&str>, Vec<&str>)>::new();
+        for file in from.read_many(removed) {
+            let (path, content) = file?;
+            moves
+                .entry(Sha256::digest(content))
+                .or_default()
+                .0
+                .push(path);
+        }
+        for file in to.read_many(candidates) {
+            let (path, content) = file?;
+            moves
+                .entry(Sha256::digest(content))
+                .or_default()
+                .1
+                .push(path);
+        }
+
+        // Files with identical contents are paired in order of their paths.
+        let mut moved = HashSet::new();
+        for (mut removed, mut added) in moves.into_values() {
+            removed.sort_unstable();
+            added.sort_unstable();
+            for (from, to) in removed.into_iter().zip(added) {
+                moved.insert(from);
+                moved.insert(to);
+                diff.moved.push((from.to_owned(), to.to_owned()));
+            }
+        }
+
+        diff.added = added
+            .into_iter()
+            .filter(|(path, _)| !moved.contains(path))
+            .map(|(path, _)| path.to_owned())
+            .collect();
+        diff.removed = old
+            .into_keys()
+            .filter(|path| !moved.contains(path))
+            .map(|path| path.to_owned())
+            .collect();
+
+        diff.added.sort_unstable();
+        diff.removed.sort_unstable();
+        diff.moved.sort_unstable();
+        diff.rebundled.sort_unstable();
+        diff.changed.sort_unstable();
+
+        Ok(diff)
+    }
+
+    /// Whether both indexes contain the same files.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.moved.is_empty()
+            && self.rebundled.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::testutil::{contents, Fixture};
+    use crate::bundle::{Bundle, LocalBundleFs};
+
+    fn index(fixture: Fixture) -> (tempfile::TempDir, IndexBundle<LocalBundleFs>) {
+        let dir = fixture.tempdir();
+        let index = Bundle::new(LocalBundleFs::new(dir.path()))
+            .into_index()
+            .unwrap();
+        (dir, index)
+    }
+
+    fn strings<const N: usize>(paths: [&str; N]) -> Vec<String> {
+        paths.map(str::to_owned).to_vec()
+    }
+
+    #[test]
+    fn diff() {
+        let (same, moved, copy) = (contents(1, 100), contents(2, 100), contents(3, 100));
+        let (_from_dir, from) = index(
+            Fixture::new()
+                .bundle(
+                    "Data",
+                    &[
+                        ("Data/Same.dat", &same),
+                        ("Data/Changed.dat", &contents(4, 100)),
+                        ("Data/Resized.dat", &contents(5, 100)),
+                        ("Data/Old.dat", &moved),
+                        ("Data/Removed.dat", &contents(6, 100)),
+                        ("Data/CopyA.dat", &copy),
+                        ("Data/CopyB.dat", &copy),
+                    ],
+                )
+                .bundle("Art", &[("Art/Ring.dds", &same)]),
+        );
+        let (_to_dir, to) = index(
+            Fixture::new()
+                .bundle(
+                    "Data",
+                    &[
+                        ("Data/Same.dat", &same),
+                        ("Data/Changed.dat", &contents(7, 100)),
+                        ("Data/Resized.dat", &contents(5, 101)),
+                        ("Data/Sub/New.dat", &moved),
+                        ("Data/Added.dat", &contents(8, 100)),
+                        ("Data/CopyC.dat", &copy),
+                        ("Art/Ring.dds", &same),
+                    ],
+                )
+                .bundle("Art", &[]),
+        );
+
+        let diff = IndexDiff::new(&from, &to).unwrap();
+        assert_eq!(
+            diff,
+            IndexDiff {
+                added: strings(["Data/Added.dat"]),
+                removed: strings(["Data/CopyB.dat", "Data/Removed.dat"]),
+                moved: vec![
+                    ("Data/CopyA.dat".to_owned(), "Data/CopyC.dat".to_owned()),
+                    ("Data/Old.dat".to_owned(), "Data/Sub/New.dat".to_owned()),
+                ],
+                rebundled: strings(["Art/Ring.dds"]),
+                changed: strings(["Data/Changed.dat", "Data/Resized.dat"]),
+            }
+        );
+        assert!(!diff.is_empty());
+
+        let reverse = IndexDiff::new(&to, &from).unwrap();
+        assert_eq!(
+            reverse.moved,
+            [
+                ("Data/CopyC.dat".to_owned(), "Data/CopyA.dat".to_owned()),
+                ("Data/Sub/New.dat".to_owned(), "Data/Old.dat".to_owned()),
+            ]
+        );
+        assert_eq!(
+            reverse.added,
+            strings(["Data/CopyB.dat", "Data/Removed.dat"])
+        );
+        assert_eq!(reverse.removed, strings(["Data/Added.dat"]));
+
+        assert!(IndexDiff::new(&from, &from).unwrap().is_empty());
+    }
+
+    #[test]
+    fn filtered_diff() {
+        let (_from_dir, from) = index(Fixture::new().bundle(
+            "Data",
+            &[("Data/Old.dat", b"moved"), ("Art/Old.dds", b"art")],
+        ));
+        let (_to_dir, to) = index(Fixture::new().bundle(
+            "Data",
+            &[("Data/New.dat", b"moved"), ("Art/New.dds", b"art")],
+        ));
+
+        let diff = IndexDiff::filtered(&from, &to, |path| path.starts_with("Data/")).unwrap();
+        assert_eq!(
+            diff,
+            IndexDiff {
+                moved: vec![("Data/Old.dat".to_owned(), "Data/New.dat".to_owned())],
+                ..Default::default()
+            }
+        );
+
+        // A move out of the filter is reported as removed.
+        let diff = IndexDiff::filtered(&from, &to, |path| path.contains("Old")).unwrap();
+        assert_eq!(diff.removed, strings(["Art/Old.dds", "Data/Old.dat"]));
+        assert!(diff.moved.is_empty());
+    }
+}
@@ -1,6 +1,13 @@
-use std::io::{Cursor, Read, Seek};
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read, Seek, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 
-use dashmap::DashMap;
+use bytes::Bytes;
+use dashmap::{DashMap, DashSet};
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, thiserror::Error)]
 pub enum BundleFsError {
@@ -9,6 +16,23 @@ pub enum BundleFsError {
     #[cfg(feature = "web")]
     #[error(transparent)]
     Web(#[from] Box<ureq::Error>), // boxed because of clippy::result_large_err
+    #[cfg(feature = "web")]
+    #[error("request for '{url}' failed with status {status}")]
+    Status { url: String, status: u16 },
+    #[cfg(feature = "web")]
+    #[error("request for '{url}' timed out")]
+    Timeout { url: String },
+    #[cfg(feature = "web")]
+    #[error("response for '{url}' has no length, an incomplete download can not be detected")]
+    UnknownLength { url: String },
+    #[cfg(feature = "web")]
+    #[error("request for '{url}' failed after {attempts} attempts")]
+    RetriesExhausted {
+        url: String,
+        attempts: u32,
+        #[source]
+        source: Box<BundleFsError>,
+    },
     #[error(transparent)]
     Dyn(#[from] Box<dyn std::error::Error + Send + Sync + 'static>),
 }
@@ -18,6 +42,20 @@ enum FsRead {
     // If necessary, probably should add a Box<dyn Read + Seek>
     Boxed(Box<dyn std::io::Read + Send + Sync + 'static>),
     Cursor(Cursor<Vec<u8>>),
+    Mapped(Cursor<MappedRange>),
+    Shared(Cursor<Bytes>),
+}
+
+/// Range of a memory mapped file, the mapping is shared with all other readers of the file.
+struct MappedRange {
+    map: Arc<Mmap>,
+    range: Range<usize>,
+}
+
+impl AsRef<[u8]> for MappedRange {
+    fn as_ref(&self) -> &[u8] {
+        &self.map[self.range.clone()]
+    }
 }
 
 pub struct FileContents {
@@ -37,6 +75,12 @@ impl FileContents {
             FsRead::Cursor(ref mut cursor) => {
                 cursor.seek(std::io::SeekFrom::Current(n as i64))?;
             }
+            FsRead::Mapped(ref mut cursor) => {
+                cursor.seek(std::io::SeekFrom::Current(n as i64))?;
+            }
+            FsRead::Shared(ref mut cursor) => {
+                cursor.seek(std::io::SeekFrom::Current(n as i64))?;
+            }
             FsRead::Boxed(ref mut read) => {
                 std::io::copy(&mut read.take(n), &mut std::io::sink())?;
             }
@@ -44,6 +88,27 @@ impl FileContents {
 
         Ok(())
     }
+
+    /// Returns the remaining contents without copying them, if they are already in memory.
+    pub fn as_slice(&self) -> Option<&[u8]> {
+        fn remaining<T: AsRef<[u8]>>(cursor: &Cursor<T>) -> &[u8] {
+            let data = cursor.get_ref().as_ref();
+            data.get(cursor.position() as usize..).unwrap_or_default()
+        }
+
+        match &self.inner {
+            FsRead::Cursor(cursor) => Some(remaining(cursor)),
+            FsRead::Mapped(cursor) => Some(remaining(cursor)),
+            FsRead::Shared(cursor) => Some(remaining(cursor)),
+            FsRead::File(_) | FsRead::Boxed(_) => None,
+        }
+    }
+
+    fn mapped(map: Arc<Mmap>, range: Range<usize>) -> Self {
+        Self {
+            inner: FsRead::Mapped(Cursor::new(MappedRange { map, range })),
+        }
+    }
 }
 
 impl std::io::Read for FileContents {
@@ -52,6 +117,8 @@ impl std::io::Read for FileContents {
             FsRead::File(ref mut read) => read.read(buf),
             FsRead::Boxed(ref mut read) => read.read(buf),
             FsRead::Cursor(ref mut read) => read.read(buf),
+            FsRead::Mapped(ref mut read) => read.read(buf),
+            FsRead::Shared(ref mut read) => read.read(buf),
         }
     }
 }
@@ -80,162 +147,2054 @@ impl From<Vec<u8>> for FileContents {
     }
 }
 
+/// Reads from a shared buffer without copying it.
+impl From<Bytes> for FileContents {
+    fn from(data: Bytes) -> Self {
+        Self {
+            inner: FsRead::Shared(Cursor::new(data)),
+        }
+    }
+}
+
 pub trait BundleFs {
     fn get(&self, name: &str) -> Result<FileContents, BundleFsError>;
+
+    /// Reads only the specified byte range of a file.
+    ///
+    /// Returns `None` if the filesystem does not support ranged reads,
+    /// the entire file then needs to be read through [`BundleFs::get`].
+    fn get_range(
+        &self,
+        _name: &str,
+        _range: Range<u64>,
+    ) -> Result<Option<FileContents>, BundleFsError> {
+        Ok(None)
+    }
+
+    /// Identifies the contents of a file without reading it, e.g. by its size
+    /// and modification time. A changed file has a different fingerprint.
+    ///
+    /// Returns `None` if the filesystem can not fingerprint files.
+    fn fingerprint(&self, _name: &str) -> Result<Option<String>, BundleFsError> {
+        Ok(None)
+    }
 }
 
-impl<T: BundleFs> BundleFs for &T {
+impl<T: BundleFs + ?Sized> BundleFs for &T {
     fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
-        (*self).get(name)
+        (**self).get(name)
+    }
+
+    fn get_range(
+        &self,
+        name: &str,
+        range: Range<u64>,
+    ) -> Result<Option<FileContents>, BundleFsError> {
+        (**self).get_range(name, range)
+    }
+
+    fn fingerprint(&self, name: &str) -> Result<Option<String>, BundleFsError> {
+        (**self).fingerprint(name)
     }
 }
 
-impl BundleFs for &dyn BundleFs {
+impl<T: BundleFs + ?Sized> BundleFs for Box<T> {
     fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
-        (*self).get(name)
+        self.as_ref().get(name)
+    }
+
+    fn get_range(
+        &self,
+        name: &str,
+        range: Range<u64>,
+    ) -> Result<Option<FileContents>, BundleFsError> {
+        self.as_ref().get_range(name, range)
+    }
+
+    fn fingerprint(&self, name: &str) -> Result<Option<String>, BundleFsError> {
+        self.as_ref().fingerprint(name)
     }
 }
 
-impl BundleFs for Box<dyn BundleFs> {
+impl<T: BundleFs + ?Sized> BundleFs for Arc<T> {
     fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
         self.as_ref().get(name)
     }
+
+    fn get_range(
+        &self,
+        name: &str,
+        range: Range<u64>,
+    ) -> Result<Option<FileContents>, BundleFsError> {
+        self.as_ref().get_range(name, range)
+    }
+
+    fn fingerprint(&self, name: &str) -> Result<Option<String>, BundleFsError> {
+        self.as_ref().fingerprint(name)
+    }
 }
 
 #[derive(Debug)]
 pub struct LocalBundleFs {
     base: std::path::PathBuf,
+    /// Mapped files by name, if memory mapping is enabled.
+    maps: Option<DashMap<String, Arc<Mmap>>>,
 }
 
 impl LocalBundleFs {
     pub fn new(base: impl Into<std::path::PathBuf>) -> Self {
-        Self { base: base.into() }
+        Self {
+            base: base.into(),
+            maps: None,
+        }
+    }
+
+    /// Memory maps files instead of reading them.
+    ///
+    /// Every file is only mapped once, the mapping is shared between all readers
+    /// and compressed chunks are decompressed straight from the mapping.
+    /// Mapped files are kept mapped until the filesystem is dropped.
+    ///
+    /// Files must not be modified while they are mapped, e.g. by the game updating itself.
+    pub fn with_mmap(mut self) -> Self {
+        self.maps = Some(DashMap::new());
+        self
+    }
+
+    fn map(&self, maps: &DashMap<String, Arc<Mmap>>, name: &str) -> std::io::Result<Arc<Mmap>> {
+        if let Some(map) = maps.get(name) {
+            return Ok(Arc::clone(&map));
+        }
+
+        let map = maps.entry(name.to_owned()).or_try_insert_with(|| {
+            let file = std::fs::File::open(self.base.join(name))?;
+            // SAFETY: modifying mapped files is undefined behaviour,
+            // memory mapping is opt-in and documented to require unmodified files.
+            let map = unsafe { Mmap::map(&file)? };
+            Ok::<_, std::io::Error>(Arc::new(map))
+        })?;
+
+        Ok(Arc::clone(&map))
     }
 }
 
 impl BundleFs for LocalBundleFs {
     fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
+        if let Some(maps) = &self.maps {
+            let map = self.map(maps, name)?;
+            let len = map.len();
+            return Ok(FileContents::mapped(map, 0..len));
+        }
+
         Ok(std::fs::File::open(self.base.join(name))?.into())
     }
+
+    fn get_range(
+        &self,
+        name: &str,
+        range: Range<u64>,
+    ) -> Result<Option<FileContents>, BundleFsError> {
+        let Some(maps) = &self.maps else {
+            return Ok(None);
+        };
+
+        // Ranges past the end are truncated, like a short read of the file.
+        let map = self.map(maps, name)?;
+        let end = (range.end as usize).min(map.len());
+        let start = (range.start as usize).min(end);
+        Ok(Some(FileContents::mapped(map, start..end)))
+    }
+
+    fn fingerprint(&self, name: &str) -> Result<Option<String>, BundleFsError> {
+        let metadata = std::fs::metadata(self.base.join(name))?;
+        let Ok(modified) = metadata.modified() else {
+            return Ok(None);
+        };
+
+        let modified = modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Some(format!("{}-{}", metadata.len(), modified.as_nanos())))
+    }
 }
 
 pub trait Cache {
     fn get<F: BundleFs>(&self, name: &str, producer: F) -> Result<FileContents, BundleFsError>;
 }
 
+/// Cache of decompressed files, see [`super::IndexBundle::with_file_cache`].
+///
+/// Failures of the cache are not fatal, files are decompressed again.
+pub trait FileCache: Send + Sync {
+    /// Returns a cached file, files kept in memory are shared instead of copied.
+    fn get_file(&self, key: &str) -> Option<Bytes>;
+
+    fn insert_file(&self, key: &str, data: &[u8]);
+}
+
+impl<T: FileCache + ?Sized> FileCache for Arc<T> {
+    fn get_file(&self, key: &str) -> Option<Bytes> {
+        (**self).get_file(key)
+    }
+
+    fn insert_file(&self, key: &str, data: &[u8]) {
+        (**self).insert_file(key, data)
+    }
+}
+
+/// Hit and miss counters of an [`InMemoryCache`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InMemoryCacheStats {
+    /// Files served from the cache.
+    pub hits: u64,
+    /// Files which had to be fetched.
+    pub misses: u64,
+    /// Files evicted to stay within the byte budget.
+    pub evictions: u64,
+    /// Total size of all currently cached files.
+    pub size: usize,
+}
+
+/// Caches files in memory.
+///
+/// Cached files are shared, hits do not copy the file contents.
+/// With a byte budget, least recently used files are evicted first.
+///
+/// Concurrent misses of the same file only fetch the file once,
+/// all other callers wait for the running fetch.
+#[derive(Default)]
+pub struct InMemoryCache {
+    budget: Option<usize>,
+    files: DashMap<String, InMemoryCacheEntry>,
+    /// Running fetches by file name.
+    pending: DashMap<String, Arc<Flight>>,
+    /// Serializes inserts and evictions, hits never take this lock.
+    insert: Mutex<()>,
+    tick: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    size: AtomicUsize,
+}
+
+struct InMemoryCacheEntry {
+    data: Bytes,
+    last_access: AtomicU64,
+}
+
+/// A running fetch of a file.
 #[derive(Default)]
-pub struct InMemoryCache(DashMap<String, Vec<u8>>);
+struct Flight {
+    done: Mutex<bool>,
+    finished: Condvar,
+}
+
+impl Flight {
+    fn wait(&self) {
+        let mut done = self.done.lock().unwrap_or_else(|err| err.into_inner());
+        while !*done {
+            done = self
+                .finished
+                .wait(done)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+}
+
+/// Ends a fetch when dropped, whether it succeeded or not, and wakes up all waiting callers.
+struct FlightGuard<'a> {
+    cache: &'a InMemoryCache,
+    name: &'a str,
+    flight: Arc<Flight>,
+}
+
+impl Drop for FlightGuard<'_> {
+    fn drop(&mut self) {
+        self.cache.pending.remove(self.name);
+        *self
+            .flight
+            .done
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = true;
+        self.flight.finished.notify_all();
+    }
+}
+
+enum Fetch<'a> {
+    /// The caller fetches the file.
+    Leader(FlightGuard<'a>),
+    /// Another caller is already fetching the file.
+    Wait(Arc<Flight>),
+}
 
 impl InMemoryCache {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Limits the total size of all cached files to `budget` bytes.
+    ///
+    /// Files larger than the budget are never cached.
+    pub fn with_budget(mut self, budget: usize) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn stats(&self) -> InMemoryCacheStats {
+        InMemoryCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            size: self.size.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Bytes> {
+        let entry = self.files.get(name)?;
+        let tick = self.tick.fetch_add(1, Ordering::Relaxed) + 1;
+        entry.last_access.fetch_max(tick, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(entry.data.clone())
+    }
+
+    /// Starts fetching a file, unless it is already being fetched.
+    fn fetch<'a>(&'a self, name: &'a str) -> Fetch<'a> {
+        match self.pending.entry(name.to_owned()) {
+            dashmap::mapref::entry::Entry::Occupied(entry) => Fetch::Wait(Arc::clone(entry.get())),
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                let flight = Arc::clone(entry.insert(Arc::default()).value());
+                Fetch::Leader(FlightGuard {
+                    cache: self,
+                    name,
+                    flight,
+                })
+            }
+        }
+    }
+
+    fn insert(&self, name: &str, data: Bytes) {
+        let budget = self.budget.unwrap_or(usize::MAX);
+        if data.len() > budget {
+            return;
+        }
+
+        let _insert = self.insert.lock().unwrap_or_else(|err| err.into_inner());
+        let mut size = self.size.load(Ordering::Relaxed);
+
+        if let Some((_, old)) = self.files.remove(name) {
+            size -= old.data.len();
+        }
+
+        if size + data.len() > budget {
+            let mut lru = self
+                .files
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.last_access.load(Ordering::Relaxed),
+                        entry.key().clone(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            lru.sort_unstable();
+
+            for (_, name) in lru {
+                if size + data.len() <= budget {
+                    break;
+                }
+                if let Some((_, entry)) = self.files.remove(&name) {
+                    tracing::trace!("evicting '{name}' from in memory cache");
+                    size -= entry.data.len();
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        size += data.len();
+        let tick = self.tick.fetch_add(1, Ordering::Relaxed) + 1;
+        self.files.insert(
+            name.to_owned(),
+            InMemoryCacheEntry {
+                data,
+                last_access: AtomicU64::new(tick),
+            },
+        );
+        self.size.store(size, Ordering::Relaxed);
+    }
 }
 
 impl Cache for InMemoryCache {
     fn get<F: BundleFs>(&self, name: &str, producer: F) -> Result<FileContents, BundleFsError> {
-        if let Some(data) = self.0.get(name) {
-            return Ok(data.clone().into());
+        loop {
+            if let Some(data) = self.lookup(name) {
+                return Ok(data.into());
+            }
+
+            match self.fetch(name) {
+                // The file is fetched again if the running fetch fails.
+                Fetch::Wait(flight) => flight.wait(),
+                Fetch::Leader(_flight) => {
+                    // Another fetch may have finished since the lookup.
+                    if let Some(data) = self.lookup(name) {
+                        return Ok(data.into());
+                    }
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+
+                    let mut data = Vec::new();
+                    producer.get(name)?.read_to_end(&mut data)?;
+
+                    let data = Bytes::from(data);
+                    self.insert(name, data.clone());
+                    return Ok(data.into());
+                }
+            }
         }
+    }
+}
 
-        let data = self
-            .0
-            .entry(name.to_owned())
-            .or_try_insert_with(|| {
-                let mut data = Vec::new();
-                producer.get(name)?.read_to_end(&mut data)?;
-                Ok::<_, BundleFsError>(data)
-            })?
-            .clone();
+impl FileCache for InMemoryCache {
+    fn get_file(&self, key: &str) -> Option<Bytes> {
+        let data = self.lookup(key);
+        if data.is_none() {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        data
+    }
 
-        Ok(data.into())
+    fn insert_file(&self, key: &str, data: &[u8]) {
+        self.insert(key, Bytes::copy_from_slice(data));
     }
 }
 
-pub struct LocalCache(std::path::PathBuf);
+/// Caches files in a local directory.
+///
+/// Files are stored per namespace, usually the patch version, in `versions/<namespace>/<name>`.
+/// The contents are stored once by their SHA-256 in `objects/` and hardlinked into the namespaces,
+/// files which are identical across patches share their storage.
+///
+/// Every cached file has a sidecar manifest `<name>.manifest` with its size and SHA-256,
+/// files which do not match their manifest are discarded and fetched again.
+/// The modification time of the manifest is the last access of the file,
+/// it is updated at most once per hour.
+#[derive(Debug, Clone)]
+pub struct LocalCache {
+    base: std::path::PathBuf,
+    namespace: String,
+    budget: Option<u64>,
+    /// Paths of files which have already been verified, every file is only hashed once.
+    verified: Arc<DashSet<std::path::PathBuf>>,
+}
+
+/// Interval in which the access time of a cached file is updated.
+const ACCESS_RESOLUTION: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Manifest {
+    size: u64,
+    sha256: String,
+}
+
+/// Amount and total size of files in a [`LocalCache`].
+///
+/// Files shared between namespaces only count once towards the size.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LocalCacheStats {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// A cached file found on disk.
+struct CacheEntry {
+    path: std::path::PathBuf,
+    /// Shared contents of the file, if the file has a valid manifest.
+    object: Option<std::path::PathBuf>,
+    size: u64,
+    accessed: std::time::SystemTime,
+}
 
 impl LocalCache {
     pub fn new(base: impl Into<std::path::PathBuf>) -> Self {
-        Self(base.into())
+        Self {
+            base: base.into(),
+            namespace: "default".to_owned(),
+            budget: None,
+            verified: Default::default(),
+        }
     }
-}
 
-impl Cache for LocalCache {
-    fn get<F: BundleFs>(&self, name: &str, producer: F) -> Result<FileContents, BundleFsError> {
-        let path = self.0.join(name);
+    /// Stores files in a separate namespace, e.g. the patch version of the bundles.
+    ///
+    /// The namespace is used as a directory name.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// Limits the total size of all cached files to `budget` bytes.
+    ///
+    /// Least recently used files are evicted whenever a new file is cached.
+    pub fn with_budget(mut self, budget: u64) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn stats(&self) -> std::io::Result<LocalCacheStats> {
+        let (entries, _) = self.entries()?;
+        Ok(LocalCacheStats {
+            files: entries.len(),
+            bytes: unique_size(&entries),
+        })
+    }
+
+    /// Evicts least recently used files of all namespaces until all cached files
+    /// fit into `budget` bytes.
+    ///
+    /// Files without a manifest, e.g. from an interrupted write, bundles stored
+    /// directly in the cache directory by earlier versions and stale temporary files
+    /// are always removed. Other files in the cache directory are never touched.
+    /// Returns the amount and size of the removed files.
+    pub fn prune(&self, budget: u64) -> std::io::Result<LocalCacheStats> {
+        let (mut entries, orphans) = self.entries()?;
+        let mut removed = LocalCacheStats::default();
+
+        for path in orphans {
+            tracing::debug!("removing orphaned cache file {path:?}");
+            let size = std::fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            remove_file(&path)?;
+            removed.files += 1;
+            removed.bytes += size;
+        }
+
+        let mut links = HashMap::<_, usize>::new();
+        for object in entries.iter().filter_map(|entry| entry.object.clone()) {
+            *links.entry(object).or_default() += 1;
+        }
+
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.accessed));
+        let mut total = unique_size(&entries);
+        while total > budget {
+            let Some(entry) = entries.pop() else {
+                break;
+            };
+
+            tracing::debug!("evicting cache file {:?}", entry.path);
+            remove_file(&entry.path)?;
+            remove_file(&manifest_path(&entry.path))?;
+            removed.files += 1;
+
+            // Shared contents are only freed once the last file is evicted.
+            if let Some(object) = &entry.object {
+                let links = links.get_mut(object).expect("object is counted");
+                *links -= 1;
+                if *links > 0 {
+                    continue;
+                }
+                remove_file(object)?;
+            }
+
+            total -= entry.size;
+            removed.bytes += entry.size;
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes all patch versions except the latest `n`.
+    ///
+    /// Only namespaces which are version numbers, like `3.25.1.2`, are removed.
+    /// Returns the amount and size of the removed files.
+    pub fn retain_latest(&self, n: usize) -> std::io::Result<LocalCacheStats> {
+        let mut versions = Vec::new();
+        match std::fs::read_dir(self.base.join("versions")) {
+            Ok(dir) => {
+                for entry in dir {
+                    let entry = entry?;
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    let version = name
+                        .split('.')
+                        .map(|part| part.parse::<u64>().ok())
+                        .collect::<Option<Vec<_>>>();
+                    if let Some(version) = version {
+                        versions.push((version, entry.path()));
+                    }
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let before = self.stats()?;
+
+        versions.sort_by(|(a, _), (b, _)| b.cmp(a));
+        for (_, path) in versions.into_iter().skip(n) {
+            tracing::debug!("removing cached version {path:?}");
+            std::fs::remove_dir_all(path)?;
+        }
+
+        // Removes the contents which are no longer used by any version.
+        self.prune(u64::MAX)?;
+
+        let after = self.stats()?;
+        Ok(LocalCacheStats {
+            files: before.files.saturating_sub(after.files),
+            bytes: before.bytes.saturating_sub(after.bytes),
+        })
+    }
+
+    fn path(&self, name: &str) -> std::path::PathBuf {
+        self.base.join("versions").join(&self.namespace).join(name)
+    }
+
+    /// Path of the shared contents of a file, `None` if `sha256` is not a valid hash.
+    fn object_path(&self, sha256: &str) -> Option<std::path::PathBuf> {
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(self.base.join("objects").join(&sha256[..2]).join(sha256))
+    }
+
+    /// Opens a cached file, returns `None` if the file is not cached or does not match its manifest.
+    ///
+    /// With `verify` the SHA-256 of every file is verified once per process,
+    /// otherwise only the size is checked.
+    fn open(&self, name: &str, verify: bool) -> std::io::Result<Option<std::fs::File>> {
+        let path = self.path(name);
+        let manifest_path = manifest_path(&path);
+
+        let manifest: Manifest = match std::fs::read(&manifest_path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    return self.discard(name, &path, None, &format!("invalid manifest: {err}"))
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let size = file.metadata()?.len();
+        if size != manifest.size {
+            let reason = format!("expected {} bytes, found {size} bytes", manifest.size);
+            return self.discard(name, &path, Some(&manifest), &reason);
+        }
+
+        if verify && !self.verified.contains(&path) {
+            if sha256(&mut file)? != manifest.sha256 {
+                return self.discard(name, &path, Some(&manifest), "SHA-256 does not match");
+            }
+
+            file.rewind()?;
+            self.verified.insert(path);
+        }
+
+        if let Err(err) = touch(&manifest_path) {
+            tracing::warn!("failed to update access time of cached file '{name}': {err}");
+        }
+
+        Ok(Some(file))
+    }
+
+    /// Writes a file and its manifest to the cache.
+    fn insert(&self, name: &str, data: &mut impl Read) -> Result<std::fs::File, BundleFsError> {
+        let path = self.path(name);
 
-        if let Ok(file) = std::fs::File::open(&path) {
-            return Ok(file.into());
+        std::fs::create_dir_all(&self.base)?;
+        let mut tmp = tempfile::NamedTempFile::new_in(&self.base)?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = match data.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            hasher.update(&buf[..read]);
+            tmp.write_all(&buf[..read])?;
+            size += read as u64;
         }
 
-        let mut tmp = tempfile::NamedTempFile::new_in(&self.0)?;
-        let mut data = producer.get(name)?;
-        std::io::copy(&mut data, &mut tmp)?;
+        // The file has to be on disk before the manifest is written,
+        // otherwise a crash can leave a manifest for an incomplete file.
+        tmp.as_file().sync_all()?;
+
+        let manifest = Manifest {
+            size,
+            sha256: format!("{:x}", hasher.finalize()),
+        };
+        let object = self
+            .object_path(&manifest.sha256)
+            .expect("hash is formatted as hex");
 
+        if let Some(parent) = object.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
         if let Some(parent) = path.parent() {
             let _ = std::fs::create_dir_all(parent);
         }
 
-        let file = match tmp.persist(path) {
-            Ok(mut file) => {
-                file.seek(std::io::SeekFrom::Start(0))?;
+        // Files which are identical to a file of another version are already stored,
+        // objects are addressed by their contents and are not hashed again.
+        // A corrupted object is detected and removed the next time it is verified.
+        let stored = std::fs::metadata(&object).is_ok_and(|metadata| metadata.len() == size);
+        let file = match stored {
+            // The caller gets the data which was just written, not the stored object.
+            true => {
+                let mut file = tmp.into_file();
+                file.rewind()?;
                 file
             }
-            Err(err) => {
-                tracing::warn!("failed to rename tmp file {err:?}");
-                err.file.reopen()?
-            }
+            false => match tmp.persist(&object) {
+                Ok(mut file) => {
+                    file.rewind()?;
+                    file
+                }
+                Err(err) => {
+                    tracing::warn!("failed to rename tmp file {err:?}");
+                    return Ok(err.file.reopen()?);
+                }
+            },
         };
 
-        Ok(file.into())
-    }
-}
+        remove_file(&path)?;
+        if let Err(err) = std::fs::hard_link(&object, &path) {
+            tracing::warn!("failed to link cached file '{name}', copying it instead: {err}");
+            std::fs::copy(&object, &path)?;
+        }
 
-pub struct CacheBundleFs<F: BundleFs, C: Cache> {
-    inner: F,
-    cache: C,
-}
+        if let Err(err) = self.write_manifest(&path, &manifest) {
+            tracing::warn!("failed to write manifest of cached file '{name}': {err}");
+        }
+        if !stored {
+            self.verified.insert(path);
+        }
 
-impl<F: BundleFs, C: Cache> CacheBundleFs<F, C> {
-    pub fn new(inner: F, cache: C) -> Self {
-        Self { inner, cache }
+        if let Some(budget) = self.budget {
+            if let Err(err) = self.prune(budget) {
+                tracing::warn!("failed to evict cached files: {err}");
+            }
+        }
+
+        // Eviction may have removed the file itself, the handle stays valid.
+        Ok(file)
     }
-}
 
-impl<F: BundleFs, C: Cache> BundleFs for CacheBundleFs<F, C> {
-    fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
-        self.cache.get(name, &self.inner)
+    fn write_manifest(&self, path: &std::path::Path, manifest: &Manifest) -> std::io::Result<()> {
+        let mut tmp = tempfile::NamedTempFile::new_in(&self.base)?;
+        serde_json::to_writer(&mut tmp, manifest)?;
+        tmp.persist(manifest_path(path)).map_err(|err| err.error)?;
+        Ok(())
     }
-}
 
-#[cfg(feature = "web")]
-mod web {
-    use super::*;
+    /// Removes a corrupted file, the shared contents are removed as well if they are corrupted.
+    fn discard(
+        &self,
+        name: &str,
+        path: &std::path::Path,
+        manifest: Option<&Manifest>,
+        reason: &str,
+    ) -> std::io::Result<Option<std::fs::File>> {
+        tracing::warn!("discarding corrupted cached file '{name}': {reason}");
+        self.verified.remove(path);
+        remove_file(path)?;
+        remove_file(&manifest_path(path))?;
 
-    #[derive(Debug)]
-    pub struct WebBundleFs {
-        base: String,
+        // The shared contents may have been replaced already by another version.
+        if let Some(manifest) = manifest {
+            if let Some(object) = self.object_path(&manifest.sha256) {
+                let valid = std::fs::File::open(&object)
+                    .and_then(|mut object| sha256(&mut object))
+                    .is_ok_and(|hash| hash == manifest.sha256);
+                if !valid {
+                    remove_file(&object)?;
+                }
+            }
+        }
+
+        Ok(None)
     }
 
-    impl WebBundleFs {
-        pub fn new(base: impl Into<String>) -> Self {
-            Self { base: base.into() }
+    /// Finds all cached files and all files which are no longer used.
+    fn entries(&self) -> std::io::Result<(Vec<CacheEntry>, Vec<std::path::PathBuf>)> {
+        let mut entries = Vec::new();
+        let mut orphans = Vec::new();
+
+        for path in walk(&self.base.join("versions"))? {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+            if let Some(data) = file_name.strip_suffix(".manifest") {
+                if !path.with_file_name(data).is_file() {
+                    orphans.push(path);
+                }
+                continue;
+            }
+
+            let manifest_path = manifest_path(&path);
+            let Ok(accessed) = std::fs::metadata(&manifest_path).and_then(|m| m.modified()) else {
+                orphans.push(path);
+                continue;
+            };
+
+            let object = std::fs::read(&manifest_path)
+                .ok()
+                .and_then(|data| serde_json::from_slice::<Manifest>(&data).ok())
+                .and_then(|manifest| self.object_path(&manifest.sha256));
+
+            entries.push(CacheEntry {
+                size: std::fs::metadata(&path)?.len(),
+                path,
+                object,
+                accessed,
+            });
         }
 
-        pub fn cdn(version: &str) -> Self {
-            Self::new(format!("http://patchcdn.pathofexile.com/{version}/"))
+        let used = entries
+            .iter()
+            .filter_map(|entry| entry.object.as_deref())
+            .collect::<HashSet<_>>();
+        for path in walk(&self.base.join("objects"))? {
+            if !used.contains(path.as_path()) {
+                orphans.push(path);
+            }
+        }
+
+        // Bundles of the flat layout of earlier versions, the cache directory may be shared
+        // with other data, e.g. index snapshots, only files known to be cached are removed.
+        let legacy = walk(&self.base.join("Bundles2"))?;
+        orphans.extend(legacy.into_iter().filter(|path| is_legacy_file(path)));
+
+        // Temporary files of failed writes.
+        let dir = match std::fs::read_dir(&self.base) {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((entries, orphans))
+            }
+            Err(err) => return Err(err),
+        };
+        for entry in dir {
+            let entry = entry?;
+            if entry.file_type()?.is_file() && is_stale_tmp(&entry)? {
+                orphans.push(entry.path());
+            }
         }
+
+        Ok((entries, orphans))
     }
+}
 
-    impl BundleFs for WebBundleFs {
-        fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
-            tracing::info!(name, "requesting file from web fs: {name}");
-            let response = ureq::get(&format!("{}{name}", self.base))
-                .call()
-                .map_err(Box::new)?;
-            Ok(response.into_reader().into())
+impl Cache for LocalCache {
+    fn get<F: BundleFs>(&self, name: &str, producer: F) -> Result<FileContents, BundleFsError> {
+        match self.open(name, true) {
+            Ok(Some(file)) => return Ok(file.into()),
+            Ok(None) => {}
+            Err(err) => tracing::warn!("failed to open cached file '{name}': {err}"),
         }
+
+        let file = self.insert(name, &mut producer.get(name)?)?;
+        Ok(file.into())
+    }
+}
+
+impl FileCache for LocalCache {
+    fn get_file(&self, key: &str) -> Option<Bytes> {
+        // Decompressed files are only checked by their size, hits are not hashed.
+        let result = self.open(key, false).and_then(|file| {
+            let Some(mut file) = file else {
+                return Ok(None);
+            };
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            Ok(Some(data.into()))
+        });
+
+        result.unwrap_or_else(|err| {
+            tracing::warn!("failed to read cached file '{key}': {err}");
+            None
+        })
+    }
+
+    fn insert_file(&self, key: &str, mut data: &[u8]) {
+        if let Err(err) = self.insert(key, &mut data) {
+            tracing::warn!("failed to cache file '{key}': {err}");
+        }
+    }
+}
+
+fn sha256(reader: &mut impl Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn manifest_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut manifest = path.as_os_str().to_owned();
+    manifest.push(".manifest");
+    manifest.into()
+}
+
+/// Marks a cached file as used by updating the modification time of its manifest.
+///
+/// The time is only updated once per [`ACCESS_RESOLUTION`], hits usually do not write to the disk.
+fn touch(manifest_path: &std::path::Path) -> std::io::Result<()> {
+    let manifest = std::fs::File::options().write(true).open(manifest_path)?;
+    let now = std::time::SystemTime::now();
+    let accessed = manifest.metadata()?.modified()?;
+    if now.duration_since(accessed).unwrap_or_default() >= ACCESS_RESOLUTION {
+        manifest.set_modified(now)?;
+    }
+    Ok(())
+}
+
+/// Whether a file is a temporary file of [`LocalCache::insert`] which is no longer written to.
+fn is_stale_tmp(entry: &std::fs::DirEntry) -> std::io::Result<bool> {
+    const MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+    if !entry.file_name().to_string_lossy().starts_with(".tmp") {
+        return Ok(false);
+    }
+    let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+    Ok(age >= MAX_AGE)
+}
+
+/// Whether a file in `Bundles2/` was cached by earlier versions, which stored bundles
+/// and their manifests directly in the cache directory.
+fn is_legacy_file(path: &std::path::Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let name = name.strip_suffix(".manifest").unwrap_or(&name);
+    name.ends_with(".bundle.bin") || name == "_.index.bin"
+}
+
+/// Size of all entries, entries sharing their contents only count once.
+fn unique_size(entries: &[CacheEntry]) -> u64 {
+    let mut seen = HashSet::new();
+    entries
+        .iter()
+        .filter(|entry| {
+            entry
+                .object
+                .as_ref()
+                .is_none_or(|object| seen.insert(object))
+        })
+        .map(|entry| entry.size)
+        .sum()
+}
+
+/// Recursively lists all files in a directory, a missing directory is empty.
+fn walk(dir: &std::path::Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    fn walk(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                walk(&entry.path(), files)?;
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    match walk(dir, &mut files) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(files),
+        result => result.map(|()| files),
+    }
+}
+
+/// Removes a file, files which have already been removed are ignored.
+fn remove_file(path: &std::path::Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+pub struct CacheBundleFs<F, C> {
+    inner: F,
+    cache: C,
+}
+
+impl<F, C> CacheBundleFs<F, C> {
+    pub fn new(inner: F, cache: C) -> Self {
+        Self { inner, cache }
+    }
+}
+
+impl<F: BundleFs, C: Cache> BundleFs for CacheBundleFs<F, C> {
+    fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
+        self.cache.get(name, &self.inner)
+    }
+
+    fn fingerprint(&self, name: &str) -> Result<Option<String>, BundleFsError> {
+        self.inner.fingerprint(name)
+    }
+}
+
+#[cfg(feature = "web")]
+mod web {
+    use std::time::Duration;
+
+    use super::*;
+
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+    const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Filesystem which downloads files over HTTP.
+    ///
+    /// Failed requests are retried with an exponential backoff,
+    /// interrupted downloads are resumed from the last received byte with a `Range` request.
+    /// Responses need a `Content-Length`, `Content-Range` or chunked encoding to detect interruptions.
+    #[derive(Debug, Clone)]
+    pub struct WebBundleFs {
+        base: String,
+        agent: ureq::Agent,
+        retries: u32,
+        backoff: Duration,
+    }
+
+    impl WebBundleFs {
+        pub fn new(base: impl Into<String>) -> Self {
+            Self {
+                base: base.into(),
+                agent: agent(CONNECT_TIMEOUT, READ_TIMEOUT),
+                retries: 3,
+                backoff: Duration::from_millis(500),
+            }
+        }
+
+        pub fn cdn(version: &str) -> Self {
+            Self::new(format!("http://patchcdn.pathofexile.com/{version}/"))
+        }
+
+        /// Sets the timeout for establishing a connection and the timeout for every read.
+        pub fn with_timeouts(mut self, connect: Duration, read: Duration) -> Self {
+            self.agent = agent(connect, read);
+            self
+        }
+
+        /// Sets how often a download is retried before giving up, `0` disables retries.
+        pub fn with_retries(mut self, retries: u32) -> Self {
+            self.retries = retries;
+            self
+        }
+
+        /// Sets the delay before the first retry, the delay doubles with every retry.
+        pub fn with_backoff(mut self, backoff: Duration) -> Self {
+            self.backoff = backoff;
+            self
+        }
+
+        /// Starts downloading a file from `start` to `end` or to the end of the file.
+        fn download(
+            &self,
+            name: &str,
+            start: u64,
+            end: Option<u64>,
+        ) -> Result<Download, BundleFsError> {
+            let mut download = Download {
+                fs: self.clone(),
+                url: format!("{}{name}", self.base),
+                position: start,
+                end,
+                body: None,
+                failures: 0,
+            };
+            download.connect()?;
+            Ok(download)
+        }
+    }
+
+    impl BundleFs for WebBundleFs {
+        fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
+            tracing::info!(name, "requesting file from web fs: {name}");
+            let read: Box<dyn Read + Send + Sync> = Box::new(self.download(name, 0, None)?);
+            Ok(read.into())
+        }
+
+        fn get_range(
+            &self,
+            name: &str,
+            range: Range<u64>,
+        ) -> Result<Option<FileContents>, BundleFsError> {
+            if range.is_empty() {
+                return Ok(Some(Vec::new().into()));
+            }
+
+            tracing::info!(name, "requesting range {range:?} from web fs: {name}");
+            let read: Box<dyn Read + Send + Sync> =
+                Box::new(self.download(name, range.start, Some(range.end))?);
+            Ok(Some(read.into()))
+        }
+    }
+
+    fn agent(connect: Duration, read: Duration) -> ureq::Agent {
+        ureq::AgentBuilder::new()
+            .timeout_connect(connect)
+            .timeout_read(read)
+            .build()
+    }
+
+    /// A download which reconnects when the connection fails.
+    ///
+    /// Retries are shared between the initial request and all reconnects.
+    struct Download {
+        fs: WebBundleFs,
+        url: String,
+        /// Offset of the next byte in the file.
+        position: u64,
+        /// End of the requested range, if it is known.
+        end: Option<u64>,
+        body: Option<Box<dyn Read + Send + Sync>>,
+        failures: u32,
+    }
+
+    impl Download {
+        /// Requests the remaining bytes, failed requests are retried.
+        fn connect(&mut self) -> Result<(), BundleFsError> {
+            loop {
+                let err = match self.request() {
+                    Ok(body) => {
+                        self.body = Some(body);
+                        return Ok(());
+                    }
+                    Err(err) => err,
+                };
+
+                if !is_transient(&err) {
+                    return Err(err);
+                }
+                self.retry(err)?;
+            }
+        }
+
+        fn request(&mut self) -> Result<Box<dyn Read + Send + Sync>, BundleFsError> {
+            let mut request = self.fs.agent.get(&self.url);
+            let ranged = self.position > 0 || self.end.is_some();
+            if ranged {
+                let end = self.end.map(|end| (end - 1).to_string());
+                request = request.set(
+                    "Range",
+                    &format!("bytes={}-{}", self.position, end.unwrap_or_default()),
+                );
+            }
+
+            let response = request
+                .call()
+                .map_err(|err| request_error(&self.url, err))?;
+
+            let partial = response.status() == 206;
+            // End of the body in the file, partial responses state their range.
+            let len = response
+                .header("Content-Length")
+                .and_then(|len| len.parse::<u64>().ok());
+            let response_end = match partial {
+                true => response
+                    .header("Content-Range")
+                    .and_then(content_range_end)
+                    .or(len.map(|len| self.position + len)),
+                false => len,
+            };
+            // Chunked bodies are terminated explicitly, ureq fails on a premature close.
+            let chunked = response
+                .header("Transfer-Encoding")
+                .is_some_and(|encoding| encoding.contains("chunked"));
+            let mut body = response.into_reader();
+
+            if ranged && !partial {
+                // The server ignored the range and sends the entire file.
+                tracing::warn!(url = self.url, "web fs does not support range requests");
+                let skipped =
+                    std::io::copy(&mut body.by_ref().take(self.position), &mut std::io::sink())
+                        .map_err(|err| read_error(&self.url, err))?;
+                if skipped != self.position {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+            }
+
+            // Without a known end, a closed connection would look like the end of the file.
+            if self.end.is_none() {
+                self.end = response_end;
+            }
+            if self.end.is_none() && !chunked {
+                return Err(BundleFsError::UnknownLength {
+                    url: self.url.clone(),
+                });
+            }
+
+            match self.end {
+                Some(end) => Ok(Box::new(body.take(end - self.position))),
+                None => Ok(body),
+            }
+        }
+
+        /// Waits before the next attempt, fails if there are no retries left.
+        fn retry(&mut self, err: BundleFsError) -> Result<(), BundleFsError> {
+            if self.fs.retries == 0 {
+                return Err(err);
+            }
+            if self.failures >= self.fs.retries {
+                return Err(BundleFsError::RetriesExhausted {
+                    url: self.url.clone(),
+                    attempts: self.failures + 1,
+                    source: Box::new(err),
+                });
+            }
+
+            let delay = self
+                .fs
+                .backoff
+                .saturating_mul(2u32.saturating_pow(self.failures));
+            self.failures += 1;
+            tracing::warn!(
+                url = self.url,
+                "download failed at byte {}, retrying in {delay:?}: {err}",
+                self.position
+            );
+            std::thread::sleep(delay);
+
+            Ok(())
+        }
+    }
+
+    impl Read for Download {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            loop {
+                if buf.is_empty() || self.end == Some(self.position) {
+                    return Ok(0);
+                }
+
+                let Some(body) = self.body.as_mut() else {
+                    self.connect().map_err(into_io_error)?;
+                    continue;
+                };
+
+                let err = match body.read(buf) {
+                    // The connection was closed before the end of the range.
+                    Ok(0) if self.end.is_some() => std::io::ErrorKind::UnexpectedEof.into(),
+                    Ok(read) => {
+                        self.position += read as u64;
+                        return Ok(read);
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => err,
+                };
+
+                self.body = None;
+                self.retry(read_error(&self.url, err))
+                    .map_err(into_io_error)?;
+            }
+        }
+    }
+
+    /// Parses the exclusive end of a `Content-Range` header, e.g. `bytes 0-99/1000`.
+    fn content_range_end(range: &str) -> Option<u64> {
+        let (_, end) = range.strip_prefix("bytes ")?.split_once('-')?;
+        let (end, _) = end.split_once('/')?;
+        Some(end.parse::<u64>().ok()? + 1)
+    }
+
+    /// Whether a failed request is worth retrying.
+    fn is_transient(err: &BundleFsError) -> bool {
+        match err {
+            BundleFsError::Status { status, .. } => matches!(status, 408 | 429 | 500..),
+            BundleFsError::Timeout { .. } | BundleFsError::Io(_) => true,
+            // Transport errors, e.g. refused or reset connections.
+            BundleFsError::Web(err) => matches!(**err, ureq::Error::Transport(_)),
+            _ => false,
+        }
+    }
+
+    fn request_error(url: &str, err: ureq::Error) -> BundleFsError {
+        let timeout = std::error::Error::source(&err)
+            .and_then(|source| source.downcast_ref::<std::io::Error>())
+            .is_some_and(is_timeout);
+
+        match err {
+            ureq::Error::Status(status, _) => BundleFsError::Status {
+                url: url.to_owned(),
+                status,
+            },
+            _ if timeout => BundleFsError::Timeout {
+                url: url.to_owned(),
+            },
+            err => BundleFsError::Web(Box::new(err)),
+        }
+    }
+
+    fn read_error(url: &str, err: std::io::Error) -> BundleFsError {
+        match is_timeout(&err) {
+            true => BundleFsError::Timeout {
+                url: url.to_owned(),
+            },
+            false => BundleFsError::Io(err),
+        }
+    }
+
+    fn is_timeout(err: &std::io::Error) -> bool {
+        matches!(
+            err.kind(),
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+        )
+    }
+
+    /// Errors of readers have to be IO errors, the original error is kept as the source.
+    fn into_io_error(err: BundleFsError) -> std::io::Error {
+        match err {
+            BundleFsError::Io(err) => err,
+            err => std::io::Error::other(err),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::bundle::testutil::{contents, Fault, Fixture, Request, Server, CHUNK_SIZE};
+        use crate::bundle::{parse, Bundle};
+
+        const BUNDLE: &str = "Bundles2/a.bundle.bin";
+
+        fn fixture() -> Fixture {
+            Fixture::new().bundle(
+                "a",
+                &[
+                    ("Data/big.bin", &contents(1, CHUNK_SIZE * 10)),
+                    ("Data/small.bin", &contents(2, 100)),
+                ],
+            )
+        }
+
+        fn read(mut contents: FileContents) -> Vec<u8> {
+            let mut data = Vec::new();
+            contents.read_to_end(&mut data).unwrap();
+            data
+        }
+
+        #[test]
+        fn get_range() {
+            let files = fixture().files();
+            let bundle = files[BUNDLE].clone();
+
+            for server in [
+                Server::new(files.clone()),
+                Server::new(files).without_ranges(),
+            ] {
+                let fs = WebBundleFs::new(&server.url);
+
+                let data = BundleFs::get_range(&fs, BUNDLE, 100..5000)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(read(data), bundle[100..5000]);
+
+                let data = BundleFs::get_range(&fs, BUNDLE, 5000..5000)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(read(data), b"");
+
+                assert_eq!(read(BundleFs::get(&fs, BUNDLE).unwrap()), bundle);
+            }
+        }
+
+        #[test]
+        fn index_requests_only_header_and_required_chunks() {
+            let files = fixture().files();
+            let (_, head) = parse::Head::parse(&files[BUNDLE]).unwrap();
+            let server = Server::new(files);
+
+            let index = Bundle::new(WebBundleFs::new(&server.url))
+                .into_index()
+                .unwrap();
+            let data = index.read_by_name("Data/small.bin").unwrap().unwrap();
+            assert_eq!(data, contents(2, 100));
+
+            // The small file is entirely contained in the last chunk.
+            let chunk_sizes = &head.payload.chunk_sizes;
+            assert_eq!(chunk_sizes.len(), 11);
+            let start = head.size() as u64 + chunk_sizes[..10].iter().sum::<u32>() as u64;
+            let end = start + chunk_sizes[10] as u64;
+
+            let requests = server
+                .requests()
+                .into_iter()
+                .filter(|request| request.path == BUNDLE)
+                .collect::<Vec<_>>();
+            let expected = [
+                (0, Some(60)),
+                (60, Some(head.size() as u64)),
+                (start, Some(end)),
+            ]
+            .map(|range| Request {
+                path: BUNDLE.to_owned(),
+                range: Some(range),
+            });
+            assert_eq!(requests, expected);
+        }
+
+        /// Web filesystem which retries quickly.
+        fn web_fs(server: &Server) -> WebBundleFs {
+            WebBundleFs::new(&server.url)
+                .with_timeouts(Duration::from_secs(1), Duration::from_millis(200))
+                .with_retries(2)
+                .with_backoff(Duration::from_millis(1))
+        }
+
+        fn ranges(server: &Server) -> Vec<Option<(u64, Option<u64>)>> {
+            server
+                .requests()
+                .into_iter()
+                .map(|request| request.range)
+                .collect()
+        }
+
+        #[test]
+        fn resume_dropped_download() {
+            let files = fixture().files();
+            let bundle = files[BUNDLE].clone();
+            let server = Server::new(files);
+            let fs = web_fs(&server);
+
+            server.fault(Fault::Drop(1000));
+            server.fault(Fault::Drop(500));
+            assert_eq!(read(BundleFs::get(&fs, BUNDLE).unwrap()), bundle);
+
+            // The end is known from the length of the first response.
+            let end = Some(bundle.len() as u64);
+            assert_eq!(
+                ranges(&server),
+                [None, Some((1000, end)), Some((1500, end))]
+            );
+        }
+
+        #[test]
+        fn resume_dropped_range() {
+            let files = fixture().files();
+            let bundle = files[BUNDLE].clone();
+
+            for server in [
+                Server::new(files.clone()),
+                Server::new(files.clone()).without_content_length(),
+            ] {
+                let fs = web_fs(&server);
+
+                server.fault(Fault::Drop(100));
+                let data = BundleFs::get_range(&fs, BUNDLE, 100..5000)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(read(data), bundle[100..5000]);
+                assert_eq!(
+                    ranges(&server),
+                    [Some((100, Some(5000))), Some((200, Some(5000)))]
+                );
+            }
+        }
+
+        #[test]
+        fn reject_unknown_length() {
+            let files = fixture().files();
+            let server = Server::new(files).without_content_length();
+            let fs = web_fs(&server);
+
+            // The end of a dropped response could not be told apart from the end of the file.
+            server.fault(Fault::Drop(1000));
+            let Err(err) = BundleFs::get(&fs, BUNDLE) else {
+                panic!("response without a length was accepted");
+            };
+            assert!(matches!(err, BundleFsError::UnknownLength { .. }), "{err}");
+            assert_eq!(server.requests().len(), 1);
+        }
+
+        #[test]
+        fn retry_timeouts() {
+            let server = Server::new(fixture().files());
+            let fs = web_fs(&server);
+
+            for _ in 0..3 {
+                server.fault(Fault::Hang);
+            }
+            let Err(err) = BundleFs::get(&fs, BUNDLE) else {
+                panic!("hanging server did not time out");
+            };
+            let BundleFsError::RetriesExhausted {
+                attempts, source, ..
+            } = err
+            else {
+                panic!("unexpected error: {err}");
+            };
+            assert_eq!(attempts, 3);
+            assert!(matches!(*source, BundleFsError::Timeout { .. }), "{source}");
+        }
+
+        #[test]
+        fn retry_server_errors() {
+            let files = fixture().files();
+            let bundle = files[BUNDLE].clone();
+            let server = Server::new(files);
+            let fs = web_fs(&server);
+
+            server.fault(Fault::Status(503));
+            server.fault(Fault::Status(500));
+            assert_eq!(read(BundleFs::get(&fs, BUNDLE).unwrap()), bundle);
+            assert_eq!(server.requests().len(), 3);
+
+            // Missing files are not retried.
+            let Err(err) = BundleFs::get(&fs, "Bundles2/missing.bundle.bin") else {
+                panic!("missing file was found");
+            };
+            assert!(
+                matches!(err, BundleFsError::Status { status: 404, .. }),
+                "{err}"
+            );
+            assert_eq!(server.requests().len(), 4);
+        }
+
+        #[test]
+        fn index_without_range_support() {
+            let server = Server::new(fixture().files()).without_ranges();
+
+            let index = Bundle::new(WebBundleFs::new(&server.url))
+                .into_index()
+                .unwrap();
+            let data = index.read_by_name("Data/small.bin").unwrap().unwrap();
+            assert_eq!(data, contents(2, 100));
+            let data = index.read_by_name("Data/big.bin").unwrap().unwrap();
+            assert_eq!(data, contents(1, CHUNK_SIZE * 10));
+        }
+    }
+}
+#[cfg(feature = "web")]
+pub use web::*;
+
+/// Async filesystems, blocking work like requests of the web filesystem
+/// runs on the blocking thread pool of tokio.
+#[cfg(feature = "async")]
+mod nonblocking {
+    use std::future::Future;
+    use std::io::SeekFrom;
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    use super::*;
+
+    /// Async version of [`BundleFs`].
+    ///
+    /// Files are always read into memory entirely, cached files are shared without copying them.
+    pub trait AsyncBundleFs: Send + Sync {
+        fn get(&self, name: &str) -> impl Future<Output = Result<Bytes, BundleFsError>> + Send;
+
+        /// Reads only the specified byte range of a file, see [`BundleFs::get_range`].
+        fn get_range(
+            &self,
+            _name: &str,
+            _range: Range<u64>,
+        ) -> impl Future<Output = Result<Option<Bytes>, BundleFsError>> + Send {
+            async { Ok(None) }
+        }
+    }
+
+    impl<T: AsyncBundleFs> AsyncBundleFs for &T {
+        fn get(&self, name: &str) -> impl Future<Output = Result<Bytes, BundleFsError>> + Send {
+            (**self).get(name)
+        }
+
+        fn get_range(
+            &self,
+            name: &str,
+            range: Range<u64>,
+        ) -> impl Future<Output = Result<Option<Bytes>, BundleFsError>> + Send {
+            (**self).get_range(name, range)
+        }
+    }
+
+    impl<T: AsyncBundleFs> AsyncBundleFs for Arc<T> {
+        fn get(&self, name: &str) -> impl Future<Output = Result<Bytes, BundleFsError>> + Send {
+            self.as_ref().get(name)
+        }
+
+        fn get_range(
+            &self,
+            name: &str,
+            range: Range<u64>,
+        ) -> impl Future<Output = Result<Option<Bytes>, BundleFsError>> + Send {
+            self.as_ref().get_range(name, range)
+        }
+    }
+
+    impl AsyncBundleFs for LocalBundleFs {
+        async fn get(&self, name: &str) -> Result<Bytes, BundleFsError> {
+            Ok(tokio::fs::read(self.base.join(name)).await?.into())
+        }
+
+        async fn get_range(
+            &self,
+            name: &str,
+            range: Range<u64>,
+        ) -> Result<Option<Bytes>, BundleFsError> {
+            let mut file = tokio::fs::File::open(self.base.join(name)).await?;
+            file.seek(SeekFrom::Start(range.start)).await?;
+
+            let mut data = Vec::new();
+            file.take(range.end.saturating_sub(range.start))
+                .read_to_end(&mut data)
+                .await?;
+
+            Ok(Some(data.into()))
+        }
+    }
+
+    #[cfg(feature = "web")]
+    impl AsyncBundleFs for WebBundleFs {
+        async fn get(&self, name: &str) -> Result<Bytes, BundleFsError> {
+            let fs = self.clone();
+            let name = name.to_owned();
+            unblock(move || {
+                let mut data = Vec::new();
+                BundleFs::get(&fs, &name)?.read_to_end(&mut data)?;
+                Ok(data.into())
+            })
+            .await
+        }
+
+        async fn get_range(
+            &self,
+            name: &str,
+            range: Range<u64>,
+        ) -> Result<Option<Bytes>, BundleFsError> {
+            let fs = self.clone();
+            let name = name.to_owned();
+            unblock(move || {
+                let Some(mut contents) = BundleFs::get_range(&fs, &name, range)? else {
+                    return Ok(None);
+                };
+
+                let mut data = Vec::new();
+                contents.read_to_end(&mut data)?;
+                Ok(Some(data.into()))
+            })
+            .await
+        }
+    }
+
+    /// Async version of [`Cache`].
+    pub trait AsyncCache: Send + Sync {
+        fn get<F: AsyncBundleFs>(
+            &self,
+            name: &str,
+            producer: &F,
+        ) -> impl Future<Output = Result<Bytes, BundleFsError>> + Send;
+    }
+
+    impl AsyncCache for InMemoryCache {
+        async fn get<F: AsyncBundleFs>(
+            &self,
+            name: &str,
+            producer: &F,
+        ) -> Result<Bytes, BundleFsError> {
+            loop {
+                if let Some(data) = self.lookup(name) {
+                    return Ok(data);
+                }
+
+                match self.fetch(name) {
+                    Fetch::Wait(flight) => {
+                        unblock(move || {
+                            flight.wait();
+                            Ok::<_, BundleFsError>(())
+                        })
+                        .await?
+                    }
+                    Fetch::Leader(_flight) => {
+                        if let Some(data) = self.lookup(name) {
+                            return Ok(data);
+                        }
+                        self.misses.fetch_add(1, Ordering::Relaxed);
+
+                        let data = producer.get(name).await?;
+                        self.insert(name, data.clone());
+                        return Ok(data);
+                    }
+                }
+            }
+        }
+    }
+
+    impl AsyncCache for LocalCache {
+        async fn get<F: AsyncBundleFs>(
+            &self,
+            name: &str,
+            producer: &F,
+        ) -> Result<Bytes, BundleFsError> {
+            let cache = self.clone();
+            let owned = name.to_owned();
+            let cached = unblock(move || match cache.open(&owned, true) {
+                Ok(Some(mut file)) => {
+                    let mut data = Vec::new();
+                    file.read_to_end(&mut data)?;
+                    Ok::<_, BundleFsError>(Some(data.into()))
+                }
+                Ok(None) => Ok(None),
+                Err(err) => {
+                    tracing::warn!("failed to open cached file '{owned}': {err}");
+                    Ok(None)
+                }
+            })
+            .await?;
+
+            if let Some(data) = cached {
+                return Ok(data);
+            }
+
+            let data = producer.get(name).await?;
+
+            let cache = self.clone();
+            let name = name.to_owned();
+            unblock(move || {
+                cache.insert(&name, &mut data.as_ref())?;
+                Ok(data)
+            })
+            .await
+        }
+    }
+
+    impl<F: AsyncBundleFs, C: AsyncCache> AsyncBundleFs for CacheBundleFs<F, C> {
+        async fn get(&self, name: &str) -> Result<Bytes, BundleFsError> {
+            self.cache.get(name, &self.inner).await
+        }
+    }
+
+    /// Runs blocking work on the blocking thread pool of tokio.
+    ///
+    /// Panics of `f` are propagated to the caller.
+    pub(crate) async fn unblock<T, E>(
+        f: impl FnOnce() -> Result<T, E> + Send + 'static,
+    ) -> Result<T, E>
+    where
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        match tokio::task::spawn_blocking(f).await {
+            Ok(result) => result,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+}
+#[cfg(feature = "async")]
+pub use nonblocking::*;
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Filesystem which counts how often files are fetched.
+    #[derive(Default)]
+    struct Counting {
+        gets: AtomicUsize,
+        delay: Duration,
+    }
+
+    impl Counting {
+        fn gets(&self) -> usize {
+            self.gets.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Every file contains its name.
+    impl BundleFs for Counting {
+        fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
+            self.gets.fetch_add(1, Ordering::Relaxed);
+            std::thread::sleep(self.delay);
+            Ok(name.as_bytes().to_vec().into())
+        }
+    }
+
+    fn get(cache: &InMemoryCache, fs: &Counting, name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        Cache::get(cache, name, fs)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn in_memory_cache_evicts_least_recently_used() {
+        let cache = InMemoryCache::new().with_budget(12);
+        let fs = Counting::default();
+
+        get(&cache, &fs, "aaaa");
+        get(&cache, &fs, "bbbb");
+        get(&cache, &fs, "cccc");
+        // Accessing `a` makes `b` the least recently used file.
+        get(&cache, &fs, "aaaa");
+        get(&cache, &fs, "dddd");
+        assert_eq!(fs.gets(), 4);
+
+        get(&cache, &fs, "aaaa");
+        get(&cache, &fs, "cccc");
+        get(&cache, &fs, "dddd");
+        assert_eq!(fs.gets(), 4);
+        assert_eq!(get(&cache, &fs, "bbbb"), b"bbbb");
+        assert_eq!(fs.gets(), 5);
+
+        // `a` was used least recently when `b` was fetched again.
+        assert_eq!(
+            cache.stats(),
+            InMemoryCacheStats {
+                hits: 4,
+                misses: 5,
+                evictions: 2,
+                size: 12,
+            }
+        );
+        get(&cache, &fs, "aaaa");
+        assert_eq!(fs.gets(), 6);
+    }
+
+    #[test]
+    fn in_memory_cache_skips_files_larger_than_the_budget() {
+        let cache = InMemoryCache::new().with_budget(4);
+        let fs = Counting::default();
+
+        get(&cache, &fs, "abc");
+        assert_eq!(get(&cache, &fs, "too large"), b"too large");
+        get(&cache, &fs, "too large");
+        get(&cache, &fs, "abc");
+
+        assert_eq!(fs.gets(), 3);
+        assert_eq!(
+            cache.stats(),
+            InMemoryCacheStats {
+                hits: 1,
+                misses: 3,
+                evictions: 0,
+                size: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn in_memory_cache_fetches_concurrent_misses_once() {
+        let cache = InMemoryCache::new();
+        let fs = Counting {
+            delay: Duration::from_millis(50),
+            ..Default::default()
+        };
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| assert_eq!(get(&cache, &fs, "a"), b"a"));
+                scope.spawn(|| assert_eq!(get(&cache, &fs, "b"), b"b"));
+            }
+        });
+
+        assert_eq!(fs.gets(), 2);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (14, 2));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn in_memory_cache_shares_async_hits() {
+        struct Async(Counting);
+
+        impl AsyncBundleFs for Async {
+            async fn get(&self, name: &str) -> Result<Bytes, BundleFsError> {
+                let mut data = Vec::new();
+                BundleFs::get(&self.0, name)?.read_to_end(&mut data)?;
+                Ok(data.into())
+            }
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let cache = InMemoryCache::new();
+        let fs = Async(Counting::default());
+
+        let (a, b) = runtime.block_on(async {
+            let a = AsyncCache::get(&cache, "a", &fs).await.unwrap();
+            let b = AsyncCache::get(&cache, "a", &fs).await.unwrap();
+            (a, b)
+        });
+        assert_eq!(a, b"a"[..]);
+        assert_eq!(a.as_ptr(), b.as_ptr());
+        assert_eq!(fs.0.gets(), 1);
+    }
+
+    fn objects(base: &Path) -> Vec<std::path::PathBuf> {
+        walk(&base.join("objects")).unwrap()
+    }
+
+    /// Reads a cached file like a cached bundle, its SHA-256 is verified.
+    fn verified(cache: &LocalCache, name: &str) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        cache
+            .open(name, true)
+            .unwrap()?
+            .read_to_end(&mut data)
+            .unwrap();
+        Some(data)
+    }
+
+    #[test]
+    fn local_cache_shares_identical_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path());
+        let (a, b) = (
+            cache.clone().with_namespace("1"),
+            cache.clone().with_namespace("2"),
+        );
+
+        a.insert_file("x", b"same");
+        b.insert_file("x", b"same");
+        b.insert_file("y", b"other");
+
+        assert_eq!(a.get_file("x").as_deref(), Some(&b"same"[..]));
+        assert_eq!(b.get_file("x").as_deref(), Some(&b"same"[..]));
+        assert_eq!(objects(dir.path()).len(), 2);
+        assert_eq!(
+            cache.stats().unwrap(),
+            LocalCacheStats { files: 3, bytes: 9 }
+        );
+    }
+
+    #[test]
+    fn local_cache_replaces_corrupted_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path());
+
+        cache.clone().with_namespace("1").insert_file("x", b"data");
+        let [object] = &objects(dir.path())[..] else {
+            panic!("expected a single object");
+        };
+        // Corrupts the contents of the first version, the length is unchanged.
+        std::fs::write(object, b"xxxx").unwrap();
+
+        // Stored objects are trusted by their size, the insert links the corrupted object
+        // and the corruption is detected when the file is verified.
+        let b = cache.clone().with_namespace("2");
+        b.insert_file("x", b"data");
+        assert_eq!(verified(&b, "x"), None);
+        assert!(objects(dir.path()).is_empty());
+
+        b.insert_file("x", b"data");
+        assert_eq!(verified(&b, "x").as_deref(), Some(&b"data"[..]));
+
+        // The corrupted file of the first version is detected by a new process.
+        let a = LocalCache::new(dir.path()).with_namespace("1");
+        assert_eq!(verified(&a, "x"), None);
+    }
+
+    #[test]
+    fn local_cache_verifies_every_namespace() {
+        let dir = tempfile::tempdir().unwrap();
+        LocalCache::new(dir.path())
+            .with_namespace("2")
+            .insert_file("x", b"data");
+        let path = dir.path().join("versions/2/x");
+        // Breaks the link to the shared contents and corrupts the file.
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, b"xxxx").unwrap();
+
+        // Verifying the file of one namespace does not verify the file of another.
+        let cache = LocalCache::new(dir.path());
+        let a = cache.clone().with_namespace("1");
+        a.insert_file("x", b"data");
+        assert_eq!(verified(&a, "x").as_deref(), Some(&b"data"[..]));
+        let b = cache.with_namespace("2");
+        assert_eq!(verified(&b, "x"), None);
+    }
+
+    #[test]
+    fn local_cache_prunes_stray_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path());
+        cache.insert_file("Bundles2/a.bundle.bin", b"cached");
+
+        // Flat layout of earlier versions.
+        std::fs::create_dir_all(dir.path().join("Bundles2")).unwrap();
+        std::fs::write(dir.path().join("Bundles2/_.index.bin"), b"legacy").unwrap();
+        std::fs::write(dir.path().join("Bundles2/_.index.bin.manifest"), b"{}").unwrap();
+
+        // Unrelated files in the cache directory.
+        let unrelated = ["notes.txt", "snapshots/_.index", "Bundles2/readme.txt"];
+        for name in unrelated {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"unrelated").unwrap();
+        }
+
+        // Temporary files of a failed and a running write.
+        let stale = dir.path().join(".tmpStale");
+        let file = std::fs::File::create(&stale).unwrap();
+        let two_hours_ago =
+            std::time::SystemTime::now() - std::time::Duration::from_secs(2 * 60 * 60);
+        file.set_modified(two_hours_ago).unwrap();
+        let pending = dir.path().join(".tmpPending");
+        std::fs::write(&pending, b"pending").unwrap();
+
+        let removed = cache.prune(u64::MAX).unwrap();
+        assert_eq!(removed.files, 3);
+        assert!(!stale.exists());
+        assert!(pending.exists());
+        assert!(!dir.path().join("Bundles2/_.index.bin").exists());
+        assert!(unrelated.iter().all(|name| dir.path().join(name).exists()));
+        assert_eq!(
+            cache.get_file("Bundles2/a.bundle.bin").as_deref(),
+            Some(&b"cached"[..])
+        );
+    }
+
+    #[test]
+    fn local_cache_file_hits_check_the_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path());
+        cache.insert_file("x", b"data");
+        cache.insert_file("y", b"data");
+
+        // Decompressed files are not hashed, only their size is checked.
+        let path = dir.path().join("versions/default/x");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, b"xxxx").unwrap();
+        assert_eq!(cache.get_file("x").as_deref(), Some(&b"xxxx"[..]));
+
+        std::fs::write(&path, b"xxxxx").unwrap();
+        assert_eq!(cache.get_file("x"), None);
+        assert_eq!(cache.get_file("y").as_deref(), Some(&b"data"[..]));
+    }
+
+    #[test]
+    fn local_cache_updates_access_times_rarely() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path());
+        cache.insert_file("x", b"data");
+
+        let manifest = dir.path().join("versions/default/x.manifest");
+        let accessed = |ago: Duration| {
+            let time = std::time::SystemTime::now() - ago;
+            let file = std::fs::File::options()
+                .write(true)
+                .open(&manifest)
+                .unwrap();
+            file.set_modified(time).unwrap();
+            time
+        };
+        let modified = || std::fs::metadata(&manifest).unwrap().modified().unwrap();
+
+        let recently = accessed(Duration::from_secs(60));
+        cache.get_file("x").unwrap();
+        assert_eq!(modified(), recently);
+
+        let long_ago = accessed(ACCESS_RESOLUTION * 2);
+        cache.get_file("x").unwrap();
+        assert!(modified() > long_ago + ACCESS_RESOLUTION);
     }
 }
-#[cfg(feature = "web")]
-pub use web::*;
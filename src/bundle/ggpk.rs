@@ -0,0 +1,657 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use nom::bytes::streaming::take;
+use nom::multi::count;
+use nom::number::streaming::{le_i32, le_u32, le_u64};
+use nom::sequence::{tuple, Tuple};
+use nom::IResult;
+
+use super::parse::{nom_read, ReadErr};
+use super::{BundleFs, BundleFsError, FileContents};
+
+/// Header of every record, the length includes the header itself.
+struct RecordHeader {
+    length: u32,
+    tag: [u8; 4],
+}
+
+impl RecordHeader {
+    const SIZE: u64 = 8;
+
+    fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, (length, tag)) = (le_u32, take(4usize)).parse(input)?;
+        Ok((
+            input,
+            Self {
+                length,
+                tag: tag.try_into().unwrap(),
+            },
+        ))
+    }
+}
+
+/// The first record of an archive.
+///
+/// It references the root directory and the first free record, in no particular order.
+struct GgpkRecord {
+    version: u32,
+    offsets: [u64; 2],
+}
+
+impl GgpkRecord {
+    fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, (version, first, second)) = (le_u32, le_u64, le_u64).parse(input)?;
+        Ok((
+            input,
+            Self {
+                version,
+                offsets: [first, second],
+            },
+        ))
+    }
+}
+
+struct DirectoryRecord {
+    name: String,
+    entries: Vec<u64>,
+}
+
+impl DirectoryRecord {
+    fn parse(input: &[u8], version: u32) -> IResult<&[u8], Self> {
+        let (input, (name_length, entry_count, _hash)) =
+            (le_u32, le_u32, take(32usize)).parse(input)?;
+        let (input, name) = parse_name(input, name_length, version)?;
+
+        // Request all entries at once, see `HeadPayload::parse`.
+        let (input, entries) = take(entry_count as usize * 12)(input)?;
+        let (_, entries) = count(tuple((le_i32, le_u64)), entry_count as usize)(entries)?;
+
+        Ok((
+            input,
+            Self {
+                name,
+                entries: entries.into_iter().map(|(_, offset)| offset).collect(),
+            },
+        ))
+    }
+}
+
+struct FileRecord {
+    name: String,
+    /// Size of the record fields up to the start of the file contents.
+    size: u64,
+}
+
+impl FileRecord {
+    fn parse(input: &[u8], version: u32) -> IResult<&[u8], Self> {
+        let (input, (name_length, _hash)) = (le_u32, take(32usize)).parse(input)?;
+        let (input, name) = parse_name(input, name_length, version)?;
+
+        Ok((
+            input,
+            Self {
+                name,
+                size: 36 + name_length as u64 * char_size(version) as u64,
+            },
+        ))
+    }
+}
+
+/// Names are UTF-16, starting with version 4 UTF-32, and include a nul terminator.
+fn parse_name(input: &[u8], name_length: u32, version: u32) -> IResult<&[u8], String> {
+    let (input, data) = take(name_length as usize * char_size(version))(input)?;
+
+    let name = match version {
+        4 => data
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .map(|c| char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect::<String>(),
+        _ => char::decode_utf16(
+            data.chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]])),
+        )
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect::<String>(),
+    };
+
+    Ok((input, name.trim_end_matches('\0').to_owned()))
+}
+
+fn char_size(version: u32) -> usize {
+    match version {
+        4 => 4,
+        _ => 2,
+    }
+}
+
+/// Filesystem backed by a legacy `Content.ggpk` archive.
+///
+/// Bundles are read from the `Bundles2/` directory of the archive,
+/// loose files which are not bundled can be read directly by their path.
+#[derive(Debug)]
+pub struct GgpkBundleFs {
+    path: PathBuf,
+    /// Byte range of the contents of every file in the archive.
+    files: HashMap<String, Range<u64>>,
+}
+
+impl GgpkBundleFs {
+    /// Opens an archive and reads its entire directory tree.
+    ///
+    /// Records are only read within the bounds of the archive and every record
+    /// is visited at most once, a corrupt archive is rejected instead of being read forever.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, BundleFsError> {
+        let path = path.into();
+        let file = std::fs::File::open(&path)?;
+        let mut file = Records {
+            size: file.metadata()?.len(),
+            file: std::io::BufReader::new(file),
+        };
+
+        let header = file.header(0)?;
+        if &header.tag != b"GGPK" {
+            return Err(invalid_data("missing GGPK record").into());
+        }
+        let ggpk = file.body(0, &header, GgpkRecord::parse)?;
+        tracing::debug!("reading ggpk version {}", ggpk.version);
+
+        let root = ggpk
+            .offsets
+            .into_iter()
+            .find(|&offset| matches!(file.header(offset), Ok(header) if &header.tag == b"PDIR"))
+            .ok_or_else(|| invalid_data("missing root directory"))?;
+
+        let mut files = HashMap::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![(String::new(), root, true)];
+        while let Some((prefix, offset, is_root)) = pending.pop() {
+            if !visited.insert(offset) {
+                return Err(invalid_data(format!("record @ {offset} is referenced twice")).into());
+            }
+
+            let header = file.header(offset)?;
+            match &header.tag {
+                b"PDIR" => {
+                    let dir = file.body(offset, &header, |input| {
+                        DirectoryRecord::parse(input, ggpk.version)
+                    })?;
+
+                    // The root directory has no name.
+                    let prefix = match is_root {
+                        true => prefix,
+                        false => format!("{prefix}{}/", dir.name),
+                    };
+                    for entry in dir.entries {
+                        pending.push((prefix.clone(), entry, false));
+                    }
+                }
+                b"FILE" => {
+                    let record = file.body(offset, &header, |input| {
+                        FileRecord::parse(input, ggpk.version)
+                    })?;
+
+                    // The record was parsed within its length, the contents fit as well.
+                    let start = offset + RecordHeader::SIZE + record.size;
+                    let end = offset + header.length as u64;
+                    files.insert(format!("{prefix}{}", record.name), start..end);
+                }
+                b"FREE" => {}
+                tag => {
+                    let tag = String::from_utf8_lossy(tag);
+                    return Err(invalid_data(format!("unknown record '{tag}' @ {offset}")).into());
+                }
+            }
+        }
+
+        tracing::debug!("found {} files in ggpk '{}'", files.len(), path.display());
+
+        Ok(Self { path, files })
+    }
+
+    /// Returns the paths of all files in the archive, bundled and loose.
+    pub fn paths(&self) -> impl Iterator<Item = &str> + '_ {
+        self.files.keys().map(|path| path.as_str())
+    }
+
+    /// Whether the archive contains the file.
+    pub fn contains(&self, name: &str) -> bool {
+        self.files.contains_key(name)
+    }
+
+    /// Whether the archive contains bundles or only loose files.
+    pub fn has_bundles(&self) -> bool {
+        self.contains("Bundles2/_.index.bin")
+    }
+
+    /// Returns the byte range of the contents of a file in the archive.
+    fn contents(&self, name: &str) -> Result<&Range<u64>, BundleFsError> {
+        let Some(contents) = self.files.get(name) else {
+            let err = std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("file '{name}' does not exist in ggpk"),
+            );
+            return Err(err.into());
+        };
+        Ok(contents)
+    }
+
+    fn open_range(&self, name: &str, range: Range<u64>) -> Result<FileContents, BundleFsError> {
+        let contents = self.contents(name)?;
+
+        let start = contents.start.saturating_add(range.start).min(contents.end);
+        let end = contents.start.saturating_add(range.end).min(contents.end);
+
+        let mut file = std::fs::File::open(&self.path)?;
+        file.seek(SeekFrom::Start(start))?;
+
+        let read: Box<dyn Read + Send + Sync> = Box::new(file.take(end - start));
+        Ok(read.into())
+    }
+}
+
+impl BundleFs for GgpkBundleFs {
+    fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
+        self.open_range(name, 0..u64::MAX)
+    }
+
+    fn get_range(
+        &self,
+        name: &str,
+        range: Range<u64>,
+    ) -> Result<Option<FileContents>, BundleFsError> {
+        self.open_range(name, range).map(Some)
+    }
+
+    /// Files are identified by the size and modification time of the archive
+    /// and their location in the archive.
+    fn fingerprint(&self, name: &str) -> Result<Option<String>, BundleFsError> {
+        let contents = self.contents(name)?;
+
+        let metadata = std::fs::metadata(&self.path)?;
+        let Ok(modified) = metadata.modified() else {
+            return Ok(None);
+        };
+
+        let modified = modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Some(format!(
+            "{}-{}-{}-{}",
+            metadata.len(),
+            modified.as_nanos(),
+            contents.start,
+            contents.end
+        )))
+    }
+}
+
+/// Reads records of an archive, without reading past the end of a record or the archive.
+struct Records {
+    file: std::io::BufReader<std::fs::File>,
+    /// Size of the archive.
+    size: u64,
+}
+
+impl Records {
+    /// Reads the header of the record at `offset` and checks that the record is within the archive.
+    fn header(&mut self, offset: u64) -> Result<RecordHeader, BundleFsError> {
+        let header = self.read(offset, RecordHeader::SIZE, RecordHeader::parse)?;
+
+        let length = header.length as u64;
+        if length < RecordHeader::SIZE || offset.saturating_add(length) > self.size {
+            let msg = format!("record @ {offset} with length {length} exceeds the archive");
+            return Err(invalid_data(msg).into());
+        }
+
+        Ok(header)
+    }
+
+    /// Parses the fields following the header of the record at `offset`.
+    fn body<O>(
+        &mut self,
+        offset: u64,
+        header: &RecordHeader,
+        parser: impl FnMut(&[u8]) -> IResult<&[u8], O>,
+    ) -> Result<O, BundleFsError> {
+        let length = header.length as u64 - RecordHeader::SIZE;
+        self.read(offset + RecordHeader::SIZE, length, parser)
+    }
+
+    /// Parses at most `limit` bytes at `offset`.
+    fn read<O>(
+        &mut self,
+        offset: u64,
+        limit: u64,
+        parser: impl FnMut(&[u8]) -> IResult<&[u8], O>,
+    ) -> Result<O, BundleFsError> {
+        // Errors must not borrow from the read buffer.
+        fn owned_err<O>(
+            mut parser: impl FnMut(&[u8]) -> IResult<&[u8], O>,
+        ) -> impl FnMut(&[u8]) -> IResult<&[u8], O, nom::error::Error<()>> {
+            move |input| parser(input).map_err(|e| e.map_input(|_| ()))
+        }
+
+        if offset >= self.size {
+            return Err(
+                invalid_data(format!("record @ {offset} is outside of the archive")).into(),
+            );
+        }
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        let reader = (&mut self.file).take(limit);
+        nom_read(owned_err(parser), 0, reader).map_err(|err| match err {
+            ReadErr::Io(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                invalid_data(format!("truncated record @ {offset}")).into()
+            }
+            ReadErr::Io(err) => err.into(),
+            ReadErr::Parse(err) => invalid_data(format!("invalid record @ {offset}: {err}")).into(),
+        })
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Write;
+
+    use super::*;
+    use crate::bundle::testutil::{contents, Fixture};
+    use crate::bundle::Bundle;
+
+    /// Builds archives record by record.
+    struct Archive {
+        version: u32,
+        data: Vec<u8>,
+    }
+
+    impl Archive {
+        fn new(version: u32) -> Self {
+            let mut archive = Self {
+                version,
+                data: Vec::new(),
+            };
+            // Offsets of the root directory and the first free record are set by `finish`.
+            archive.record(b"GGPK", &[0; 20]);
+            archive
+        }
+
+        fn record(&mut self, tag: &[u8; 4], body: &[u8]) -> u64 {
+            let offset = self.data.len() as u64;
+            let length = RecordHeader::SIZE as usize + body.len();
+            self.data.extend((length as u32).to_le_bytes());
+            self.data.extend(tag);
+            self.data.extend(body);
+            offset
+        }
+
+        /// Encodes a name with a nul terminator, returns the length in characters.
+        fn name(&self, name: &str, body: &mut Vec<u8>) -> u32 {
+            let name = format!("{name}\0");
+            match self.version {
+                4 => {
+                    body.extend(name.chars().flat_map(|c| (c as u32).to_le_bytes()));
+                    name.chars().count() as u32
+                }
+                _ => {
+                    body.extend(name.encode_utf16().flat_map(u16::to_le_bytes));
+                    name.encode_utf16().count() as u32
+                }
+            }
+        }
+
+        fn file(&mut self, name: &str, contents: &[u8]) -> u64 {
+            let mut encoded = Vec::new();
+            let length = self.name(name, &mut encoded);
+
+            let mut body = Vec::new();
+            body.extend(length.to_le_bytes());
+            body.extend([0xAA; 32]);
+            body.extend(encoded);
+            body.extend(contents);
+            self.record(b"FILE", &body)
+        }
+
+        fn dir(&mut self, name: &str, entries: &[u64]) -> u64 {
+            let mut encoded = Vec::new();
+            let length = self.name(name, &mut encoded);
+
+            let mut body = Vec::new();
+            body.extend(length.to_le_bytes());
+            body.extend((entries.len() as u32).to_le_bytes());
+            body.extend([0xBB; 32]);
+            body.extend(encoded);
+            for (i, entry) in entries.iter().enumerate() {
+                body.extend((i as i32).to_le_bytes());
+                body.extend(entry.to_le_bytes());
+            }
+            self.record(b"PDIR", &body)
+        }
+
+        fn free(&mut self, size: usize) -> u64 {
+            let mut body = 0u64.to_le_bytes().to_vec();
+            body.resize(size, 0xCC);
+            self.record(b"FREE", &body)
+        }
+
+        /// Adds the directory tree of `files`, returns the offset of the directory.
+        fn tree(&mut self, name: &str, files: &[(&str, &[u8])]) -> u64 {
+            let mut dirs = BTreeMap::<&str, Vec<(&str, &[u8])>>::new();
+            let mut entries = Vec::new();
+            for &(path, data) in files {
+                match path.split_once('/') {
+                    Some((dir, rest)) => dirs.entry(dir).or_default().push((rest, data)),
+                    None => entries.push(self.file(path, data)),
+                }
+            }
+            for (dir, files) in dirs {
+                entries.push(self.tree(dir, &files));
+            }
+            self.dir(name, &entries)
+        }
+
+        fn finish(mut self, offsets: [u64; 2]) -> Vec<u8> {
+            self.data[8..12].copy_from_slice(&self.version.to_le_bytes());
+            self.data[12..20].copy_from_slice(&offsets[0].to_le_bytes());
+            self.data[20..28].copy_from_slice(&offsets[1].to_le_bytes());
+            self.data
+        }
+
+        fn write(self, offsets: [u64; 2]) -> tempfile::NamedTempFile {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(&self.finish(offsets)).unwrap();
+            file
+        }
+    }
+
+    fn read(fs: &GgpkBundleFs, name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        fs.get(name).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    fn paths(fs: &GgpkBundleFs) -> Vec<&str> {
+        let mut paths = fs.paths().collect::<Vec<_>>();
+        paths.sort_unstable();
+        paths
+    }
+
+    fn open_err(data: &[u8]) -> String {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(data).unwrap();
+        GgpkBundleFs::open(file.path()).unwrap_err().to_string()
+    }
+
+    /// An archive with a free record, nested directories and loose files.
+    fn sample(version: u32, root_first: bool) -> tempfile::NamedTempFile {
+        let mut archive = Archive::new(version);
+        let free = archive.free(100);
+        let root = archive.tree(
+            "",
+            &[
+                ("Data/Mods.dat", b"mods"),
+                ("Data/Sub/Deep.dat", b"deep"),
+                ("Empty.txt", b""),
+            ],
+        );
+        // The order of the root and free offsets is not fixed.
+        match root_first {
+            true => archive.write([root, free]),
+            false => archive.write([free, root]),
+        }
+    }
+
+    #[test]
+    fn reads_files_and_directories() {
+        for version in [2, 3, 4] {
+            for root_first in [true, false] {
+                let file = sample(version, root_first);
+                let fs = GgpkBundleFs::open(file.path()).unwrap();
+
+                assert_eq!(
+                    paths(&fs),
+                    ["Data/Mods.dat", "Data/Sub/Deep.dat", "Empty.txt"],
+                    "version {version}"
+                );
+                assert_eq!(read(&fs, "Data/Mods.dat"), b"mods");
+                assert_eq!(read(&fs, "Data/Sub/Deep.dat"), b"deep");
+                assert_eq!(read(&fs, "Empty.txt"), b"");
+                assert!(fs.contains("Data/Mods.dat"));
+                assert!(!fs.contains("Data/Sub"));
+                assert!(!fs.has_bundles());
+                assert!(fs.get("Data/Missing.dat").is_err());
+
+                let mut data = Vec::new();
+                let mut range = fs.get_range("Data/Sub/Deep.dat", 1..3).unwrap().unwrap();
+                range.read_to_end(&mut data).unwrap();
+                assert_eq!(data, b"ee");
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_names() {
+        let names = ["Ünïcödé.txt", "Crab🦀.txt", "Plain.txt"];
+        for version in [3, 4] {
+            let mut archive = Archive::new(version);
+            let files = names.map(|name| (name, name.as_bytes()));
+            let dir = archive.tree("Dïr🦀", &files);
+            let root = archive.dir("", &[dir]);
+            let free = archive.free(8);
+            let file = archive.write([root, free]);
+
+            let fs = GgpkBundleFs::open(file.path()).unwrap();
+            for name in names {
+                let path = format!("Dïr🦀/{name}");
+                assert_eq!(read(&fs, &path), name.as_bytes(), "version {version}");
+            }
+        }
+    }
+
+    #[test]
+    fn reads_bundles_and_loose_files() {
+        let bundled = contents(1, 10_000);
+        let fixture = Fixture::new().bundle("Data", &[("Data/Mods.dat64", &bundled)]);
+
+        let mut files = fixture.files().into_iter().collect::<Vec<_>>();
+        files.push(("Art/Loose.dds".to_owned(), b"loose".to_vec()));
+        let files = files
+            .iter()
+            .map(|(path, data)| (path.as_str(), data.as_slice()))
+            .collect::<Vec<_>>();
+
+        let mut archive = Archive::new(3);
+        let free = archive.free(16);
+        let root = archive.tree("", &files);
+        let file = archive.write([free, root]);
+
+        let fs = GgpkBundleFs::open(file.path()).unwrap();
+        assert!(fs.has_bundles());
+        assert_eq!(read(&fs, "Art/Loose.dds"), b"loose");
+
+        let index = Bundle::new(fs).into_index().unwrap();
+        assert_eq!(
+            index.read_by_name("Data/Mods.dat64").unwrap(),
+            Some(bundled)
+        );
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let mut archive = Archive::new(3);
+        let file = archive.file("a.txt", b"a");
+        // The directory contains itself, it is the next record.
+        let offset = archive.data.len() as u64;
+        let root = archive.dir("", &[file, offset]);
+        assert_eq!(root, offset);
+        let free = archive.free(8);
+        let data = archive.finish([root, free]);
+
+        assert!(open_err(&data).contains("referenced twice"));
+
+        // A file referenced by two directories.
+        let mut archive = Archive::new(3);
+        let file = archive.file("a.txt", b"a");
+        let dir = archive.dir("Dir", &[file]);
+        let root = archive.dir("", &[file, dir]);
+        let data = archive.finish([root, root]);
+        assert!(open_err(&data).contains("referenced twice"));
+    }
+
+    #[test]
+    fn rejects_records_outside_of_the_archive() {
+        let mut archive = Archive::new(3);
+        let file = archive.file("a.txt", b"a");
+        let root = archive.dir("", &[file, 1 << 40]);
+        let data = archive.finish([root, root]);
+        assert!(open_err(&data).contains("outside of the archive"));
+
+        // The length of the file record exceeds the archive.
+        let mut archive = Archive::new(3);
+        let file = archive.file("a.txt", b"a");
+        let root = archive.dir("", &[file]);
+        let mut data = archive.finish([root, root]);
+        let file = file as usize;
+        data[file..file + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(open_err(&data).contains("exceeds the archive"));
+
+        // The name of the file exceeds its record.
+        let mut archive = Archive::new(3);
+        let file = archive.file("a.txt", b"");
+        let root = archive.dir("", &[file]);
+        let mut data = archive.finish([root, root]);
+        let file = file as usize + 8;
+        data[file..file + 4].copy_from_slice(&100u32.to_le_bytes());
+        assert!(open_err(&data).contains("truncated record"));
+    }
+
+    #[test]
+    fn rejects_archives_without_root() {
+        let mut archive = Archive::new(3);
+        let free = archive.free(8);
+        let data = archive.finish([free, free]);
+        assert!(open_err(&data).contains("missing root directory"));
+
+        assert!(open_err(b"").contains("outside of the archive"));
+        assert!(open_err(b"\x08\0\0\0PDIR").contains("missing GGPK record"));
+    }
+
+    #[test]
+    fn rejects_truncated_archives() {
+        let file = sample(3, true);
+        let data = std::fs::read(file.path()).unwrap();
+
+        for len in 0..data.len() {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(&data[..len]).unwrap();
+            assert!(GgpkBundleFs::open(file.path()).is_err(), "{len}");
+        }
+    }
+}
@@ -1,6 +1,17 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
 
-use super::{ooz, parse, BundleFs};
+use sha2::{Digest, Sha256};
+
+use super::chunk_cache::ChunkCache;
+use super::snapshot::{Snapshot, SnapshotKey};
+use super::table::{Table, TableBuilder};
+use super::tree::Tree;
+use super::{ooz, parse, BundleFs, ChunkCacheStats, DirEntry, FileCache, FileContents};
+use crate::PathHasher;
 
 #[derive(Debug, thiserror::Error)]
 pub enum BundleError {
@@ -12,6 +23,35 @@ pub enum BundleError {
     Parse(nom::Err<nom::error::Error<()>>),
     #[error("failed to decompress file: {0}")]
     Decompress(i32),
+    #[cfg(feature = "pure-rust")]
+    #[error("failed to decompress file: {0}")]
+    Oodle(super::oodle::Error),
+    #[error("failed to compress file: {0}")]
+    Compress(i32),
+    #[error("'{name}' is truncated, expected {expected} bytes @ {offset}, got {actual}")]
+    Truncated {
+        name: String,
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("'{name}' is invalid @ {offset}: {reason}")]
+    Invalid {
+        name: String,
+        offset: usize,
+        reason: &'static str,
+    },
+    #[error("'{name}' has {actual} uncompressed bytes, expected {expected}")]
+    SizeMismatch {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("failed to parse dat file '{name}': {source}")]
+    Dat {
+        name: String,
+        source: crate::DatError,
+    },
 }
 
 impl<T> From<nom::Err<nom::error::Error<T>>> for BundleError {
@@ -20,72 +60,502 @@ impl<T> From<nom::Err<nom::error::Error<T>>> for BundleError {
     }
 }
 
+impl From<std::io::Error> for BundleError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ooz::CompressionError> for BundleError {
+    fn from(err: ooz::CompressionError) -> Self {
+        Self::Compress(err.0)
+    }
+}
+
 pub type BundleResult<T> = Result<T, BundleError>;
 
-pub struct Bundle<F: BundleFs> {
+const INDEX: &str = "Bundles2/_.index.bin";
+
+impl BundleError {
+    fn decompress(name: &str, err: ooz::DecompressionError) -> Self {
+        match err {
+            ooz::DecompressionError::Io(err) => Self::Io(err),
+            ooz::DecompressionError::Ooz(err) => Self::Decompress(err),
+            #[cfg(feature = "pure-rust")]
+            ooz::DecompressionError::Oodle(err) => Self::Oodle(err),
+            ooz::DecompressionError::Size {
+                offset,
+                expected,
+                actual,
+            } => Self::Truncated {
+                name: name.to_owned(),
+                offset,
+                expected,
+                actual,
+            },
+        }
+    }
+
+    /// Converts a parse error of `data` into an error with the offset of the failure.
+    ///
+    /// The parser input must be a sub slice of `data`.
+    pub(super) fn parse(name: &str, data: &[u8], err: nom::Err<nom::error::Error<&[u8]>>) -> Self {
+        match err {
+            nom::Err::Incomplete(needed) => Self::Truncated {
+                name: name.to_owned(),
+                offset: data.len(),
+                expected: match needed {
+                    nom::Needed::Size(len) => len.get(),
+                    nom::Needed::Unknown => 1,
+                },
+                actual: 0,
+            },
+            nom::Err::Error(err) | nom::Err::Failure(err) => Self::Invalid {
+                name: name.to_owned(),
+                offset: err.input.as_ptr() as usize - data.as_ptr() as usize,
+                reason: match err.code {
+                    nom::error::ErrorKind::MapRes => "invalid utf-8",
+                    nom::error::ErrorKind::Verify => "value out of range",
+                    _ => "unexpected data",
+                },
+            },
+        }
+    }
+}
+
+pub struct Bundle<F> {
     fs: F,
 }
 
-impl<F: BundleFs> Bundle<F> {
+impl<F> Bundle<F> {
     pub fn new(fs: F) -> Self {
         Self { fs }
     }
+}
 
+impl<F: BundleFs> Bundle<F> {
     pub fn index(&self) -> BundleResult<IndexBundle<&F>> {
-        let index_file = decompress(&self.fs, "Bundles2/_.index.bin", None)?;
+        let index_file = decompress(&self.fs, INDEX, None, None)?;
         IndexBundle::parse(&self.fs, index_file)
     }
+
+    /// Parses the index, the index takes ownership of the filesystem.
+    ///
+    /// Unlike [`Bundle::index`] the index does not borrow the bundle,
+    /// it can be shared between threads with an [`Arc`] if the filesystem is `Send + Sync`.
+    pub fn into_index(self) -> BundleResult<IndexBundle<F>> {
+        let index_file = decompress(&self.fs, INDEX, None, None)?;
+        IndexBundle::parse(self.fs, index_file)
+    }
+
+    /// Loads the index from a snapshot in `dir`, see [`IndexBundle::save_snapshot`].
+    ///
+    /// If there is no snapshot for `key` yet, the index is parsed
+    /// and a new snapshot is created.
+    pub fn index_snapshot(
+        &self,
+        dir: impl AsRef<Path>,
+        key: SnapshotKey,
+    ) -> BundleResult<IndexBundle<&F>> {
+        let hash_index = || -> BundleResult<_> {
+            let mut raw = Vec::new();
+            self.fs
+                .get(INDEX)
+                .map_err(BundleError::Fs)?
+                .read_to_end(&mut raw)?;
+            Ok((format!("{:x}", Sha256::digest(&raw)), Some(raw)))
+        };
+
+        let (name, raw) = match key {
+            SnapshotKey::Version(version) => (version, None),
+            SnapshotKey::IndexHash => hash_index()?,
+            SnapshotKey::Fingerprint => {
+                match self.fs.fingerprint(INDEX).map_err(BundleError::Fs)? {
+                    Some(fingerprint) => {
+                        let name = format!("fingerprint-{:x}", Sha256::digest(fingerprint));
+                        (name, None)
+                    }
+                    None => {
+                        tracing::debug!("index can not be fingerprinted, using its hash instead");
+                        hash_index()?
+                    }
+                }
+            }
+        };
+        let path = dir.as_ref().join(format!("{name}.index"));
+
+        if path.exists() {
+            match IndexBundle::load_snapshot(&self.fs, &path) {
+                Ok(index) => return Ok(index),
+                Err(err) => tracing::warn!("ignoring index snapshot '{}': {err}", path.display()),
+            }
+        }
+
+        let index = match raw {
+            Some(raw) => IndexBundle::parse(&self.fs, decompress_index(&raw)?)?,
+            None => self.index()?,
+        };
+        index.save_snapshot(&path)?;
+        tracing::debug!("saved index snapshot '{}'", path.display());
+
+        Ok(index)
+    }
+
+    /// Verifies the integrity of every bundle listed in the index.
+    ///
+    /// Every bundle is read entirely and all of its chunks are decompressed,
+    /// bundles are verified lazily while iterating.
+    pub fn verify(&self) -> BundleResult<impl Iterator<Item = BundleReport> + '_> {
+        let data = decompress(&self.fs, INDEX, None, None)?;
+        let (_, ib) = parse::IndexBundle::parse(&data)
+            .map_err(|err| BundleError::parse(INDEX, &data, err))?;
+
+        let mut bundles = ib
+            .bundles
+            .iter()
+            .map(|bundle| BundleReport {
+                name: bundle.name.to_owned(),
+                files: 0,
+                uncompressed_size: bundle.size,
+                problems: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut files = vec![Vec::new(); bundles.len()];
+        for file in &ib.files {
+            // Bundle indices are verified while parsing.
+            let bundle = file.bundle_index as usize;
+            bundles[bundle].files += 1;
+            files[bundle].push((file.file_offset as usize, file.file_size as usize));
+        }
+
+        tracing::debug!("verifying {} bundles", bundles.len());
+
+        Ok(bundles.into_iter().zip(files).map(|(mut report, files)| {
+            report.problems = self.verify_bundle(&report, &files);
+            report
+        }))
+    }
+
+    fn verify_bundle(&self, report: &BundleReport, files: &[(usize, usize)]) -> Vec<BundleError> {
+        let name = format!("Bundles2/{}.bundle.bin", report.name);
+        tracing::trace!("verifying bundle '{name}'");
+
+        let mut problems = Vec::new();
+
+        // Also verifies the chunk sizes of the header.
+        let mut reader = match BundleReader::open(&self.fs, &name, None) {
+            Ok(reader) => reader,
+            Err(err) => {
+                problems.push(err);
+                return problems;
+            }
+        };
+
+        if reader.uncompressed_size() != report.uncompressed_size {
+            problems.push(BundleError::SizeMismatch {
+                name: name.clone(),
+                expected: report.uncompressed_size,
+                actual: reader.uncompressed_size(),
+            });
+        }
+
+        for &(offset, size) in files {
+            if let Err(err) = reader.check_range(offset, size) {
+                problems.push(err);
+            }
+        }
+
+        // Decompress a few chunks at a time to limit the memory usage.
+        let chunk_count = reader.head.payload.chunk_count as usize;
+        for start in (0..chunk_count).step_by(VERIFY_CHUNKS) {
+            let end = (start + VERIFY_CHUNKS).min(chunk_count);
+            if let Err(err) = reader.decompress_uncached(start..end) {
+                problems.push(err);
+                break;
+            }
+        }
+
+        problems
+    }
 }
 
-pub struct IndexBundle<F: BundleFs> {
+/// Amount of chunks decompressed at once while verifying a bundle.
+const VERIFY_CHUNKS: usize = 16;
+
+/// Result of verifying a single bundle, see [`Bundle::verify`].
+#[derive(Debug)]
+pub struct BundleReport {
+    /// Name of the bundle as listed in the index.
+    pub name: String,
+    /// Amount of files stored in the bundle.
+    pub files: usize,
+    /// Uncompressed size of the bundle as listed in the index.
+    pub uncompressed_size: usize,
+    /// All problems found, the bundle is intact if there are none.
+    pub problems: Vec<BundleError>,
+}
+
+impl BundleReport {
+    /// Whether the bundle is intact.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Parsed index of all bundled files.
+///
+/// The index is `Send + Sync` if the filesystem is, all reads take `&self`
+/// and can be made concurrently from multiple threads.
+pub struct IndexBundle<F> {
     fs: F,
-    refs: HashMap<u64, FileRef>,
+    /// Files, paths and directories in a compact layout.
+    table: Table,
+    cache: Option<ChunkCache>,
+    file_cache: Option<Box<dyn FileCache>>,
 }
 
-impl<F: BundleFs> IndexBundle<F> {
+// Indexes over the filesystems and caches of this crate can be shared between threads.
+const _: fn() = || {
+    use super::{CacheBundleFs, InMemoryCache, LocalBundleFs, LocalCache};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<IndexBundle<CacheBundleFs<LocalBundleFs, InMemoryCache>>>();
+    assert_send_sync::<IndexBundle<CacheBundleFs<LocalBundleFs, LocalCache>>>();
+    assert_send_sync::<IndexBundle<Box<dyn BundleFs + Send + Sync>>>();
+    #[cfg(feature = "web")]
+    assert_send_sync::<IndexBundle<CacheBundleFs<super::WebBundleFs, InMemoryCache>>>();
+};
+
+impl<F> IndexBundle<F> {
     fn parse(fs: F, data: Vec<u8>) -> BundleResult<Self> {
         tracing::trace!("parsing index bundle");
-        let (_, ib) = parse::IndexBundle::parse(&data)?;
+        let (_, ib) = parse::IndexBundle::parse(&data)
+            .map_err(|err| BundleError::parse(INDEX, &data, err))?;
+        check_head(INDEX, &ib.head)?;
 
-        let mut refs = HashMap::new();
+        let mut table = TableBuilder::with_capacity(ib.files.len());
+        for bundle in &ib.bundles {
+            table.bundle(bundle.name);
+        }
+        for file in &ib.files {
+            let fref = FileRef {
+                bundle_index: file.bundle_index,
+                file_offset: file.file_offset,
+                file_size: file.file_size,
+            };
+            table.file(file.hash, fref);
+        }
 
-        for file in ib.files {
-            let bundle = &ib.bundles[file.bundle_index as usize];
+        tracing::trace!("parsed {} files from index bundle", ib.files.len());
 
-            refs.insert(
-                file.hash,
-                FileRef {
-                    bundle_name: bundle.name.to_owned(),
-                    file_offset: file.file_offset as usize,
-                    file_size: file.file_size as usize,
-                },
-            );
+        let path_data = ooz::decompress_slice(
+            ib.path_data,
+            ib.head.payload.chunk_unpacked_size as usize,
+            &ib.head.payload.chunk_sizes,
+            0,
+            ib.head.payload.uncompressed_size as usize,
+        )
+        .map_err(|err| BundleError::decompress(INDEX, err))?;
+
+        for rep in &ib.paths {
+            let start = rep.payload_offset as usize;
+            let end = start + rep.payload_size as usize;
+            let Some(payload) = path_data.get(start..end) else {
+                return Err(BundleError::Truncated {
+                    name: INDEX.to_owned(),
+                    offset: start,
+                    expected: rep.payload_size as usize,
+                    actual: path_data.len().saturating_sub(start),
+                });
+            };
+            let (_, rep_paths) = parse::parse_paths(payload)
+                .map_err(|err| BundleError::parse(INDEX, &path_data[..end], err))?;
+
+            let first = table.path_count();
+            for path in &rep_paths {
+                table.path(path);
+            }
+            table.dir(rep.hash, first..table.path_count());
+        }
+
+        tracing::trace!(
+            "reconstructed {} paths from index bundle",
+            table.path_count()
+        );
+
+        let table = table.finish().map_err(|err| err.error(INDEX))?;
+        Ok(Self::from_table(fs, table))
+    }
+
+    fn from_table(fs: F, table: Table) -> Self {
+        Self {
+            fs,
+            table,
+            cache: None,
+            file_cache: None,
+        }
+    }
+
+    /// Replaces the filesystem files are read from.
+    #[cfg(feature = "async")]
+    fn with_fs<G>(self, fs: G) -> IndexBundle<G> {
+        IndexBundle {
+            fs,
+            table: self.table,
+            cache: self.cache,
+            file_cache: self.file_cache,
+        }
+    }
+
+    /// Loads an index from a snapshot created with [`IndexBundle::save_snapshot`].
+    ///
+    /// Nothing has to be decompressed, the paths are stored already reconstructed.
+    /// The snapshot is memory mapped and validated once, lookups read it directly
+    /// without copying it.
+    pub fn load_snapshot(fs: F, path: impl AsRef<Path>) -> BundleResult<Self> {
+        let table = Snapshot::read(path.as_ref())?;
+        tracing::trace!("loaded {} files from index snapshot", table.files().len());
+
+        Ok(Self::from_table(fs, table))
+    }
+
+    /// Saves the parsed index to `path` in a compact binary format.
+    ///
+    /// An existing snapshot is replaced atomically.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> BundleResult<()> {
+        Snapshot::write(path.as_ref(), &self.table)
+    }
+
+    /// Enables a cache of decompressed chunks and parsed bundle headers.
+    ///
+    /// Decompressed chunks are evicted least recently used first
+    /// to keep the total size of cached chunks within `max_size` bytes.
+    ///
+    /// Async reads with `read_by_name_async` bypass the cache, cache the bundles
+    /// with a `CacheBundleFs` instead.
+    pub fn with_chunk_cache(mut self, max_size: usize) -> Self {
+        self.cache = Some(ChunkCache::new(max_size));
+        self
+    }
+
+    /// Returns the statistics of the chunk cache, if enabled.
+    pub fn chunk_cache_stats(&self) -> Option<ChunkCacheStats> {
+        self.cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Enables a cache of decompressed files, used by [`IndexBundle::read_by_name`]
+    /// and [`IndexBundle::read_many`].
+    ///
+    /// Files are cached by path and location in their bundle. Files which are changed
+    /// by a patch move to a different location, the cache does not have to be cleared
+    /// when switching between patches.
+    ///
+    /// Async reads with `read_by_name_async` bypass the cache, cache the bundles
+    /// with a `CacheBundleFs` instead.
+    pub fn with_file_cache(mut self, cache: impl FileCache + 'static) -> Self {
+        self.file_cache = Some(Box::new(cache));
+        self
+    }
+
+    /// Returns the hash algorithm used for paths of this index.
+    pub fn hasher(&self) -> PathHasher {
+        self.table.hasher()
+    }
+
+    /// Looks up a decompressed file in the file cache, if enabled.
+    ///
+    /// Files read from disk are not copied, files shared with an in memory cache are.
+    fn cached_file(&self, name: &str, fref: &FileRef) -> Option<Vec<u8>> {
+        let data = self
+            .file_cache
+            .as_ref()?
+            .get_file(&self.file_key(name, fref))?;
+        tracing::trace!("file cache hit for file '{name}'");
+        Some(data.into())
+    }
+
+    fn cache_file(&self, name: &str, fref: &FileRef, data: &[u8]) {
+        if let Some(cache) = &self.file_cache {
+            cache.insert_file(&self.file_key(name, fref), data);
         }
+    }
+
+    fn file_key(&self, name: &str, fref: &FileRef) -> String {
+        let bundle = self.bundle_name(fref).replace('/', "_");
+        format!("{name}@{bundle}-{}-{}", fref.file_offset, fref.file_size)
+    }
 
-        tracing::trace!("parsed {} files from index bundle", refs.len());
+    /// Returns all file paths contained in the index.
+    pub fn paths(&self) -> impl Iterator<Item = &str> + '_ {
+        self.table.paths().iter()
+    }
+
+    /// Returns the name of the bundle containing a file of this index.
+    pub fn bundle_name(&self, fref: &FileRef) -> &str {
+        self.table.bundles().get(fref.bundle_index as usize)
+    }
 
-        Ok(Self { fs, refs })
+    /// Returns all file paths contained in the index together with their location.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, FileRef)> + '_ {
+        self.paths().filter_map(|path| {
+            let fref = self.file_ref(path)?;
+            Some((path, fref))
+        })
     }
 
+    /// Looks up the location of a file.
+    fn file_ref(&self, name: &str) -> Option<FileRef> {
+        self.table.file(self.table.hasher().file(name))
+    }
+
+    /// Lists the files and sub directories directly contained in a directory.
+    ///
+    /// Returns `None` if the directory does not exist, the root directory is `""`.
+    pub fn list(&self, dir: &str) -> Option<impl Iterator<Item = DirEntry<'_>> + '_> {
+        Tree::new(&self.table).list(dir)
+    }
+
+    /// Finds all files matching a glob pattern, e.g. `Art/2DItems/**/*.dds`.
+    pub fn glob(&self, pattern: &str) -> Vec<&str> {
+        Tree::new(&self.table).glob(pattern)
+    }
+}
+
+impl<F: BundleFs> IndexBundle<F> {
     pub fn read<T: BundleFile>(&self) -> BundleResult<Option<T::Output>> {
-        self.read_by_name(T::NAME).map(|r| r.map(T::from))
+        let Some(data) = self.read_by_name(T::NAME)? else {
+            return Ok(None);
+        };
+
+        T::from(data).map(Some).map_err(|source| BundleError::Dat {
+            name: T::NAME.to_owned(),
+            source,
+        })
     }
 
     pub fn read_by_name(&self, name: &str) -> BundleResult<Option<Vec<u8>>> {
-        let hash = crate::filepath_hash(name);
-        let Some(fref) = self.refs.get(&hash) else {
+        let Some(fref) = self.file_ref(name) else {
             tracing::warn!("file '{name}' not found in index bundle");
             return Ok(None);
         };
 
-        let bundle_name = format!("Bundles2/{}.bundle.bin", fref.bundle_name);
+        let bundle_name = format!("Bundles2/{}.bundle.bin", self.bundle_name(&fref));
         tracing::trace!(
             "reading file '{name}' from bundle '{bundle_name}' @ {} ({} bytes)",
             fref.file_offset,
             fref.file_size
         );
 
-        let content = decompress(&self.fs, &bundle_name, Some(fref))?;
+        let content = match self.cached_file(name, &fref) {
+            Some(content) => content,
+            None => {
+                let content = decompress(&self.fs, &bundle_name, Some(&fref), self.cache.as_ref())?;
+                self.cache_file(name, &fref, &content);
+                content
+            }
+        };
 
         tracing::trace!(
             "successfully loaded file '{name}' from bundle '{bundle_name}' with {} bytes",
@@ -94,6 +564,127 @@ impl<F: BundleFs> IndexBundle<F> {
 
         Ok(Some(content))
     }
+
+    /// Opens a file for streaming, chunks are only decompressed once they are read.
+    pub fn open(&self, name: &str) -> BundleResult<Option<BundledFileReader<'_, F>>> {
+        let Some(fref) = self.file_ref(name) else {
+            tracing::warn!("file '{name}' not found in index bundle");
+            return Ok(None);
+        };
+
+        let bundle_name = format!("Bundles2/{}.bundle.bin", self.bundle_name(&fref));
+        tracing::trace!(
+            "opening file '{name}' from bundle '{bundle_name}' @ {} ({} bytes)",
+            fref.file_offset,
+            fref.file_size
+        );
+
+        let reader = BundleReader::open(&self.fs, &bundle_name, self.cache.as_ref())?;
+        let (file_offset, file_size) = (fref.file_offset as usize, fref.file_size as usize);
+        reader.check_range(file_offset, file_size)?;
+
+        Ok(Some(BundledFileReader {
+            reader,
+            file_offset,
+            file_size,
+            position: 0,
+            chunks: None,
+        }))
+    }
+
+    /// Reads multiple files at once.
+    ///
+    /// Files are grouped by bundle and every bundle is only opened once,
+    /// chunks shared between files are only decompressed once.
+    /// Files which do not exist are skipped.
+    ///
+    /// Results are not in the order of `names`. Files served by the file cache come first,
+    /// followed by the files of every bundle in order of the bundle names,
+    /// files of a bundle are ordered by their offset. A bundle which can not be read
+    /// yields a single error in place of its files.
+    pub fn read_many<'a, N>(
+        &'a self,
+        names: impl IntoIterator<Item = N>,
+    ) -> impl Iterator<Item = BundleResult<(N, Vec<u8>)>> + 'a
+    where
+        N: AsRef<str> + 'a,
+    {
+        let mut cached = Vec::new();
+        let mut bundles = BTreeMap::<&str, Vec<(N, FileRef)>>::new();
+        for name in names {
+            let Some(fref) = self.file_ref(name.as_ref()) else {
+                tracing::warn!("file '{}' not found in index bundle", name.as_ref());
+                continue;
+            };
+
+            if let Some(data) = self.cached_file(name.as_ref(), &fref) {
+                cached.push(Ok((name, data)));
+                continue;
+            }
+
+            bundles
+                .entry(self.bundle_name(&fref))
+                .or_default()
+                .push((name, fref));
+        }
+
+        let read = bundles.into_iter().flat_map(|(bundle_name, files)| {
+            match self.read_from_bundle(bundle_name, files) {
+                Ok(files) => files.into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err)],
+            }
+        });
+
+        cached.into_iter().chain(read)
+    }
+
+    fn read_from_bundle<N: AsRef<str>>(
+        &self,
+        bundle_name: &str,
+        mut files: Vec<(N, FileRef)>,
+    ) -> BundleResult<Vec<(N, Vec<u8>)>> {
+        let bundle_name = format!("Bundles2/{bundle_name}.bundle.bin");
+        tracing::trace!("reading {} files from bundle '{bundle_name}'", files.len());
+
+        let mut reader = BundleReader::open(&self.fs, &bundle_name, self.cache.as_ref())?;
+        for (_, fref) in &files {
+            reader.check_range(fref.file_offset as usize, fref.file_size as usize)?;
+        }
+
+        files.sort_by_key(|(_, fref)| fref.file_offset);
+        let mut files = files.into_iter().peekable();
+
+        let mut result = Vec::with_capacity(files.len());
+        while let Some(first) = files.next() {
+            // Group all following files which share at least one chunk.
+            let mut chunks =
+                reader.chunks(first.1.file_offset as usize, first.1.file_size as usize);
+            let mut group = vec![first];
+            while let Some(next) = files.next_if(|(_, fref)| {
+                reader
+                    .chunks(fref.file_offset as usize, fref.file_size as usize)
+                    .start
+                    < chunks.end
+            }) {
+                let next_chunks =
+                    reader.chunks(next.1.file_offset as usize, next.1.file_size as usize);
+                chunks.end = chunks.end.max(next_chunks.end);
+                group.push(next);
+            }
+
+            let decompress_start = chunks.start * reader.chunk_unpacked_size();
+            let content = reader.decompress(chunks)?;
+
+            for (name, fref) in group {
+                let start = fref.file_offset as usize - decompress_start;
+                let end = start + fref.file_size as usize;
+                self.cache_file(name.as_ref(), &fref, &content[start..end]);
+                result.push((name, content[start..end].to_vec()));
+            }
+        }
+
+        Ok(result)
+    }
 }
 
 pub trait BundleFile {
@@ -101,67 +692,1373 @@ pub trait BundleFile {
 
     type Output;
 
-    fn from(data: Vec<u8>) -> Self::Output;
+    fn from(data: Vec<u8>) -> Result<Self::Output, crate::DatError>;
 }
 
-#[derive(Debug)]
-struct FileRef {
-    bundle_name: String,
+/// Amount of chunks decompressed at once by a [`BundledFileReader`].
+const READ_AHEAD_CHUNKS: usize = 16;
+
+/// Streaming reader of a bundled file, see [`IndexBundle::open`].
+///
+/// Consecutive chunks are decompressed together, to limit the amount of requests.
+pub struct BundledFileReader<'a, F: BundleFs> {
+    reader: BundleReader<'a, F>,
     file_offset: usize,
     file_size: usize,
+    position: usize,
+    /// Uncompressed offset and contents of the last decompressed chunks.
+    chunks: Option<(usize, Vec<u8>)>,
 }
 
-fn decompress<F: BundleFs>(fs: &F, name: &str, fref: Option<&FileRef>) -> BundleResult<Vec<u8>> {
-    let mut file = fs.get(name).map_err(BundleError::Fs)?;
+impl<'a, F: BundleFs> BundledFileReader<'a, F> {
+    /// Total size of the file in bytes.
+    pub fn len(&self) -> usize {
+        self.file_size
+    }
 
-    let head = parse::Head::read(&mut file).map_err(|err| match err {
-        parse::ReadErr::Io(err) => BundleError::Io(err),
-        parse::ReadErr::Parse(err) => err.into(),
-    })?;
+    pub fn is_empty(&self) -> bool {
+        self.file_size == 0
+    }
+}
 
-    let chunk_unpacked_size = head.payload.chunk_unpacked_size as usize;
-    let uncompressed_size = head.payload.uncompressed_size as usize;
+impl<'a, F: BundleFs> Read for BundledFileReader<'a, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.file_size || buf.is_empty() {
+            return Ok(0);
+        }
 
-    let file_offset = fref.map(|fref| fref.file_offset).unwrap_or(0);
-    let file_size = fref.map(|fref| fref.file_size).unwrap_or(uncompressed_size);
+        let offset = self.file_offset + self.position;
 
-    // First chunk that includes a part of the targeted file.
-    let num_chunk_start = file_offset / chunk_unpacked_size;
-    // Last chunk that includes a part of the targeted file.
-    let num_chunk_end = div_ceil(file_offset + file_size, chunk_unpacked_size);
+        let (start, chunks) = match self.chunks {
+            Some((start, ref chunks)) if (start..start + chunks.len()).contains(&offset) => {
+                (start, chunks)
+            }
+            _ => {
+                // Decompress multiple chunks at once, every decompression may be a separate request.
+                let chunk_unpacked_size = self.reader.chunk_unpacked_size();
+                let index = offset / chunk_unpacked_size;
+                let end = self.reader.chunks(self.file_offset, self.file_size).end;
+                let chunks = self
+                    .reader
+                    .decompress(index..end.min(index + READ_AHEAD_CHUNKS))
+                    .map_err(std::io::Error::other)?;
+
+                let start = index * chunk_unpacked_size;
+                (start, &self.chunks.insert((start, chunks)).1)
+            }
+        };
+
+        let remaining = self.file_size - self.position;
+        let available = chunks.get(offset - start..).unwrap_or_default();
+        let n = buf.len().min(available.len()).min(remaining);
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+impl<'a, F: BundleFs> std::io::Seek for BundledFileReader<'a, F> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            std::io::SeekFrom::Start(n) => Some(n),
+            std::io::SeekFrom::End(n) => (self.file_size as u64).checked_add_signed(n),
+            std::io::SeekFrom::Current(n) => (self.position as u64).checked_add_signed(n),
+        };
 
-    let chunks_start: usize = head.payload.chunk_sizes[..num_chunk_start]
-        .iter()
-        .map(|&s| s as usize)
-        .sum();
+        let Some(position) = position else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ));
+        };
 
-    file.discard(chunks_start as u64).map_err(BundleError::Fs)?;
+        self.position = position as usize;
+        Ok(position)
+    }
+}
 
-    let mut content = ooz::decompress(
-        &mut file,
-        chunk_unpacked_size,
-        &head.payload.chunk_sizes[num_chunk_start..num_chunk_end],
-        num_chunk_start,
-        uncompressed_size,
+/// Location of a file inside of a bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileRef {
+    /// Index of the bundle in the index, see [`IndexBundle::bundle_name`].
+    pub bundle_index: u32,
+    pub file_offset: u32,
+    pub file_size: u32,
+}
+
+/// Decompresses an entire `_.index.bin` which has already been read into memory.
+fn decompress_index(raw: &[u8]) -> BundleResult<Vec<u8>> {
+    let (chunks, head) =
+        parse::Head::parse(raw).map_err(|err| BundleError::parse(INDEX, raw, err))?;
+    check_head(INDEX, &head)?;
+
+    ooz::decompress_slice(
+        chunks,
+        head.payload.chunk_unpacked_size as usize,
+        &head.payload.chunk_sizes,
+        0,
+        head.payload.uncompressed_size as usize,
     )
-    .map_err(|err| match err {
-        ooz::DecompressionError::Io(err) => BundleError::Io(err),
-        ooz::DecompressionError::Ooz(err) => BundleError::Decompress(err),
-    })?;
+    .map_err(|err| BundleError::decompress(INDEX, err))
+}
+
+/// Verifies that the chunks of a bundle header exactly cover the uncompressed size.
+fn check_head(name: &str, head: &parse::Head) -> BundleResult<()> {
+    let payload = &head.payload;
+
+    // Offsets are the positions of the invalid fields in the header.
+    if payload.chunk_unpacked_size == 0 {
+        return Err(BundleError::Invalid {
+            name: name.to_owned(),
+            offset: 40,
+            reason: "chunk size is zero",
+        });
+    }
+    let chunk_count = payload
+        .uncompressed_size
+        .div_ceil(payload.chunk_unpacked_size as u64);
+    if chunk_count != payload.chunk_count as u64 {
+        return Err(BundleError::Invalid {
+            name: name.to_owned(),
+            offset: 36,
+            reason: "chunk count does not match the uncompressed size",
+        });
+    }
+
+    // Chunk sizes determine how much is read, they have to be verified before allocating.
+    let max_chunk_size = libooz_sys::compress_bound(payload.chunk_unpacked_size as usize);
+    let mut compressed_size = 0u64;
+    for (i, &size) in payload.chunk_sizes.iter().enumerate() {
+        if size as usize > max_chunk_size {
+            return Err(BundleError::Invalid {
+                name: name.to_owned(),
+                offset: head.size() - (payload.chunk_sizes.len() - i) * 4,
+                reason: "chunk is larger than the maximum compressed size",
+            });
+        }
+        compressed_size += size as u64;
+    }
+    if compressed_size != payload.compressed_size {
+        return Err(BundleError::Invalid {
+            name: name.to_owned(),
+            offset: 28,
+            reason: "chunk sizes do not match the compressed size",
+        });
+    }
+
+    Ok(())
+}
+
+/// Reads and parses the header of a bundle using ranged reads.
+///
+/// Returns `None` if the filesystem does not support ranged reads.
+fn read_head_ranged<F: BundleFs>(fs: &F, name: &str) -> BundleResult<Option<parse::Head>> {
+    // The header is always at least 60 bytes, then the variable data starts.
+    // This means we're actually just doing two requests for the entire header.
+    let mut data = Vec::with_capacity(60);
+    let mut to_read = 60;
+
+    loop {
+        let start = data.len() as u64;
+        let Some(file) = fs
+            .get_range(name, start..start + to_read as u64)
+            .map_err(BundleError::Fs)?
+        else {
+            return Ok(None);
+        };
+
+        let read = file
+            .take(to_read as u64)
+            .read_to_end(&mut data)
+            .map_err(BundleError::Io)?;
+        if read == 0 {
+            return Err(BundleError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        match parse::Head::parse(&data) {
+            Ok((_, head)) => return Ok(Some(head)),
+            Err(nom::Err::Incomplete(nom::Needed::Size(len))) => to_read = len.into(),
+            Err(nom::Err::Incomplete(nom::Needed::Unknown)) => to_read = 1,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// An opened bundle which decompresses ranges of chunks on request.
+struct BundleReader<'a, F: BundleFs> {
+    fs: &'a F,
+    name: String,
+    head: Arc<parse::Head>,
+    cache: Option<&'a ChunkCache>,
+    /// The bundle stream together with the current offset after the header,
+    /// once it is known that the filesystem does not support ranged reads.
+    stream: Option<(FileContents, usize)>,
+}
+
+impl<'a, F: BundleFs> BundleReader<'a, F> {
+    fn open(fs: &'a F, name: &str, cache: Option<&'a ChunkCache>) -> BundleResult<Self> {
+        if let Some(head) = cache.and_then(|cache| cache.head(name)) {
+            return Ok(Self {
+                fs,
+                name: name.to_owned(),
+                head,
+                cache,
+                stream: None,
+            });
+        }
+
+        // Filesystems with support for ranged reads only fetch the header and the required chunks,
+        // otherwise the file is streamed and everything before the required chunks is discarded.
+        let (head, stream) = match read_head_ranged(fs, name)? {
+            Some(head) => (head, None),
+            None => {
+                let mut file = fs.get(name).map_err(BundleError::Fs)?;
+                let head = parse::Head::read(&mut file).map_err(|err| match err {
+                    parse::ReadErr::Io(err) => BundleError::Io(err),
+                    parse::ReadErr::Parse(err) => err.into(),
+                })?;
+                (head, Some((file, 0)))
+            }
+        };
+
+        check_head(name, &head)?;
+
+        let head = Arc::new(head);
+        if let Some(cache) = cache {
+            cache.insert_head(name, Arc::clone(&head));
+        }
+
+        Ok(Self {
+            fs,
+            name: name.to_owned(),
+            head,
+            cache,
+            stream,
+        })
+    }
+
+    fn chunk_unpacked_size(&self) -> usize {
+        self.head.payload.chunk_unpacked_size as usize
+    }
+
+    fn uncompressed_size(&self) -> usize {
+        self.head.payload.uncompressed_size as usize
+    }
+
+    fn check_range(&self, offset: usize, size: usize) -> BundleResult<()> {
+        check_range(&self.name, &self.head, offset, size)
+    }
+
+    fn chunks(&self, offset: usize, size: usize) -> Range<usize> {
+        chunk_range(&self.head, offset, size)
+    }
+
+    /// Decompresses a continuous range of chunks.
+    ///
+    /// Without support for ranged reads, chunks should be requested in ascending order,
+    /// going backwards requires reading the bundle from the start again.
+    fn decompress(&mut self, chunks: Range<usize>) -> BundleResult<Vec<u8>> {
+        let Some(cache) = self.cache else {
+            return self.decompress_uncached(chunks);
+        };
+
+        let chunk_unpacked_size = self.chunk_unpacked_size();
+
+        let mut content = Vec::with_capacity(chunks.len() * chunk_unpacked_size);
+        let mut chunk = chunks.start;
+        while chunk < chunks.end {
+            if let Some(data) = cache.get(&self.name, chunk) {
+                content.extend_from_slice(&data);
+                chunk += 1;
+                continue;
+            }
+
+            // Decompress all consecutive chunks which are not cached in one go.
+            let missing_end = (chunk + 1..chunks.end)
+                .find(|&c| cache.contains(&self.name, c))
+                .unwrap_or(chunks.end);
+
+            let data = self.decompress_uncached(chunk..missing_end)?;
+            for (i, data) in data.chunks(chunk_unpacked_size).enumerate() {
+                cache.insert(&self.name, chunk + i, data);
+            }
+
+            content.extend_from_slice(&data);
+            chunk = missing_end;
+        }
 
+        Ok(content)
+    }
+
+    fn decompress_uncached(&mut self, chunks: Range<usize>) -> BundleResult<Vec<u8>> {
+        if chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let chunk_sizes = &self.head.payload.chunk_sizes;
+
+        let chunks_start: usize = chunk_sizes[..chunks.start]
+            .iter()
+            .map(|&s| s as usize)
+            .sum();
+        let chunks_size: usize = chunk_sizes[chunks.clone()]
+            .iter()
+            .map(|&s| s as usize)
+            .sum();
+
+        // Streams can not go backwards, the bundle has to be opened again.
+        if matches!(self.stream, Some((_, position)) if position > chunks_start) {
+            self.stream = None;
+        }
+
+        let mut ranged = None;
+        if self.stream.is_none() {
+            let start = (self.head.size() + chunks_start) as u64;
+            ranged = self
+                .fs
+                .get_range(&self.name, start..start + chunks_size as u64)
+                .map_err(BundleError::Fs)?;
+
+            if ranged.is_none() {
+                let mut file = self.fs.get(&self.name).map_err(BundleError::Fs)?;
+                file.discard(self.head.size() as u64)
+                    .map_err(BundleError::Fs)?;
+                self.stream = Some((file, 0));
+            }
+        }
+
+        let file = match (ranged.as_mut(), self.stream.as_mut()) {
+            (Some(file), _) => file,
+            (None, Some((file, position))) => {
+                file.discard((chunks_start - *position) as u64)
+                    .map_err(BundleError::Fs)?;
+                *position = chunks_start + chunks_size;
+                file
+            }
+            (None, None) => unreachable!("stream is opened without support for ranged reads"),
+        };
+
+        let chunk_unpacked_size = self.head.payload.chunk_unpacked_size as usize;
+        let uncompressed_size = self.head.payload.uncompressed_size as usize;
+        let chunk_sizes = &chunk_sizes[chunks.clone()];
+
+        // Contents which are already in memory, e.g. memory mapped files, are not copied.
+        let content = match file.as_slice() {
+            Some(data) => {
+                let content = ooz::decompress_slice(
+                    data,
+                    chunk_unpacked_size,
+                    chunk_sizes,
+                    chunks.start,
+                    uncompressed_size,
+                );
+                file.discard(chunks_size as u64).map_err(BundleError::Fs)?;
+                content
+            }
+            None => ooz::decompress(
+                file,
+                chunk_unpacked_size,
+                chunk_sizes,
+                chunks.start,
+                uncompressed_size,
+            ),
+        }
+        .map_err(|err| BundleError::decompress(&self.name, err))?;
+
+        Ok(content)
+    }
+}
+
+fn decompress<F: BundleFs>(
+    fs: &F,
+    name: &str,
+    fref: Option<&FileRef>,
+    cache: Option<&ChunkCache>,
+) -> BundleResult<Vec<u8>> {
+    let mut reader = BundleReader::open(fs, name, cache)?;
+
+    let file_offset = fref.map(|fref| fref.file_offset as usize).unwrap_or(0);
+    let file_size = fref
+        .map(|fref| fref.file_size as usize)
+        .unwrap_or(reader.uncompressed_size());
+    reader.check_range(file_offset, file_size)?;
+
+    let chunks = reader.chunks(file_offset, file_size);
+    let decompress_start = chunks.start * reader.chunk_unpacked_size();
+    let mut content = reader.decompress(chunks)?;
+
+    truncate_to_file(&mut content, decompress_start, file_offset, file_size);
+    Ok(content)
+}
+
+/// Verifies that the uncompressed bytes `offset..offset + size` are contained in the bundle.
+fn check_range(name: &str, head: &parse::Head, offset: usize, size: usize) -> BundleResult<()> {
+    let uncompressed_size = head.payload.uncompressed_size as usize;
+    if offset.saturating_add(size) > uncompressed_size {
+        return Err(BundleError::Truncated {
+            name: name.to_owned(),
+            offset,
+            expected: size,
+            actual: uncompressed_size.saturating_sub(offset),
+        });
+    }
+    Ok(())
+}
+
+/// Range of chunks which contain the uncompressed bytes `offset..offset + size`.
+fn chunk_range(head: &parse::Head, offset: usize, size: usize) -> Range<usize> {
+    let chunk_unpacked_size = head.payload.chunk_unpacked_size as usize;
+
+    // First chunk that includes a part of the targeted file.
+    let num_chunk_start = offset / chunk_unpacked_size;
+    // Last chunk that includes a part of the targeted file.
+    let num_chunk_end = (offset + size).div_ceil(chunk_unpacked_size);
+
+    num_chunk_start..num_chunk_end
+}
+
+/// Cuts a file out of its decompressed chunks.
+///
+/// `decompress_start` is the uncompressed offset of the first decompressed chunk.
+fn truncate_to_file(
+    content: &mut Vec<u8>,
+    decompress_start: usize,
+    file_offset: usize,
+    file_size: usize,
+) {
     // If the file does not starts at the beginning of the buffer,
     // we have to move its contents to the start of the buffer first
     // and then truncate the buffer to the file size.
-    // The file offset is always 0 when `fref` is `None` (entire file decompressed).
-    if file_offset > 0 {
-        let file_decompress_start = file_offset - num_chunk_start * chunk_unpacked_size;
-        content.copy_within(file_decompress_start..file_decompress_start + file_size, 0);
+    let start = file_offset - decompress_start;
+    if start > 0 {
+        content.copy_within(start..start + file_size, 0);
     }
 
     content.truncate(file_size);
-    Ok(content)
 }
 
-fn div_ceil(a: usize, b: usize) -> usize {
-    (a + b - 1) / b
+/// Async counterparts of the index reads, see [`super::AsyncBundleFs`].
+#[cfg(feature = "async")]
+mod nonblocking {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::bundle::{unblock, AsyncBundleFs};
+
+    impl<F: AsyncBundleFs> Bundle<F> {
+        /// Async version of [`Bundle::index`].
+        ///
+        /// Decompressing and parsing the index runs on the blocking thread pool.
+        pub async fn index_async(&self) -> BundleResult<IndexBundle<&F>> {
+            Ok(parse_index(&self.fs).await?.with_fs(&self.fs))
+        }
+
+        /// Async version of [`Bundle::into_index`].
+        pub async fn into_index_async(self) -> BundleResult<IndexBundle<F>> {
+            Ok(parse_index(&self.fs).await?.with_fs(self.fs))
+        }
+    }
+
+    async fn parse_index<F: AsyncBundleFs>(fs: &F) -> BundleResult<IndexBundle<()>> {
+        let raw = fs.get(INDEX).await.map_err(BundleError::Fs)?;
+        unblock(move || IndexBundle::parse((), decompress_index(&raw)?)).await
+    }
+
+    impl<F: AsyncBundleFs> IndexBundle<F> {
+        /// Async version of [`IndexBundle::read_by_name`].
+        ///
+        /// Chunks are decompressed on the blocking thread pool.
+        ///
+        /// Neither the chunk cache of [`IndexBundle::with_chunk_cache`] nor the file cache
+        /// of [`IndexBundle::with_file_cache`] are used, every call reads and decompresses
+        /// the chunks of the file again. Wrap the filesystem in a
+        /// [`CacheBundleFs`](crate::CacheBundleFs) to avoid fetching bundles repeatedly.
+        pub async fn read_by_name_async(&self, name: &str) -> BundleResult<Option<Vec<u8>>> {
+            let Some(fref) = self.file_ref(name) else {
+                tracing::warn!("file '{name}' not found in index bundle");
+                return Ok(None);
+            };
+
+            let bundle_name = format!("Bundles2/{}.bundle.bin", self.bundle_name(&fref));
+            tracing::trace!(
+                "reading file '{name}' from bundle '{bundle_name}' @ {} ({} bytes)",
+                fref.file_offset,
+                fref.file_size
+            );
+
+            let (file_offset, file_size) = (fref.file_offset as usize, fref.file_size as usize);
+            let (head, chunks, data) =
+                read_chunks(&self.fs, &bundle_name, file_offset, file_size).await?;
+
+            let content = {
+                let bundle_name = bundle_name.clone();
+                unblock(move || {
+                    let chunk_unpacked_size = head.payload.chunk_unpacked_size as usize;
+                    let mut content = ooz::decompress_slice(
+                        &data,
+                        chunk_unpacked_size,
+                        &head.payload.chunk_sizes[chunks.clone()],
+                        chunks.start,
+                        head.payload.uncompressed_size as usize,
+                    )
+                    .map_err(|err| BundleError::decompress(&bundle_name, err))?;
+
+                    let decompress_start = chunks.start * chunk_unpacked_size;
+                    truncate_to_file(&mut content, decompress_start, file_offset, file_size);
+                    Ok::<_, BundleError>(content)
+                })
+                .await?
+            };
+
+            tracing::trace!(
+                "successfully loaded file '{name}' from bundle '{bundle_name}' with {} bytes",
+                content.len()
+            );
+
+            Ok(Some(content))
+        }
+    }
+
+    /// Reads the header of a bundle and the compressed chunks containing the uncompressed bytes
+    /// `offset..offset + size`.
+    ///
+    /// Without support for ranged reads the entire bundle is read.
+    async fn read_chunks<F: AsyncBundleFs>(
+        fs: &F,
+        name: &str,
+        offset: usize,
+        size: usize,
+    ) -> BundleResult<(parse::Head, Range<usize>, Bytes)> {
+        let (head, full) = match read_head_ranged(fs, name).await? {
+            Some(head) => (head, None),
+            None => {
+                let data = fs.get(name).await.map_err(BundleError::Fs)?;
+                let (_, head) = parse::Head::parse(&data)
+                    .map_err(|err| BundleError::parse(name, &data, err))?;
+                (head, Some(data))
+            }
+        };
+
+        check_head(name, &head)?;
+        check_range(name, &head, offset, size)?;
+        let chunks = chunk_range(&head, offset, size);
+
+        let chunk_sizes = &head.payload.chunk_sizes;
+        let start = head.size()
+            + chunk_sizes[..chunks.start]
+                .iter()
+                .map(|&s| s as usize)
+                .sum::<usize>();
+        let end = start
+            + chunk_sizes[chunks.clone()]
+                .iter()
+                .map(|&s| s as usize)
+                .sum::<usize>();
+
+        let ranged = match full {
+            Some(_) => None,
+            None => fs
+                .get_range(name, start as u64..end as u64)
+                .await
+                .map_err(BundleError::Fs)?,
+        };
+
+        let data = match (ranged, full) {
+            (Some(data), _) => data,
+            (None, full) => {
+                let full = match full {
+                    Some(full) => full,
+                    None => fs.get(name).await.map_err(BundleError::Fs)?,
+                };
+                // Truncated bundles are reported when decompressing.
+                full.slice(start.min(full.len())..end.min(full.len()))
+            }
+        };
+
+        Ok((head, chunks, data))
+    }
+
+    /// Async version of [`super::read_head_ranged`].
+    async fn read_head_ranged<F: AsyncBundleFs>(
+        fs: &F,
+        name: &str,
+    ) -> BundleResult<Option<parse::Head>> {
+        let mut data = Vec::with_capacity(60);
+        let mut to_read = 60;
+
+        loop {
+            let start = data.len() as u64;
+            let Some(file) = fs
+                .get_range(name, start..start + to_read as u64)
+                .await
+                .map_err(BundleError::Fs)?
+            else {
+                return Ok(None);
+            };
+
+            if file.is_empty() {
+                return Err(BundleError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            data.extend(file.into_iter().take(to_read));
+
+            match parse::Head::parse(&data) {
+                Ok((_, head)) => return Ok(Some(head)),
+                Err(nom::Err::Incomplete(nom::Needed::Size(len))) => to_read = len.into(),
+                Err(nom::Err::Incomplete(nom::Needed::Unknown)) => to_read = 1,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Seek, SeekFrom};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::bundle::testutil::{contents, Fixture, CHUNK_SIZE};
+    use crate::bundle::{
+        BundleFsError, CacheBundleFs, FileContents, InMemoryCache, LocalBundleFs, LocalCache,
+    };
+
+    /// Filesystem which records all ranged reads.
+    struct Recording<F> {
+        inner: F,
+        ranges: Mutex<Vec<(String, Range<u64>)>>,
+    }
+
+    impl<F> Recording<F> {
+        fn new(inner: F) -> Self {
+            Self {
+                inner,
+                ranges: Mutex::default(),
+            }
+        }
+
+        fn ranges(&self, name: &str) -> Vec<Range<u64>> {
+            let ranges = self.ranges.lock().unwrap();
+            ranges
+                .iter()
+                .filter(|(n, _)| n == name)
+                .map(|(_, range)| range.clone())
+                .collect()
+        }
+    }
+
+    impl<F: BundleFs> BundleFs for Recording<F> {
+        fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
+            self.inner.get(name)
+        }
+
+        fn get_range(
+            &self,
+            name: &str,
+            range: Range<u64>,
+        ) -> Result<Option<FileContents>, BundleFsError> {
+            let mut ranges = self.ranges.lock().unwrap();
+            ranges.push((name.to_owned(), range.clone()));
+            self.inner.get_range(name, range)
+        }
+    }
+
+    #[test]
+    fn open_decompresses_multiple_chunks_per_read() {
+        let big = contents(1, CHUNK_SIZE * 40 + 123);
+        let dir = Fixture::new()
+            .bundle("a", &[("Data/big.bin", &big)])
+            .tempdir();
+        let fs = Recording::new(LocalBundleFs::new(dir.path()).with_mmap());
+        let bundle = Bundle::new(&fs);
+        let index = bundle.index().unwrap();
+
+        let mut reader = index.open("Data/big.bin").unwrap().unwrap();
+        let mut data = Vec::new();
+        let mut buf = [0; 1000];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                n => data.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(data, big);
+
+        // Two requests for the header, then 41 chunks in batches of 16.
+        let ranges = fs.ranges("Bundles2/a.bundle.bin");
+        assert_eq!(ranges.len(), 2 + 3, "{ranges:?}");
+
+        reader.seek(SeekFrom::Start(100)).unwrap();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, big[100..]);
+    }
+
+    /// Creates an index with a single bundle `a` and returns the directory and raw bundle.
+    fn corpus() -> (tempfile::TempDir, Vec<u8>) {
+        let fixture = Fixture::new().bundle(
+            "a",
+            &[
+                ("Data/a.bin", &contents(1, CHUNK_SIZE * 2 + 10)),
+                ("Data/b.bin", &contents(2, 100)),
+            ],
+        );
+        let bundle = fixture.files().remove("Bundles2/a.bundle.bin").unwrap();
+        (fixture.tempdir(), bundle)
+    }
+
+    /// Decompresses the index in `dir`, lets `f` modify it and writes it back.
+    fn rewrite_index(dir: &Path, f: impl FnOnce(&mut [u8], usize, usize)) {
+        let path = dir.join(INDEX);
+        let mut data = decompress_index(&std::fs::read(&path).unwrap()).unwrap();
+
+        let (_, ib) = parse::IndexBundle::parse(&data).unwrap();
+        let files = 4
+            + ib.bundles
+                .iter()
+                .map(|bundle| 8 + bundle.name.len())
+                .sum::<usize>()
+            + 4;
+        let paths = files + ib.files.len() * 20 + 4;
+        f(&mut data, files, paths);
+
+        let mut writer = crate::BundleWriter::new();
+        writer.add("", &data);
+        writer
+            .write(&mut std::fs::File::create(path).unwrap())
+            .unwrap();
+    }
+
+    fn read_all<F: BundleFs>(index: &IndexBundle<F>) -> Vec<BundleResult<Vec<u8>>> {
+        let mut results = Vec::new();
+        for path in ["Data/a.bin", "Data/b.bin"] {
+            results.push(index.read_by_name(path).map(Option::unwrap));
+            results.push(index.open(path).and_then(|reader| {
+                let mut data = Vec::new();
+                reader.unwrap().read_to_end(&mut data)?;
+                Ok(data)
+            }));
+        }
+        for result in index.read_many(["Data/a.bin", "Data/b.bin"]) {
+            results.push(result.map(|(_, data)| data));
+        }
+        results
+    }
+
+    #[test]
+    fn truncated_index_is_rejected() {
+        let (dir, _) = corpus();
+        let path = dir.path().join(INDEX);
+        let raw = std::fs::read(&path).unwrap();
+
+        for len in 0..raw.len() {
+            std::fs::write(&path, &raw[..len]).unwrap();
+            let bundle = Bundle::new(LocalBundleFs::new(dir.path()));
+            assert!(bundle.index().is_err(), "{len}");
+        }
+    }
+
+    #[test]
+    fn truncated_bundles_are_rejected() {
+        let (dir, raw) = corpus();
+        let path = dir.path().join("Bundles2/a.bundle.bin");
+
+        // Every byte of the header, then a sample of the chunks.
+        let head = parse::Head::parse(&raw).unwrap().1.size();
+        for len in (0..head).chain((head..raw.len()).step_by(61)) {
+            std::fs::write(&path, &raw[..len]).unwrap();
+            for fs in [
+                LocalBundleFs::new(dir.path()),
+                LocalBundleFs::new(dir.path()).with_mmap(),
+            ] {
+                // Both files are in the last chunk.
+                let index = Bundle::new(fs).into_index().unwrap();
+                let results = read_all(&index);
+                assert!(results.iter().all(Result::is_err), "{len}: {results:?}");
+            }
+        }
+
+        std::fs::write(&path, &raw).unwrap();
+        let index = Bundle::new(LocalBundleFs::new(dir.path()))
+            .into_index()
+            .unwrap();
+        assert!(read_all(&index).iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn bogus_chunk_counts_are_rejected() {
+        let (dir, raw) = corpus();
+        let path = dir.path().join("Bundles2/a.bundle.bin");
+
+        for chunk_count in [0, 1, 4, 1 << 20, u32::MAX] {
+            let mut data = raw.clone();
+            data[36..40].copy_from_slice(&chunk_count.to_le_bytes());
+            std::fs::write(&path, &data).unwrap();
+
+            let index = Bundle::new(LocalBundleFs::new(dir.path()))
+                .into_index()
+                .unwrap();
+            for result in read_all(&index) {
+                match result {
+                    Err(BundleError::Invalid { offset, .. }) => assert_eq!(offset, 36),
+                    Err(BundleError::Io(_) | BundleError::Truncated { .. }) => {}
+                    result => panic!("{chunk_count}: {result:?}"),
+                }
+            }
+        }
+
+        let mut data = std::fs::read(dir.path().join(INDEX)).unwrap();
+        data[36..40].copy_from_slice(&2u32.to_le_bytes());
+        std::fs::write(dir.path().join(INDEX), data).unwrap();
+        let bundle = Bundle::new(LocalBundleFs::new(dir.path()));
+        assert!(matches!(
+            bundle.index(),
+            Err(BundleError::Invalid { offset: 36, .. })
+        ));
+    }
+
+    #[test]
+    fn out_of_range_path_reps_are_rejected() {
+        for (field, value) in [(8, u32::MAX), (8, 1 << 20), (12, u32::MAX), (12, 1 << 20)] {
+            let (dir, _) = corpus();
+            rewrite_index(dir.path(), |data, _, paths| {
+                let offset = paths + field;
+                data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            });
+
+            let bundle = Bundle::new(LocalBundleFs::new(dir.path()));
+            assert!(
+                matches!(bundle.index(), Err(BundleError::Truncated { .. })),
+                "{field}: {value}"
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range_files_are_rejected() {
+        // Bundle index of the first file.
+        let (dir, _) = corpus();
+        rewrite_index(dir.path(), |data, files, _| {
+            data[files + 8..files + 12].copy_from_slice(&1u32.to_le_bytes());
+        });
+        let bundle = Bundle::new(LocalBundleFs::new(dir.path()));
+        assert!(matches!(bundle.index(), Err(BundleError::Invalid { .. })));
+
+        // Offset and size of both files.
+        for (field, value) in [(12, u32::MAX), (12, CHUNK_SIZE as u32 * 3), (16, u32::MAX)] {
+            let (dir, _) = corpus();
+            rewrite_index(dir.path(), |data, files, _| {
+                for file in [files, files + 20] {
+                    let offset = file + field;
+                    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                }
+            });
+
+            let index = Bundle::new(LocalBundleFs::new(dir.path()))
+                .into_index()
+                .unwrap();
+            for result in read_all(&index) {
+                assert!(
+                    matches!(result, Err(BundleError::Truncated { .. })),
+                    "{field}: {value}: {result:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn detect_path_hasher() {
+        for hasher in PathHasher::ALL {
+            for files in [
+                &[("Data/Mods.dat64", &b"mods"[..]), ("README.txt", b"readme")][..],
+                &[("README.txt", b"readme")],
+                &[("Art/2DItems/Rings/Ring1.dds", b"ring")],
+            ] {
+                let dir = Fixture::new().hasher(hasher).bundle("a", files).tempdir();
+                let index = Bundle::new(LocalBundleFs::new(dir.path()))
+                    .into_index()
+                    .unwrap();
+
+                assert_eq!(index.hasher(), hasher, "{files:?}");
+                for (path, data) in files {
+                    assert_eq!(index.read_by_name(path).unwrap().as_deref(), Some(*data));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn share_index_between_threads() {
+        let files = (0..20)
+            .map(|i| {
+                (
+                    format!("Data/{i}.bin"),
+                    contents(i, CHUNK_SIZE / 2 + i as usize),
+                )
+            })
+            .collect::<Vec<_>>();
+        let refs = files
+            .iter()
+            .map(|(path, data)| (path.as_str(), data.as_slice()))
+            .collect::<Vec<_>>();
+        let dir = Fixture::new()
+            .bundle("a", &refs[..10])
+            .bundle("b", &refs[10..])
+            .tempdir();
+
+        let fs = CacheBundleFs::new(LocalBundleFs::new(dir.path()), InMemoryCache::new());
+        let index = Arc::new(Bundle::new(fs).into_index().unwrap());
+
+        let threads = (0..8)
+            .map(|thread| {
+                let index = Arc::clone(&index);
+                let files = files.clone();
+                std::thread::spawn(move || {
+                    for (path, data) in files.iter().cycle().skip(thread).take(40) {
+                        assert_eq!(index.read_by_name(path).unwrap().as_ref(), Some(data));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn file_cache_serves_decompressed_files() {
+        let (a, b) = (contents(1, 100), contents(2, 200));
+        let dir = Fixture::new()
+            .bundle("a", &[("Data/a.bin", &a), ("Data/b.bin", &b)])
+            .tempdir();
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        for cache in [
+            Arc::new(InMemoryCache::new()) as Arc<dyn FileCache>,
+            Arc::new(LocalCache::new(cache_dir.path())),
+        ] {
+            let fs = Recording::new(LocalBundleFs::new(dir.path()).with_mmap());
+            let index = Bundle::new(&fs)
+                .into_index()
+                .unwrap()
+                .with_file_cache(cache);
+            let reads = || fs.ranges("Bundles2/a.bundle.bin").len();
+
+            assert_eq!(index.read_by_name("Data/a.bin").unwrap(), Some(a.clone()));
+            let before = reads();
+            assert_eq!(index.read_by_name("Data/a.bin").unwrap(), Some(a.clone()));
+            assert_eq!(reads(), before);
+
+            // Only `b.bin` has to be read from the bundle.
+            for _ in 0..2 {
+                let files = index
+                    .read_many(["Data/a.bin", "Data/b.bin"])
+                    .collect::<BundleResult<HashMap<_, _>>>()
+                    .unwrap();
+                assert_eq!(
+                    files,
+                    HashMap::from([("Data/a.bin", a.clone()), ("Data/b.bin", b.clone())])
+                );
+            }
+            assert!(reads() > before);
+            let before = reads();
+            assert_eq!(index.read_by_name("Data/b.bin").unwrap(), Some(b.clone()));
+            assert_eq!(reads(), before);
+        }
+    }
+
+    #[test]
+    fn read_many_order() {
+        let dir = Fixture::new()
+            .bundle("b", &[("Data/b1.bin", b"b1"), ("Data/b2.bin", b"b2")])
+            .bundle("a", &[("Data/a1.bin", b"a1"), ("Data/a2.bin", b"a2")])
+            .tempdir();
+        let index = Bundle::new(LocalBundleFs::new(dir.path()))
+            .into_index()
+            .unwrap()
+            .with_file_cache(InMemoryCache::new());
+
+        index.read_by_name("Data/b2.bin").unwrap();
+        let names = |names: [&'static str; 3]| {
+            index
+                .read_many(names)
+                .map(|file| file.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+
+        // Cached files first, then by bundle name and offset.
+        assert_eq!(
+            names(["Data/b1.bin", "Data/missing.bin", "Data/a2.bin"]),
+            ["Data/a2.bin", "Data/b1.bin"]
+        );
+        assert_eq!(
+            names(["Data/a2.bin", "Data/a1.bin", "Data/b2.bin"]),
+            ["Data/a2.bin", "Data/b2.bin", "Data/a1.bin"]
+        );
+    }
+
+    #[test]
+    fn chunk_cache_skips_reads() {
+        let (a, b) = (contents(1, CHUNK_SIZE * 3), contents(2, CHUNK_SIZE));
+        let dir = Fixture::new()
+            .bundle("a", &[("Data/a.bin", &a), ("Data/b.bin", &b)])
+            .tempdir();
+        let fs = Recording::new(LocalBundleFs::new(dir.path()));
+        let index = Bundle::new(&fs)
+            .into_index()
+            .unwrap()
+            .with_chunk_cache(CHUNK_SIZE * 16);
+        let reads = || fs.ranges("Bundles2/a.bundle.bin").len();
+
+        assert_eq!(index.read_by_name("Data/a.bin").unwrap(), Some(a.clone()));
+        let before = reads();
+        assert_eq!(index.read_by_name("Data/a.bin").unwrap(), Some(a.clone()));
+        assert_eq!(reads(), before);
+
+        let stats = index.chunk_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses), (3, 3));
+        assert_eq!((stats.head_hits, stats.head_misses), (1, 1));
+
+        // Only the last chunk is missing, the header is cached.
+        assert_eq!(index.read_by_name("Data/b.bin").unwrap(), Some(b.clone()));
+        assert_eq!(reads(), before + 1);
+        let stats = index.chunk_cache_stats().unwrap();
+        assert_eq!((stats.misses, stats.size), (4, CHUNK_SIZE * 4));
+    }
+
+    #[test]
+    fn chunk_cache_stays_within_the_budget() {
+        let a = contents(1, CHUNK_SIZE * 4);
+        let dir = Fixture::new().bundle("a", &[("Data/a.bin", &a)]).tempdir();
+        let fs = Recording::new(LocalBundleFs::new(dir.path()));
+        let index = Bundle::new(&fs)
+            .into_index()
+            .unwrap()
+            .with_chunk_cache(CHUNK_SIZE * 2);
+        let reads = || fs.ranges("Bundles2/a.bundle.bin").len();
+
+        for _ in 0..2 {
+            let before = reads();
+            assert_eq!(index.read_by_name("Data/a.bin").unwrap(), Some(a.clone()));
+            // The first two chunks were evicted, all chunks have to be read again.
+            assert!(reads() > before);
+
+            let stats = index.chunk_cache_stats().unwrap();
+            assert!(stats.size <= CHUNK_SIZE * 2, "{stats:?}");
+        }
+
+        let stats = index.chunk_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (0, 8, 6));
+        assert_eq!(stats.size, CHUNK_SIZE * 2);
+    }
+
+    /// Bundles `a`, `b` and `c` with multiple chunks each, verified as intact.
+    fn verify_fixture() -> tempfile::TempDir {
+        let fixture = Fixture::new();
+        let fixture = ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .fold(fixture, |fixture, (i, name)| {
+                let data = contents(i as u8, CHUNK_SIZE * 3 + 10);
+                fixture.bundle(name, &[(&format!("Data/{name}.bin"), &data)])
+            });
+        fixture.tempdir()
+    }
+
+    fn verify(dir: &Path) -> HashMap<String, Vec<BundleError>> {
+        let bundle = Bundle::new(LocalBundleFs::new(dir));
+        bundle
+            .verify()
+            .unwrap()
+            .map(|report| {
+                assert_eq!(report.files, 1);
+                (report.name, report.problems)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn verify_intact_bundles() {
+        let dir = verify_fixture();
+        let reports = verify(dir.path());
+
+        assert_eq!(reports.len(), 3);
+        assert!(reports.values().all(Vec::is_empty), "{reports:?}");
+    }
+
+    #[test]
+    fn verify_reports_missing_bundles() {
+        let dir = verify_fixture();
+        std::fs::remove_file(dir.path().join("Bundles2/b.bundle.bin")).unwrap();
+        let reports = verify(dir.path());
+
+        assert!(reports["a"].is_empty());
+        assert!(
+            matches!(reports["b"][..], [BundleError::Fs(_)]),
+            "{reports:?}"
+        );
+        assert!(reports["c"].is_empty());
+    }
+
+    #[test]
+    fn verify_reports_size_mismatches() {
+        let dir = verify_fixture();
+        // Replace `b` with a smaller bundle, the file is no longer within the bundle.
+        let smaller = Fixture::new()
+            .bundle("b", &[("Data/b.bin", &contents(1, CHUNK_SIZE))])
+            .files();
+        let path = dir.path().join("Bundles2/b.bundle.bin");
+        std::fs::write(path, &smaller["Bundles2/b.bundle.bin"]).unwrap();
+        let reports = verify(dir.path());
+
+        assert!(reports["a"].is_empty());
+        assert!(
+            matches!(
+                reports["b"][..],
+                [
+                    BundleError::SizeMismatch {
+                        expected,
+                        actual,
+                        ..
+                    },
+                    BundleError::Truncated { .. },
+                ] if expected == CHUNK_SIZE * 3 + 10 && actual == CHUNK_SIZE
+            ),
+            "{reports:?}"
+        );
+        assert!(reports["c"].is_empty());
+    }
+
+    #[test]
+    fn verify_reports_corrupted_chunks() {
+        let dir = verify_fixture();
+        let path = dir.path().join("Bundles2/b.bundle.bin");
+        let mut raw = std::fs::read(&path).unwrap();
+
+        // Move the boundary between the first two chunks, the total size stays the same.
+        let first = u32::from_le_bytes(raw[60..64].try_into().unwrap());
+        let second = u32::from_le_bytes(raw[64..68].try_into().unwrap());
+        raw[60..64].copy_from_slice(&(first + 1).to_le_bytes());
+        raw[64..68].copy_from_slice(&(second - 1).to_le_bytes());
+        std::fs::write(&path, &raw).unwrap();
+
+        let reports = verify(dir.path());
+        assert!(reports["a"].is_empty());
+        let rejected = match reports["b"][..] {
+            [BundleError::Decompress(_) | BundleError::SizeMismatch { .. }] => true,
+            #[cfg(feature = "pure-rust")]
+            [BundleError::Oodle(_)] => true,
+            _ => false,
+        };
+        assert!(rejected, "{reports:?}");
+        assert!(reports["c"].is_empty());
+
+        // A truncated bundle is reported as well.
+        std::fs::write(&path, &raw[..raw.len() - 1]).unwrap();
+        let reports = verify(dir.path());
+        assert_eq!(reports["b"].len(), 1, "{reports:?}");
+    }
+
+    #[cfg(feature = "async")]
+    mod nonblocking {
+        use std::future::Future;
+
+        use super::*;
+        use crate::bundle::AsyncBundleFs;
+
+        fn block_on<T>(future: impl Future<Output = T>) -> T {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(future)
+        }
+
+        fn fixture() -> Fixture {
+            Fixture::new()
+                .bundle(
+                    "a",
+                    &[
+                        ("Data/small.bin", &contents(1, 100)),
+                        ("Data/big.bin", &contents(2, CHUNK_SIZE * 5 + 17)),
+                        ("Data/tail.bin", &contents(3, CHUNK_SIZE + 1)),
+                    ],
+                )
+                .bundle("b", &[("Art/b.dds", &contents(4, 10))])
+        }
+
+        const FILES: [&str; 4] = [
+            "Data/small.bin",
+            "Data/big.bin",
+            "Data/tail.bin",
+            "Art/b.dds",
+        ];
+
+        /// Reads all files asynchronously and compares them with blocking reads.
+        async fn read_all<F: AsyncBundleFs + BundleFs>(index: &IndexBundle<F>) {
+            for name in FILES {
+                let expected = index.read_by_name(name).unwrap().unwrap();
+                let data = index.read_by_name_async(name).await.unwrap();
+                assert_eq!(data, Some(expected), "{name}");
+            }
+            assert_eq!(
+                index.read_by_name_async("Data/missing.bin").await.unwrap(),
+                None
+            );
+        }
+
+        #[test]
+        fn local_index_async() {
+            let dir = fixture().tempdir();
+
+            block_on(async {
+                for fs in [
+                    LocalBundleFs::new(dir.path()),
+                    LocalBundleFs::new(dir.path()).with_mmap(),
+                ] {
+                    let bundle = Bundle::new(fs);
+                    let index = bundle.index_async().await.unwrap();
+                    assert_eq!(index.paths().count(), FILES.len());
+                    read_all(&index).await;
+
+                    let index = bundle.into_index_async().await.unwrap();
+                    read_all(&index).await;
+                }
+            });
+        }
+
+        #[test]
+        fn local_get_range_async() {
+            let dir = fixture().tempdir();
+            let fs = LocalBundleFs::new(dir.path());
+            let raw = std::fs::read(dir.path().join(INDEX)).unwrap();
+
+            block_on(async {
+                let data = AsyncBundleFs::get(&fs, INDEX).await.unwrap();
+                assert_eq!(data, raw);
+                let range = AsyncBundleFs::get_range(&fs, INDEX, 10..20).await.unwrap();
+                assert_eq!(range.as_deref(), Some(&raw[10..20]));
+                let range = AsyncBundleFs::get_range(&fs, INDEX, 10..u64::MAX)
+                    .await
+                    .unwrap();
+                assert_eq!(range.as_deref(), Some(&raw[10..]));
+                assert!(AsyncBundleFs::get(&fs, "missing").await.is_err());
+            });
+        }
+
+        #[test]
+        fn async_index_rejects_corrupt_bundles() {
+            let dir = fixture().tempdir();
+            let path = dir.path().join("Bundles2/a.bundle.bin");
+            let raw = std::fs::read(&path).unwrap();
+            std::fs::write(&path, &raw[..raw.len() / 2]).unwrap();
+
+            block_on(async {
+                let index = Bundle::new(LocalBundleFs::new(dir.path()))
+                    .into_index_async()
+                    .await
+                    .unwrap();
+                assert!(index.read_by_name_async("Data/tail.bin").await.is_err());
+                assert!(index.read_by_name_async("Art/b.dds").await.is_ok());
+
+                std::fs::remove_file(dir.path().join(INDEX)).unwrap();
+                let bundle = Bundle::new(LocalBundleFs::new(dir.path()));
+                assert!(bundle.index_async().await.is_err());
+            });
+        }
+
+        #[test]
+        fn cached_index_async() {
+            let dir = fixture().tempdir();
+            let cache_dir = tempfile::tempdir().unwrap();
+
+            block_on(async {
+                let in_memory = Bundle::new(CacheBundleFs::new(
+                    LocalBundleFs::new(dir.path()),
+                    InMemoryCache::new(),
+                ))
+                .into_index_async()
+                .await
+                .unwrap();
+                let local = Bundle::new(CacheBundleFs::new(
+                    LocalBundleFs::new(dir.path()),
+                    LocalCache::new(cache_dir.path()),
+                ))
+                .into_index_async()
+                .await
+                .unwrap();
+                read_all(&in_memory).await;
+                read_all(&local).await;
+
+                // Everything is served from the caches once the bundles are gone.
+                let expected = contents(2, CHUNK_SIZE * 5 + 17);
+                std::fs::remove_dir_all(dir.path().join("Bundles2")).unwrap();
+                assert_eq!(
+                    in_memory.read_by_name_async("Data/big.bin").await.unwrap(),
+                    Some(expected.clone())
+                );
+                assert_eq!(
+                    local.read_by_name_async("Data/big.bin").await.unwrap(),
+                    Some(expected)
+                );
+
+                // A new index is read from the cached bundles.
+                let index = Bundle::new(CacheBundleFs::new(
+                    LocalBundleFs::new(dir.path()),
+                    LocalCache::new(cache_dir.path()),
+                ))
+                .into_index_async()
+                .await
+                .unwrap();
+                assert_eq!(
+                    index.read_by_name_async("Art/b.dds").await.unwrap(),
+                    Some(contents(4, 10))
+                );
+            });
+        }
+
+        #[cfg(feature = "web")]
+        #[test]
+        fn web_index_async() {
+            use crate::bundle::testutil::Server;
+            use crate::bundle::WebBundleFs;
+
+            for server in [
+                Server::new(fixture().files()),
+                Server::new(fixture().files()).without_ranges(),
+            ] {
+                block_on(async {
+                    let index = Bundle::new(WebBundleFs::new(&server.url))
+                        .into_index_async()
+                        .await
+                        .unwrap();
+                    read_all(&index).await;
+                });
+            }
+
+            // With range support only the header and required chunks are requested.
+            let server = Server::new(fixture().files());
+            block_on(async {
+                let index = Bundle::new(WebBundleFs::new(&server.url))
+                    .into_index_async()
+                    .await
+                    .unwrap();
+                let data = index.read_by_name_async("Data/small.bin").await.unwrap();
+                assert_eq!(data, Some(contents(1, 100)));
+            });
+            let requests = server.requests();
+            let bundle = requests
+                .iter()
+                .filter(|request| request.path == "Bundles2/a.bundle.bin")
+                .collect::<Vec<_>>();
+            assert!(!bundle.is_empty());
+            assert!(
+                bundle.iter().all(|request| request.range.is_some()),
+                "{requests:?}"
+            );
+        }
+    }
 }
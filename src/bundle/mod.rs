@@ -1,7 +1,27 @@
+mod chunk_cache;
+mod diff;
 mod fs;
+mod ggpk;
 mod high;
+#[cfg(feature = "pure-rust")]
+mod oodle;
 mod ooz;
+mod overlay;
 mod parse;
+mod snapshot;
+mod table;
+#[cfg(test)]
+mod testutil;
+mod tree;
+mod write;
 
+pub use self::chunk_cache::ChunkCacheStats;
+pub use self::diff::IndexDiff;
 pub use self::fs::*;
+pub use self::ggpk::GgpkBundleFs;
 pub use self::high::*;
+pub use self::overlay::{OverlayDirEntry, OverlayFileReader, OverlayIndex};
+pub use self::snapshot::SnapshotKey;
+pub use self::tree::DirEntry;
+pub use self::write::{BundleWriter, IndexWriter};
+pub use libooz_sys::Compressor;
@@ -0,0 +1,174 @@
+//! Bit readers over byte slices.
+//!
+//! Streams are read either from the front or from the back of a slice, which allows two
+//! streams to share a buffer and meet in the middle. Reading past the end yields zeros,
+//! decoders check how many bytes were consumed instead.
+
+/// Reads bits starting with the most significant bit of every byte.
+pub struct MsbBits<'a> {
+    data: &'a [u8],
+    backward: bool,
+    pos: usize,
+}
+
+impl<'a> MsbBits<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            backward: false,
+            pos: 0,
+        }
+    }
+
+    /// Reads the bytes of `data` from the last to the first.
+    pub fn backward(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            backward: true,
+            pos: 0,
+        }
+    }
+
+    fn byte(&self, i: usize) -> u64 {
+        let byte = match self.backward {
+            false => self.data.get(i),
+            true => self.data.len().checked_sub(i + 1).map(|i| &self.data[i]),
+        };
+        byte.copied().unwrap_or(0).into()
+    }
+
+    /// Returns the next 32 bits without consuming them.
+    pub fn peek(&self) -> u32 {
+        let i = self.pos / 8;
+        let v = (0..5).fold(0, |v, j| v << 8 | self.byte(i + j));
+        (v >> (8 - self.pos % 8)) as u32
+    }
+
+    /// Reads `n` bits, up to 32.
+    pub fn read(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        let v = self.peek() >> (32 - n);
+        self.pos += n as usize;
+        v
+    }
+
+    pub fn read_bit(&mut self) -> bool {
+        self.read(1) == 1
+    }
+
+    pub fn skip(&mut self, n: u32) {
+        self.pos += n as usize;
+    }
+
+    /// Amount of leading zero bits before the next set bit, 32 if there is none within 32 bits.
+    pub fn leading_zeros(&self) -> u32 {
+        self.peek().leading_zeros()
+    }
+
+    /// Amount of started bytes.
+    pub fn bytes(&self) -> usize {
+        self.pos.div_ceil(8)
+    }
+
+    /// Amount of bits left until the end of the data.
+    pub fn remaining(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.pos)
+    }
+}
+
+/// Reads bits starting with the least significant bit of every byte.
+pub struct LsbBits<'a> {
+    data: &'a [u8],
+    backward: bool,
+    pos: usize,
+}
+
+impl<'a> LsbBits<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            backward: false,
+            pos: 0,
+        }
+    }
+
+    /// Reads the bytes of `data` from the last to the first.
+    pub fn backward(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            backward: true,
+            pos: 0,
+        }
+    }
+
+    fn byte(&self, i: usize) -> u64 {
+        let byte = match self.backward {
+            false => self.data.get(i),
+            true => self.data.len().checked_sub(i + 1).map(|i| &self.data[i]),
+        };
+        byte.copied().unwrap_or(0).into()
+    }
+
+    /// Returns the next 32 bits without consuming them, the next bit is the lowest.
+    pub fn peek(&self) -> u32 {
+        let i = self.pos / 8;
+        let v = (0..5).rev().fold(0, |v, j| v << 8 | self.byte(i + j));
+        (v >> (self.pos % 8)) as u32
+    }
+
+    /// Reads `n` bits, up to 32.
+    pub fn read(&mut self, n: u32) -> u32 {
+        let v = (self.peek() as u64 & ((1 << n) - 1)) as u32;
+        self.pos += n as usize;
+        v
+    }
+
+    pub fn skip(&mut self, n: u32) {
+        self.pos += n as usize;
+    }
+
+    /// Amount of started bytes.
+    pub fn bytes(&self) -> usize {
+        self.pos.div_ceil(8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msb_bits() {
+        let data = [0b1010_0000, 0b1111_0000, 0x12];
+        let mut bits = MsbBits::new(&data);
+        assert_eq!(bits.read(3), 0b101);
+        assert_eq!(bits.leading_zeros(), 5);
+        assert_eq!(bits.read(9), 0b0_0000_1111);
+        assert_eq!(bits.bytes(), 2);
+        assert_eq!(bits.read(12), 0x012);
+        assert_eq!(bits.read(8), 0);
+
+        let mut bits = MsbBits::backward(&data);
+        assert_eq!(bits.read(12), 0x12f);
+        assert_eq!(bits.read(4), 0);
+        assert_eq!(bits.bytes(), 2);
+    }
+
+    #[test]
+    fn lsb_bits() {
+        let data = [0b1010_0110, 0b1111_0000, 0x12];
+        let mut bits = LsbBits::new(&data);
+        assert_eq!(bits.read(3), 0b110);
+        assert_eq!(bits.read(9), 0b0000_10100);
+        assert_eq!(bits.bytes(), 2);
+        assert_eq!(bits.read(12), 0x12f);
+        assert_eq!(bits.read(32), 0);
+
+        let mut bits = LsbBits::backward(&data);
+        assert_eq!(bits.read(12), 0x012);
+        assert_eq!(bits.read(4), 0xf);
+        assert_eq!(bits.bytes(), 2);
+    }
+}
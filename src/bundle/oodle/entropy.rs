@@ -0,0 +1,933 @@
+//! Entropy coded byte streams.
+//!
+//! Every stream starts with a header, which contains the type of the stream,
+//! its compressed and its decompressed size, see [`decode_bytes`].
+use super::bits::{LsbBits, MsbBits};
+use super::{Error, Result};
+
+/// Decodes a stream of at most `capacity` bytes and returns it and the amount of consumed bytes.
+pub fn decode_bytes(src: &[u8], capacity: usize) -> Result<(Vec<u8>, usize)> {
+    let header = Header::parse(src, capacity)?;
+    let data = &src[header.size..header.size + header.compressed];
+    let consumed = header.size + header.compressed;
+
+    if header.kind == 0 {
+        return Ok((data.to_vec(), consumed));
+    }
+
+    let mut out = vec![0; header.decompressed];
+    let used = match header.kind {
+        1 => decode_tans(data, &mut out)?,
+        2 | 4 => decode_huffman(data, &mut out, header.kind == 4)?,
+        3 => decode_rle(data, &mut out)?,
+        5 => decode_recursive(data, &mut out)?,
+        _ => return Err(Error("unknown entropy type")),
+    };
+    if used != data.len() {
+        return Err(Error("entropy stream size mismatch"));
+    }
+    Ok((out, consumed))
+}
+
+/// Returns the decompressed size of the next stream without decoding it.
+fn decoded_size(src: &[u8], capacity: usize) -> Result<usize> {
+    Header::parse(src, capacity).map(|header| header.decompressed)
+}
+
+struct Header {
+    kind: u8,
+    /// Size of the header.
+    size: usize,
+    compressed: usize,
+    decompressed: usize,
+}
+
+impl Header {
+    fn parse(src: &[u8], capacity: usize) -> Result<Self> {
+        let truncated = Error("truncated entropy header");
+        let &[first, ..] = src else {
+            return Err(truncated);
+        };
+        if src.len() < 2 {
+            return Err(truncated);
+        }
+
+        let kind = (first >> 4) & 7;
+        let (size, compressed, decompressed) = if kind == 0 {
+            // Stored, the size is either 12 or 18 bits.
+            let (size, len): (usize, u32) = if first >= 0x80 {
+                (2, u32::from_be_bytes([0, 0, src[0], src[1]]) & 0xfff)
+            } else {
+                let b = src.get(..3).ok_or(truncated)?;
+                let len = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+                if len & !0x3ffff != 0 {
+                    return Err(Error("invalid stored size"));
+                }
+                (3, len)
+            };
+            (size, len, len)
+        } else if kind >= 6 {
+            return Err(Error("unknown entropy type"));
+        } else if first >= 0x80 {
+            // Short header, with 10 bit sizes.
+            let b = src.get(..3).ok_or(truncated)?;
+            let bits = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+            let compressed = bits & 0x3ff;
+            (3, compressed, compressed + ((bits >> 10) & 0x3ff) + 1)
+        } else {
+            // Long header, with 18 bit sizes.
+            let b = src.get(..5).ok_or(truncated)?;
+            let bits = u32::from_be_bytes([b[1], b[2], b[3], b[4]]);
+            let compressed = bits & 0x3ffff;
+            let decompressed = (((bits >> 18) | (b[0] as u32) << 14) & 0x3ffff) + 1;
+            if compressed >= decompressed {
+                return Err(Error("entropy stream is not compressed"));
+            }
+            (5, compressed, decompressed)
+        };
+
+        let (compressed, decompressed) = (compressed as usize, decompressed as usize);
+        if src.len() - size < compressed || decompressed > capacity {
+            return Err(Error("entropy stream is too large"));
+        }
+
+        Ok(Self {
+            kind,
+            size,
+            compressed,
+            decompressed,
+        })
+    }
+}
+
+/// Decodes a stream split into multiple streams, which are decoded back to back.
+fn decode_recursive(src: &[u8], out: &mut [u8]) -> Result<usize> {
+    if src.len() < 6 {
+        return Err(Error("truncated recursive stream"));
+    }
+
+    let count = src[0] & 0x7f;
+    if count < 2 {
+        return Err(Error("invalid recursive stream"));
+    }
+
+    if src[0] & 0x80 != 0 {
+        let (arrays, consumed) = decode_multi_array(src, out.len(), 1)?;
+        if arrays[0].len() != out.len() {
+            return Err(Error("recursive stream size mismatch"));
+        }
+        out.copy_from_slice(&arrays[0]);
+        return Ok(consumed);
+    }
+
+    let mut s = 1;
+    let mut pos = 0;
+    for _ in 0..count {
+        let (decoded, n) = decode_bytes(&src[s..], out.len() - pos)?;
+        out[pos..pos + decoded.len()].copy_from_slice(&decoded);
+        pos += decoded.len();
+        s += n;
+    }
+    if pos != out.len() {
+        return Err(Error("recursive stream size mismatch"));
+    }
+    Ok(s)
+}
+
+/// Decodes `count` arrays which are interleaved from multiple streams.
+///
+/// Returns the arrays and the amount of consumed bytes,
+/// the total size of the arrays is limited to `capacity`.
+pub fn decode_multi_array(
+    src: &[u8],
+    capacity: usize,
+    count: usize,
+) -> Result<(Vec<Vec<u8>>, usize)> {
+    let truncated = Error("truncated multi array");
+    if src.len() < 4 {
+        return Err(truncated);
+    }
+    if src[0] & 0x80 == 0 {
+        return Err(Error("invalid multi array"));
+    }
+    let streams = (src[0] & 0x3f) as usize;
+    let mut s = 1;
+
+    if streams == 0 {
+        let mut arrays = Vec::with_capacity(count);
+        let mut left = capacity;
+        for _ in 0..count {
+            let (array, n) = decode_bytes(&src[s..], left)?;
+            left -= array.len();
+            s += n;
+            arrays.push(array);
+        }
+        return Ok((arrays, s));
+    }
+
+    let mut decoded = Vec::with_capacity(streams);
+    for _ in 0..streams {
+        let (stream, n) = decode_bytes(&src[s..], usize::MAX)?;
+        decoded.push(stream);
+        s += n;
+    }
+    let total = decoded.iter().map(Vec::len).sum();
+
+    let q = src.get(s..s + 2).ok_or(truncated)?;
+    let q = u16::from_le_bytes([q[0], q[1]]);
+    s += 2;
+    if src.len() - s < 1 {
+        return Err(truncated);
+    }
+
+    let index_count = decoded_size(&src[s..], total)?;
+    let mut length_count = index_count
+        .checked_sub(count)
+        .filter(|&n| n >= 1)
+        .ok_or(Error("invalid multi array"))?;
+
+    // The intervals of every array, as the index of its stream and log2 of its length.
+    let (mut indexes, n) = decode_bytes(&src[s..], index_count)?;
+    s += n;
+    if indexes.len() != index_count {
+        return Err(Error("multi array size mismatch"));
+    }
+    let joined_lengths = q & 0x8000 != 0;
+    let log2_lengths = if joined_lengths {
+        length_count = index_count;
+        let log2_lengths = indexes.iter().map(|i| i >> 4).collect();
+        indexes.iter_mut().for_each(|i| *i &= 0xf);
+        log2_lengths
+    } else {
+        let (log2_lengths, n) = decode_bytes(&src[s..], length_count)?;
+        s += n;
+        if log2_lengths.len() != length_count || log2_lengths.iter().any(|&l| l > 16) {
+            return Err(Error("invalid multi array lengths"));
+        }
+        log2_lengths
+    };
+
+    let bits_size = (q & 0x3fff) as usize;
+    let bits = src.get(s..s + bits_size).ok_or(truncated)?;
+    let mut forward = MsbBits::new(bits);
+    let mut backward = MsbBits::backward(bits);
+    let lengths = log2_lengths[..length_count]
+        .iter()
+        .enumerate()
+        .map(|(i, &log2)| {
+            let bits = match i % 2 {
+                0 => &mut forward,
+                _ => &mut backward,
+            };
+            (1 << log2 | bits.read(log2.into())) as usize
+        })
+        .collect::<Vec<_>>();
+
+    if indexes.last() != Some(&0) {
+        return Err(Error("invalid multi array"));
+    }
+
+    let mut streams = decoded.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    let mut arrays = Vec::with_capacity(count);
+    let (mut i, mut l) = (0, 0);
+    let mut total = 0;
+    for _ in 0..count {
+        let mut array = Vec::new();
+        loop {
+            let &index = indexes.get(i).ok_or(Error("multi array index overflow"))?;
+            i += 1;
+            if index == 0 {
+                break;
+            }
+            let stream = streams
+                .get_mut(index as usize - 1)
+                .ok_or(Error("invalid multi array index"))?;
+            let &len = lengths.get(l).ok_or(Error("multi array length overflow"))?;
+            l += 1;
+            if len > stream.len() || len > capacity - total {
+                return Err(Error("multi array interval is too large"));
+            }
+            let (interval, rest) = stream.split_at(len);
+            array.extend_from_slice(interval);
+            *stream = rest;
+            total += len;
+        }
+        l += usize::from(joined_lengths);
+        arrays.push(array);
+    }
+
+    if i != indexes.len() || l != lengths.len() || streams.iter().any(|s| !s.is_empty()) {
+        return Err(Error("multi array has unused data"));
+    }
+
+    Ok((arrays, s + bits_size))
+}
+
+/// Decodes a run length encoded stream.
+fn decode_rle(src: &[u8], out: &mut [u8]) -> Result<usize> {
+    match src {
+        [] => return Err(Error("empty rle stream")),
+        &[byte] => {
+            out.fill(byte);
+            return Ok(1);
+        }
+        _ => {}
+    }
+
+    // The beginning of the commands can be entropy coded, the rest is stored as is.
+    let prefix;
+    let commands = if src[0] != 0 {
+        let (decoded, n) = decode_bytes(src, usize::MAX)?;
+        prefix = [decoded.as_slice(), &src[n..]].concat();
+        &prefix
+    } else {
+        &src[1..]
+    };
+
+    // Literals are read from the front of the commands, commands from the back.
+    let truncated = Error("truncated rle stream");
+    let (mut front, mut back) = (0, commands.len());
+    let mut pos = 0;
+    let mut byte = 0;
+    while front < back {
+        let cmd = commands[back - 1] as u32;
+        let wide = || match back.checked_sub(2) {
+            Some(i) if i >= front => Ok(u16::from_le_bytes([commands[i], commands[i + 1]]) as u32),
+            _ => Err(Error("truncated rle stream")),
+        };
+        let (copy, run) = if cmd.wrapping_sub(1) >= 0x2f {
+            back -= 1;
+            (!cmd & 0xf, cmd >> 4)
+        } else if cmd >= 0x10 {
+            let data = wide()? - 4096;
+            back -= 2;
+            (data & 0x3f, data >> 6)
+        } else if cmd == 1 {
+            byte = commands[front];
+            front += 1;
+            back -= 1;
+            (0, 0)
+        } else if cmd >= 9 {
+            let run = (wide()? - 0x8ff) * 128;
+            back -= 2;
+            (0, run)
+        } else {
+            let copy = (wide()? - 511) * 64;
+            back -= 2;
+            (copy, 0)
+        };
+
+        let (copy, run) = (copy as usize, run as usize);
+        if out.len() - pos < copy + run || back.saturating_sub(front) < copy {
+            return Err(truncated);
+        }
+        out[pos..pos + copy].copy_from_slice(&commands[front..front + copy]);
+        front += copy;
+        pos += copy;
+        out[pos..pos + run].fill(byte);
+        pos += run;
+    }
+
+    if front != back || pos != out.len() {
+        return Err(Error("rle stream size mismatch"));
+    }
+    Ok(src.len())
+}
+
+/// Start of the symbols of every code length, in a list of symbols sorted by code length.
+const CODE_PREFIX: [usize; 12] = [
+    0x0, 0x0, 0x2, 0x6, 0xe, 0x1e, 0x3e, 0x7e, 0xfe, 0x1fe, 0x2fe, 0x3fe,
+];
+
+/// Symbols sorted by their code length, which is at most 11 bits.
+struct Symbols {
+    syms: [u8; 1280],
+    ends: [usize; 12],
+}
+
+impl Symbols {
+    fn new() -> Self {
+        Self {
+            syms: [0; 1280],
+            ends: CODE_PREFIX,
+        }
+    }
+
+    fn push(&mut self, len: usize, sym: u8) {
+        // At most 256 symbols are read, which always fits.
+        self.syms[self.ends[len]] = sym;
+        self.ends[len] += 1;
+    }
+}
+
+/// Decodes a canonical Huffman coded stream, which is split into 3 or 6 interleaved streams.
+fn decode_huffman(src: &[u8], out: &mut [u8], six_streams: bool) -> Result<usize> {
+    let mut bits = MsbBits::new(src);
+    let mut symbols = Symbols::new();
+    let count = if !bits.read_bit() {
+        read_code_lengths_old(&mut bits, &mut symbols)?
+    } else if !bits.read_bit() {
+        read_code_lengths_new(&mut bits, &mut symbols)?
+    } else {
+        return Err(Error("unknown huffman table"));
+    };
+
+    let mut s = bits.bytes();
+    if count == 1 {
+        out.fill(symbols.syms[0]);
+        return Ok(s);
+    }
+
+    let lut = HuffmanLut::new(&symbols)?;
+    let truncated = Error("truncated huffman stream");
+    if !six_streams {
+        if s + 3 > src.len() {
+            return Err(truncated);
+        }
+        let split = u16::from_le_bytes([src[s], src[s + 1]]) as usize;
+        s += 2;
+        lut.decode(&src[s..], split, out)?;
+    } else {
+        if s + 6 > src.len() {
+            return Err(truncated);
+        }
+        let half = out.len().div_ceil(2);
+        let split = u32::from_le_bytes([src[s], src[s + 1], src[s + 2], 0]) as usize;
+        s += 3;
+        if split > src.len() - s {
+            return Err(truncated);
+        }
+        let mid = s + split;
+        let left = u16::from_le_bytes([src[s], src[s + 1]]) as usize;
+        s += 2;
+        if mid.checked_sub(s).is_none_or(|n| n < left + 2) || src.len() - mid < 3 {
+            return Err(truncated);
+        }
+        let right = u16::from_le_bytes([src[mid], src[mid + 1]]) as usize;
+
+        let (first, second) = out.split_at_mut(half);
+        lut.decode(&src[s..mid], left, first)?;
+        lut.decode(&src[mid + 2..], right, second)?;
+    }
+    Ok(src.len())
+}
+
+/// Reads a gamma coded value with a `n` bit prefix of zeros, and `n + 1` bits after the set bit.
+fn read_gamma(bits: &mut MsbBits) -> Result<u32> {
+    if bits.peek() & 0xff00_0000 == 0 {
+        return Err(Error("invalid huffman table"));
+    }
+    let n = bits.leading_zeros();
+    Ok(bits.read(2 * (n + 1)) - 2)
+}
+
+/// Reads code lengths of runs of symbols.
+fn read_code_lengths_old(bits: &mut MsbBits, symbols: &mut Symbols) -> Result<usize> {
+    let invalid = Error("invalid huffman table");
+    if !bits.read_bit() {
+        // Sparse table, which lists the symbols and their code lengths.
+        let count = bits.read(8) as usize;
+        match count {
+            0 => return Err(invalid),
+            1 => symbols.syms[0] = bits.read(8) as u8,
+            _ => {
+                let len_bits = bits.read(3);
+                if len_bits > 4 {
+                    return Err(invalid);
+                }
+                for _ in 0..count {
+                    let sym = bits.read(8) as u8;
+                    let len = bits.read(len_bits) as usize + 1;
+                    if len > 11 {
+                        return Err(invalid);
+                    }
+                    symbols.push(len, sym);
+                }
+            }
+        }
+        return Ok(count);
+    }
+
+    let forced_bits = bits.read(2);
+    let threshold = 1 << (31 - (20 >> forced_bits));
+    let mut average_x4 = 32;
+    let mut sym = 0;
+    let mut count = 0;
+    let mut skip_zeros = bits.read_bit();
+    loop {
+        // Symbols without a code, followed by a run of symbols with a code.
+        if !skip_zeros {
+            sym += read_gamma(bits)? as usize + 1;
+            if sym >= 256 {
+                break;
+            }
+        }
+        skip_zeros = false;
+
+        let n = read_gamma(bits)? as usize + 1;
+        if sym + n > 256 {
+            return Err(invalid);
+        }
+        count += n;
+        for _ in 0..n {
+            if bits.peek() < threshold {
+                return Err(invalid);
+            }
+            let lz = bits.leading_zeros() as i32;
+            let v = bits.read(lz as u32 + forced_bits + 1) as i32 + ((lz - 1) << forced_bits);
+            let len = (-(v & 1) ^ (v >> 1)) + ((average_x4 + 2) >> 2);
+            if !(1..=11).contains(&len) {
+                return Err(invalid);
+            }
+            average_x4 = len + ((3 * average_x4 + 2) >> 2);
+            symbols.push(len as usize, sym as u8);
+            sym += 1;
+        }
+        if sym == 256 {
+            break;
+        }
+    }
+
+    if sym != 256 || count < 2 {
+        return Err(invalid);
+    }
+    Ok(count)
+}
+
+/// Reads Golomb-Rice coded code lengths of ranges of symbols.
+fn read_code_lengths_new(bits: &mut MsbBits, symbols: &mut Symbols) -> Result<usize> {
+    let invalid = Error("invalid huffman table");
+    let forced_bits = bits.read(2);
+    let count = bits.read(8) as usize + 1;
+    let fluff = read_fluff(bits, count);
+
+    let mut lengths = read_rice_lengths(bits, count + fluff)?;
+    read_rice_bits(bits, &mut lengths[..count], forced_bits)?;
+
+    let mut sum = 0x1eu32;
+    for len in &mut lengths[..count] {
+        let v = *len as i32;
+        let v = -(v & 1) ^ (v >> 1);
+        *len = (v as u32).wrapping_add(sum >> 2).wrapping_add(1) as u8;
+        if !(1..=11).contains(len) {
+            return Err(invalid);
+        }
+        sum = sum.wrapping_add(v as u32);
+    }
+
+    let ranges = read_ranges(bits, count, &lengths[count..])?;
+    let mut lengths = lengths.iter();
+    for (start, n) in ranges {
+        for sym in start..start + n {
+            let &len = lengths.next().ok_or(invalid)?;
+            symbols.push(len.into(), sym as u8);
+        }
+    }
+    Ok(count)
+}
+
+/// Reads the amount of values describing the ranges of `count` symbols.
+fn read_fluff(bits: &mut MsbBits, count: usize) -> usize {
+    if count == 256 {
+        return 0;
+    }
+    let x = 2 * (257 - count).min(count) as u32;
+    let y = 32 - (x - 1).leading_zeros();
+    let v = bits.peek() >> (32 - y);
+    let z = (1 << y) - x;
+    if v >> 1 >= z {
+        bits.skip(y);
+        (v - z) as usize
+    } else {
+        bits.skip(y - 1);
+        (v >> 1) as usize
+    }
+}
+
+/// Reads `count` unary coded values, the amount of zeros before every set bit.
+fn read_rice_lengths(bits: &mut MsbBits, count: usize) -> Result<Vec<u8>> {
+    let available = bits.remaining();
+    let mut values = Vec::with_capacity(count);
+    let mut read = 0;
+    for _ in 0..count {
+        let mut v = 0u32;
+        loop {
+            if read >= available {
+                return Err(Error("truncated rice lengths"));
+            }
+            read += 1;
+            if bits.read_bit() {
+                break;
+            }
+            v += 1;
+        }
+        values.push(v as u8);
+    }
+    Ok(values)
+}
+
+/// Appends `n` more bits to every value.
+fn read_rice_bits(bits: &mut MsbBits, values: &mut [u8], n: u32) -> Result<()> {
+    if n == 0 {
+        return Ok(());
+    }
+    if bits.remaining() < values.len() * n as usize {
+        return Err(Error("truncated rice bits"));
+    }
+    for v in values {
+        *v = (*v << n) | bits.read(n) as u8;
+    }
+    Ok(())
+}
+
+/// Reads ranges of symbols with a code as start and length.
+///
+/// The ranges are described by `lengths`, the last range contains the remaining symbols.
+fn read_ranges(bits: &mut MsbBits, count: usize, lengths: &[u8]) -> Result<Vec<(usize, usize)>> {
+    let invalid = Error("invalid symbol ranges");
+    let mut lengths = lengths.iter().map(|&l| l as u32);
+    let mut sym = 0;
+    if lengths.len() % 2 == 1 {
+        let v = lengths.next().ok_or(invalid)?;
+        if v >= 8 {
+            return Err(invalid);
+        }
+        sym = (bits.read(v + 1) + (1 << (v + 1)) - 1) as usize;
+    }
+
+    let mut ranges = Vec::with_capacity(lengths.len() / 2 + 1);
+    let mut used = 0;
+    while let (Some(a), Some(b)) = (lengths.next(), lengths.next()) {
+        if a >= 9 || b >= 8 {
+            return Err(invalid);
+        }
+        let n = (bits.read(a) + (1 << a)) as usize;
+        let space = (bits.read(b + 1) + (1 << (b + 1)) - 1) as usize;
+        ranges.push((sym, n));
+        used += n;
+        sym += n + space;
+    }
+
+    if sym >= 256 || used >= count || sym + count - used > 256 {
+        return Err(invalid);
+    }
+    ranges.push((sym, count - used));
+    Ok(ranges)
+}
+
+/// Lookup table of the next 11 bits of a stream to the code length and symbol.
+struct HuffmanLut {
+    lens: [u8; 2048],
+    syms: [u8; 2048],
+}
+
+impl HuffmanLut {
+    fn new(symbols: &Symbols) -> Result<Self> {
+        let invalid = Error("invalid huffman code lengths");
+        let mut lens = [0; 2048];
+        let mut syms = [0; 2048];
+
+        // Codes are assigned in order of their length, the stream is read least significant
+        // bit first, which reverses the order of the bits in the table.
+        let mut slot = 0;
+        for (len, (&start, &end)) in CODE_PREFIX.iter().zip(&symbols.ends).enumerate().skip(1) {
+            let step = 1 << (11 - len);
+            for &sym in &symbols.syms[start..end] {
+                if slot + step > 2048 {
+                    return Err(invalid);
+                }
+                for code in slot..slot + step {
+                    let reversed = (code as u16).reverse_bits() as usize >> 5;
+                    lens[reversed] = len as u8;
+                    syms[reversed] = sym;
+                }
+                slot += step;
+            }
+        }
+        if slot != 2048 {
+            return Err(invalid);
+        }
+
+        Ok(Self { lens, syms })
+    }
+
+    /// Decodes three interleaved streams, the first one of `split` bytes and the other two
+    /// from both ends of the remaining bytes.
+    fn decode(&self, src: &[u8], split: usize, out: &mut [u8]) -> Result<()> {
+        if split > src.len() {
+            return Err(Error("truncated huffman stream"));
+        }
+        let (first, rest) = src.split_at(split);
+        let mut streams = [
+            LsbBits::new(first),
+            LsbBits::backward(rest),
+            LsbBits::new(rest),
+        ];
+
+        for (i, out) in out.iter_mut().enumerate() {
+            let bits = &mut streams[i % 3];
+            let code = (bits.peek() & 0x7ff) as usize;
+            *out = self.syms[code];
+            bits.skip(self.lens[code].into());
+        }
+
+        let [first_bits, back, front] = &streams;
+        if first_bits.bytes() != first.len() || back.bytes() + front.bytes() != rest.len() {
+            return Err(Error("huffman stream size mismatch"));
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a tANS coded stream.
+fn decode_tans(src: &[u8], out: &mut [u8]) -> Result<usize> {
+    if src.len() < 8 || out.len() < 5 {
+        return Err(Error("truncated tans stream"));
+    }
+
+    let mut bits = MsbBits::new(src);
+    if bits.read_bit() {
+        return Err(Error("invalid tans stream"));
+    }
+    let l_bits = bits.read(2) + 8;
+    let weights = read_tans_weights(&mut bits, l_bits)?;
+    let lut = tans_lut(&weights, l_bits)?;
+
+    let data = &src[bits.bytes()..];
+    if data.is_empty() {
+        return Err(Error("truncated tans stream"));
+    }
+
+    // Five states are decoded round robin, from a stream at the front and one at the back.
+    let mut forward = LsbBits::new(data);
+    let mut backward = LsbBits::backward(data);
+    let mut states = [0; 5];
+    for (i, state) in states.iter_mut().enumerate() {
+        let bits = if i % 2 == 0 {
+            &mut forward
+        } else {
+            &mut backward
+        };
+        *state = bits.read(l_bits) as usize;
+    }
+
+    let (out, last) = out.split_at_mut(out.len() - 5);
+    let mut out = out.iter_mut();
+    'decode: loop {
+        for bits in [&mut forward, &mut backward] {
+            for state in &mut states {
+                let Some(out) = out.next() else {
+                    break 'decode;
+                };
+                let entry = lut.get(*state).ok_or(Error("invalid tans state"))?;
+                *out = entry.symbol;
+                *state = bits.read(entry.bits.into()) as usize + entry.base as usize;
+            }
+        }
+    }
+
+    if forward.bytes() + backward.bytes() != data.len() {
+        return Err(Error("tans stream size mismatch"));
+    }
+    for (out, &state) in last.iter_mut().zip(&states) {
+        *out = u8::try_from(state).map_err(|_| Error("invalid tans state"))?;
+    }
+    Ok(src.len())
+}
+
+/// Symbols with their weight, the amount of states of a symbol.
+struct TansWeights {
+    /// Symbols with a weight of 1.
+    singles: Vec<u8>,
+    /// Symbols and weights above 1.
+    others: Vec<(u8, u32)>,
+}
+
+fn read_tans_weights(bits: &mut MsbBits, l_bits: u32) -> Result<TansWeights> {
+    let invalid = Error("invalid tans table");
+    let l = 1 << l_bits;
+    let mut weights = TansWeights {
+        singles: Vec::new(),
+        others: Vec::new(),
+    };
+    let mut push = |sym: u8, weight: u32| match weight {
+        1 => weights.singles.push(sym),
+        _ => weights.others.push((sym, weight)),
+    };
+
+    if bits.read_bit() {
+        // Golomb-Rice coded weights of ranges of symbols.
+        let q = bits.read(3);
+        let count = bits.read(8) as usize + 1;
+        if count < 2 {
+            return Err(invalid);
+        }
+        let fluff = read_fluff(bits, count);
+        let rice = read_rice_lengths(bits, count + fluff)?;
+        let ranges = read_ranges(bits, count, &rice[count..])?;
+
+        let mut rice = rice.iter();
+        let mut average = 6i32;
+        let mut sum = 0;
+        for (start, n) in ranges {
+            for sym in start..start + n {
+                let extra = q + *rice.next().ok_or(invalid)? as u32;
+                if extra > 15 {
+                    return Err(invalid);
+                }
+                let mut v = (bits.read(extra) + (1 << extra) - (1 << q)) as i32;
+                let average_div4 = average >> 2;
+                let mut limit = 2 * average_div4;
+                if v <= limit {
+                    v = average_div4 + (-(v & 1) ^ (v >> 1));
+                }
+                limit = limit.min(v);
+                v += 1;
+                average += limit - average_div4;
+                // Symbols with an invalid weight are skipped, the sum does not match anymore.
+                if v >= 1 {
+                    push(sym as u8, v as u32);
+                }
+                sum += v;
+            }
+        }
+        if sum != l as i32 {
+            return Err(invalid);
+        }
+        return Ok(weights);
+    }
+
+    // Sparse table, which lists the symbols and the delta to the previous weight.
+    let mut seen = [false; 256];
+    let count = bits.read(3) + 1;
+    let delta_bits = bits.read(32 - l_bits.leading_zeros());
+    if delta_bits == 0 || delta_bits > l_bits {
+        return Err(invalid);
+    }
+
+    let mut weight = 0;
+    let mut total = 0;
+    for _ in 0..count {
+        let sym = bits.read(8) as usize;
+        if seen[sym] {
+            return Err(invalid);
+        }
+        weight += bits.read(delta_bits);
+        if weight == 0 {
+            return Err(invalid);
+        }
+        seen[sym] = true;
+        push(sym as u8, weight);
+        total += weight;
+    }
+
+    // The last symbol gets all remaining states.
+    let sym = bits.read(8) as usize;
+    if seen[sym] || total >= l || l - total < weight || l - total <= 1 {
+        return Err(invalid);
+    }
+    push(sym as u8, l - total);
+
+    weights.singles.sort_unstable();
+    weights.others.sort_unstable();
+    Ok(weights)
+}
+
+#[derive(Clone, Copy, Default)]
+struct TansEntry {
+    symbol: u8,
+    /// Amount of bits read for the next state.
+    bits: u8,
+    /// Base of the next state.
+    base: u16,
+}
+
+/// Builds the decoding table, the states of every symbol are spread over four quarters.
+fn tans_lut(weights: &TansWeights, l_bits: u32) -> Result<Vec<TansEntry>> {
+    let invalid = Error("invalid tans table");
+    let l = 1usize << l_bits;
+    let mut lut = vec![TansEntry::default(); l];
+
+    let singles = weights.singles.len();
+    let slots = l.checked_sub(singles).ok_or(invalid)?;
+    for (entry, &symbol) in lut[slots..].iter_mut().zip(&weights.singles) {
+        *entry = TansEntry {
+            symbol,
+            bits: l_bits as u8,
+            base: 0,
+        };
+    }
+
+    let quarter = slots / 4;
+    let mut pointers = [0; 4];
+    for i in 1..4 {
+        pointers[i] = pointers[i - 1] + quarter + usize::from(slots % 4 >= i);
+    }
+
+    let mut set = |pointer: &mut usize, entry: TansEntry| {
+        let slot = lut[..slots].get_mut(*pointer).ok_or(invalid)?;
+        *slot = entry;
+        *pointer += 1;
+        Ok::<_, Error>(())
+    };
+
+    let mut weights_sum = 0usize;
+    for &(symbol, weight) in &weights.others {
+        let weight = weight as usize;
+        if weight > 4 {
+            let sym_bits = weight.ilog2();
+            let mut z = l_bits - sym_bits;
+            let mut entry = TansEntry {
+                symbol,
+                bits: z as u8,
+                base: ((l - 1) & (weight << z)) as u16,
+            };
+            let mut add = 1 << z;
+            let mut x = (1 << (sym_bits + 1)) - weight;
+
+            for (j, pointer) in pointers.iter_mut().enumerate() {
+                let y = (weight + (weights_sum.wrapping_sub(j + 1) & 3)) >> 2;
+                if x >= y {
+                    for _ in 0..y {
+                        set(pointer, entry)?;
+                        entry.base = entry.base.wrapping_add(add);
+                    }
+                    x -= y;
+                } else {
+                    for _ in 0..x {
+                        set(pointer, entry)?;
+                        entry.base = entry.base.wrapping_add(add);
+                    }
+                    z -= 1;
+                    add >>= 1;
+                    entry.bits = z as u8;
+                    entry.base = 0;
+                    for _ in x..y {
+                        set(pointer, entry)?;
+                        entry.base = entry.base.wrapping_add(add);
+                    }
+                    x = weight;
+                }
+            }
+        } else {
+            let mut quarters: u32 = ((1 << weight) - 1) << (weights_sum & 3);
+            quarters |= quarters >> 4;
+            for w in weight..2 * weight {
+                let quarter = quarters.trailing_zeros() as usize;
+                quarters &= quarters - 1;
+                let bits = l_bits - w.ilog2();
+                set(
+                    pointers.get_mut(quarter).ok_or(invalid)?,
+                    TansEntry {
+                        symbol,
+                        bits: bits as u8,
+                        base: ((l - 1) & (w << bits)) as u16,
+                    },
+                )?;
+            }
+        }
+        weights_sum += weight;
+    }
+
+    Ok(lut)
+}
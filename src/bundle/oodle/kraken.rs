@@ -0,0 +1,101 @@
+//! Kraken chunks.
+//!
+//! Every command copies literals followed by a match, the offset of the match is either
+//! one of the last three offsets or a new one.
+use super::entropy::decode_bytes;
+use super::lz::{self, Offsets, PackedOffsets, INITIAL_OFFSET};
+use super::{Error, Result};
+
+/// Decodes a chunk into `dst[pos..]`.
+///
+/// In `mode` 0 literals are added to the bytes at the last offset, in `mode` 1 they are copied.
+pub fn decode(mode: u32, src: &[u8], dst: &mut [u8], pos: usize) -> Result<()> {
+    if mode > 1 {
+        return Err(Error("unknown kraken mode"));
+    }
+    if src.len() < 13 {
+        return Err(Error("truncated kraken chunk"));
+    }
+    let len = dst.len() - pos;
+    let (pos, src) = lz::copy_initial_bytes(src, dst, pos)?;
+
+    if src[0] & 0x80 != 0 {
+        return Err(Error("kraken chunks with excess bytes are not supported"));
+    }
+    let (literals, n) = decode_bytes(src, len)?;
+    let src = &src[n..];
+    let (commands, n) = decode_bytes(src, len)?;
+    let src = &src[n..];
+    if src.len() < 3 {
+        return Err(Error("truncated kraken chunk"));
+    }
+
+    let (packed_offsets, src) = PackedOffsets::read(src, commands.len())?;
+    let (packed_lengths, n) = decode_bytes(src, len / 4)?;
+    let offsets = lz::unpack_offsets(&src[n..], &packed_offsets, &packed_lengths)?;
+
+    run(mode == 0, dst, pos, &literals, &commands, offsets)
+}
+
+fn run(
+    delta: bool,
+    dst: &mut [u8],
+    mut pos: usize,
+    mut literals: &[u8],
+    commands: &[u8],
+    offsets: Offsets,
+) -> Result<()> {
+    let mut lengths = offsets.lengths.into_iter();
+    let mut new_offsets = offsets.offsets.into_iter();
+    let mut recent = [INITIAL_OFFSET; 3];
+    let mut last_offset = INITIAL_OFFSET;
+
+    for &cmd in commands {
+        let literal_len = match cmd & 3 {
+            3 => lengths
+                .next()
+                .ok_or(Error("kraken length stream is exhausted"))? as usize,
+            n => n as usize,
+        };
+        let run = lz::take(&mut literals, literal_len)?;
+        lz::copy_literals(dst, pos, run, delta.then_some(last_offset))?;
+        pos += literal_len;
+
+        // Index 3 is a new offset, used offsets move to the front.
+        let index = (cmd >> 6) as usize;
+        let offset = match index {
+            3 => new_offsets
+                .next()
+                .ok_or(Error("kraken offset stream is exhausted"))?,
+            i => recent[i],
+        };
+        recent.copy_within(0..index.min(2), 1);
+        recent[0] = offset;
+        last_offset = offset;
+
+        let len = match (cmd >> 2) & 0xf {
+            15 => {
+                let len = 14
+                    + lengths
+                        .next()
+                        .ok_or(Error("kraken length stream is exhausted"))?;
+                let len = len as usize;
+                if len + 8 > dst.len() - pos {
+                    return Err(Error("kraken match out of bounds"));
+                }
+                len
+            }
+            n => n as usize + 2,
+        };
+        lz::copy_match(dst, pos, offset, len)?;
+        pos += len;
+    }
+
+    if new_offsets.len() != 0 || lengths.len() != 0 {
+        return Err(Error("kraken chunk has unused offsets"));
+    }
+    if dst.len() - pos != literals.len() {
+        return Err(Error("kraken literals size mismatch"));
+    }
+    lz::copy_literals(dst, pos, literals, delta.then_some(last_offset))
+}
@@ -0,0 +1,234 @@
+//! Leviathan chunks.
+//!
+//! Like Kraken, but with seven recent offsets and literals which may be split into several
+//! streams, selected by the position or by the previous byte.
+use super::entropy::{decode_bytes, decode_multi_array};
+use super::lz::{self, PackedOffsets, INITIAL_OFFSET};
+use super::{Error, Result};
+
+/// How literals are coded, selected by the chunk mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Literals {
+    /// Added to the bytes at the last offset.
+    Sub,
+    Raw,
+    /// Like `Sub`, but the first literal after a match comes from its own stream.
+    LamSub,
+    /// Like `Sub`, with a stream for every position modulo 4.
+    SubAnd3,
+    /// Copied from a stream selected by the high bits of the previous byte.
+    O1,
+    /// Like `Sub`, with a stream for every position modulo 16.
+    SubAndF,
+}
+
+/// Decodes a chunk into `dst[pos..]`.
+pub fn decode(mode: u32, src: &[u8], dst: &mut [u8], pos: usize) -> Result<()> {
+    let (literal_mode, literal_streams) = match mode {
+        0 => (Literals::Sub, 1),
+        1 => (Literals::Raw, 1),
+        2 => (Literals::LamSub, 2),
+        3 => (Literals::SubAnd3, 4),
+        4 => (Literals::O1, 16),
+        5 => (Literals::SubAndF, 16),
+        _ => return Err(Error("unknown leviathan mode")),
+    };
+    if src.len() < 13 {
+        return Err(Error("truncated leviathan chunk"));
+    }
+    let len = dst.len() - pos;
+    let chunk_start = pos;
+    let (pos, src) = lz::copy_initial_bytes(src, dst, pos)?;
+
+    let (packed_offsets, src) = PackedOffsets::read(src, len / 3)?;
+    let (packed_lengths, n) = decode_bytes(src, len / 5)?;
+    let mut src = &src[n..];
+
+    let literals = if literal_streams == 1 {
+        let (literals, n) = decode_bytes(src, len)?;
+        src = &src[n..];
+        vec![literals]
+    } else {
+        let (literals, n) = decode_multi_array(src, len, literal_streams)?;
+        src = &src[n..];
+        literals
+    };
+
+    let &first = src.first().ok_or(Error("truncated leviathan chunk"))?;
+    let commands = if first & 0x80 == 0 {
+        let (commands, n) = decode_bytes(src, len)?;
+        src = &src[n..];
+        Commands::Single(commands)
+    } else {
+        if first != 0x83 {
+            return Err(Error("invalid leviathan command streams"));
+        }
+        let (streams, n) = decode_multi_array(&src[1..], len, 8)?;
+        src = &src[1 + n..];
+        Commands::Multi(streams)
+    };
+
+    let offsets = lz::unpack_offsets(src, &packed_offsets, &packed_lengths)?;
+
+    let mut chunk = Chunk {
+        literal_mode,
+        chunk_start,
+        match_zone_end: if len >= 16 {
+            dst.len() - 16
+        } else {
+            chunk_start
+        },
+        literals: literals.iter().map(Vec::as_slice).collect(),
+        lengths: &offsets.lengths,
+    };
+    chunk.run(dst, pos, commands, &offsets.offsets)
+}
+
+enum Commands {
+    Single(Vec<u8>),
+    /// A stream for every position modulo 8.
+    Multi(Vec<Vec<u8>>),
+}
+
+struct Chunk<'a> {
+    literal_mode: Literals,
+    chunk_start: usize,
+    /// Long literal runs have to end before the last 16 bytes.
+    match_zone_end: usize,
+    literals: Vec<&'a [u8]>,
+    /// Literal run lengths are taken from the front, match lengths from the back.
+    lengths: &'a [u32],
+}
+
+impl Chunk<'_> {
+    fn run(
+        &mut self,
+        dst: &mut [u8],
+        mut pos: usize,
+        commands: Commands,
+        offsets: &[i32],
+    ) -> Result<()> {
+        let mut new_offsets = offsets.iter();
+        let mut recent = [INITIAL_OFFSET; 7];
+        let mut last_offset = INITIAL_OFFSET;
+
+        let mut streams = match &commands {
+            Commands::Single(commands) => vec![commands.as_slice()],
+            Commands::Multi(streams) => streams.iter().map(Vec::as_slice).collect(),
+        };
+        let total: usize = streams.iter().map(|s| s.len()).sum();
+
+        for _ in 0..total {
+            let stream = match &commands {
+                Commands::Single(_) => &mut streams[0],
+                Commands::Multi(_) => &mut streams[(pos - self.chunk_start) & 7],
+            };
+            let (&cmd, rest) = stream
+                .split_first()
+                .ok_or(Error("leviathan command stream is exhausted"))?;
+            *stream = rest;
+
+            pos = self.copy_literals(dst, pos, cmd, last_offset)?;
+
+            // Index 7 is a new offset, used offsets move to the front.
+            let index = (cmd >> 5) as usize;
+            let offset = match index {
+                7 => *new_offsets
+                    .next()
+                    .ok_or(Error("leviathan offset stream is exhausted"))?,
+                i => recent[i],
+            };
+            recent.copy_within(0..index.min(6), 1);
+            recent[0] = offset;
+            last_offset = offset;
+
+            let len = match (cmd & 7) as usize + 2 {
+                9 => {
+                    let (&len, rest) = self
+                        .lengths
+                        .split_last()
+                        .ok_or(Error("leviathan length stream is exhausted"))?;
+                    self.lengths = rest;
+                    let len = len as usize + 6;
+                    if len > 16 && len + 8 > dst.len() - pos {
+                        return Err(Error("leviathan match out of bounds"));
+                    }
+                    len
+                }
+                len => len,
+            };
+            lz::copy_match(dst, pos, offset, len)?;
+            pos += len;
+        }
+
+        if new_offsets.len() != 0 || !self.lengths.is_empty() {
+            return Err(Error("leviathan chunk has unused offsets"));
+        }
+        let len = dst.len() - pos;
+        if len > 0 {
+            self.copy_run(dst, pos, len, last_offset)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the literals of a command and returns the position after them.
+    fn copy_literals(&mut self, dst: &mut [u8], pos: usize, cmd: u8, offset: i32) -> Result<usize> {
+        let len = match (cmd >> 3) & 3 {
+            0 => return Ok(pos),
+            3 => {
+                let (&len, rest) = self
+                    .lengths
+                    .split_first()
+                    .ok_or(Error("leviathan length stream is exhausted"))?;
+                self.lengths = rest;
+                let len = (len & 0xffffff) as usize;
+                let checked = match self.literal_mode {
+                    Literals::Sub | Literals::Raw => len > 24,
+                    _ => true,
+                };
+                if checked && len > self.match_zone_end.saturating_sub(pos) {
+                    return Err(Error("leviathan literals out of bounds"));
+                }
+                len
+            }
+            len => len as usize,
+        };
+        self.copy_run(dst, pos, len, offset)?;
+        Ok(pos + len)
+    }
+
+    /// Copies a run of `len` literals.
+    fn copy_run(&mut self, dst: &mut [u8], pos: usize, len: usize, offset: i32) -> Result<()> {
+        if dst.len() - pos < len {
+            return Err(Error("leviathan literals out of bounds"));
+        }
+        match self.literal_mode {
+            Literals::Sub | Literals::Raw => {
+                let literals = lz::take(&mut self.literals[0], len)?;
+                let delta = self.literal_mode == Literals::Sub;
+                lz::copy_literals(dst, pos, literals, delta.then_some(offset))
+            }
+            Literals::LamSub => {
+                let first = lz::take(&mut self.literals[1], 1)?;
+                lz::copy_literals(dst, pos, first, Some(offset))?;
+                let rest = lz::take(&mut self.literals[0], len - 1)?;
+                lz::copy_literals(dst, pos + 1, rest, Some(offset))
+            }
+            Literals::SubAnd3 | Literals::SubAndF => {
+                let mask = self.literals.len() - 1;
+                for p in pos..pos + len {
+                    let stream = &mut self.literals[(p - self.chunk_start) & mask];
+                    lz::copy_literals(dst, p, lz::take(stream, 1)?, Some(offset))?;
+                }
+                Ok(())
+            }
+            Literals::O1 => {
+                for p in pos..pos + len {
+                    let stream = &mut self.literals[(dst[p - 1] >> 4) as usize];
+                    dst[p] = lz::take(stream, 1)?[0];
+                }
+                Ok(())
+            }
+        }
+    }
+}
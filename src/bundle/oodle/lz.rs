@@ -0,0 +1,279 @@
+//! Parts of the LZ decoding which are shared between the codecs.
+use super::bits::MsbBits;
+use super::entropy::decode_bytes;
+use super::{kraken, leviathan, mermaid, Error, Result};
+
+const CHUNK_SIZE: usize = 0x20000;
+
+/// Offset of the first match without any previous one.
+pub const INITIAL_OFFSET: i32 = -8;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Codec {
+    Kraken,
+    Mermaid,
+    Leviathan,
+}
+
+/// Decodes a quantum into `dst[pos..]` and returns the amount of consumed bytes.
+///
+/// Quanta are split into chunks of 128 KiB, which are either only entropy coded,
+/// stored or compressed by the codec. Matches may reference everything before `pos`.
+pub fn decode_quantum(codec: Codec, src: &[u8], dst: &mut [u8], mut pos: usize) -> Result<usize> {
+    let mut s = 0;
+    while pos < dst.len() {
+        let len = (dst.len() - pos).min(CHUNK_SIZE);
+        let &[a, b, c, _, ..] = &src[s..] else {
+            return Err(Error("truncated chunk header"));
+        };
+        let header = u32::from_be_bytes([0, a, b, c]);
+
+        if header & 0x800000 == 0 {
+            let (decoded, n) = decode_bytes(&src[s..], len)?;
+            if decoded.len() != len {
+                return Err(Error("chunk size mismatch"));
+            }
+            dst[pos..pos + len].copy_from_slice(&decoded);
+            s += n;
+            pos += len;
+            continue;
+        }
+
+        s += 3;
+        let size = (header & 0x7ffff) as usize;
+        let mode = (header >> 19) & 0xf;
+        let chunk = src.get(s..s + size).ok_or(Error("truncated chunk"))?;
+        let out = &mut dst[..pos + len];
+
+        if size < len {
+            match codec {
+                Codec::Kraken => kraken::decode(mode, chunk, out, pos)?,
+                Codec::Mermaid => mermaid::decode(mode, chunk, out, pos)?,
+                Codec::Leviathan => leviathan::decode(mode, chunk, out, pos)?,
+            }
+        } else if size > len || mode != 0 {
+            return Err(Error("invalid chunk header"));
+        } else {
+            out[pos..].copy_from_slice(chunk);
+        }
+
+        s += size;
+        pos += len;
+    }
+    Ok(s)
+}
+
+/// Copies the 8 bytes which start the very first chunk, they are never compressed.
+///
+/// Returns the position after the copied bytes and the remaining source.
+pub fn copy_initial_bytes<'a>(
+    src: &'a [u8],
+    dst: &mut [u8],
+    pos: usize,
+) -> Result<(usize, &'a [u8])> {
+    if pos != 0 {
+        return Ok((pos, src));
+    }
+    let (initial, src) = src.split_at_checked(8).ok_or(Error("truncated chunk"))?;
+    dst.get_mut(..8)
+        .ok_or(Error("chunk is too small"))?
+        .copy_from_slice(initial);
+    Ok((8, src))
+}
+
+/// Copies a match of `len` bytes from `offset` bytes before `pos`, the copy may overlap itself.
+pub fn copy_match(dst: &mut [u8], pos: usize, offset: i32, len: usize) -> Result<()> {
+    let from = pos
+        .checked_add_signed(offset as isize)
+        .filter(|&from| from < pos)
+        .ok_or(Error("match offset out of bounds"))?;
+    if dst.len() - pos < len {
+        return Err(Error("match length out of bounds"));
+    }
+    for i in 0..len {
+        dst[pos + i] = dst[from + i];
+    }
+    Ok(())
+}
+
+/// Copies literals to `pos`, with `delta` they are added to the bytes at the last offset.
+pub fn copy_literals(
+    dst: &mut [u8],
+    pos: usize,
+    literals: &[u8],
+    delta: Option<i32>,
+) -> Result<()> {
+    if dst.len() - pos < literals.len() {
+        return Err(Error("literals out of bounds"));
+    }
+    match delta {
+        None => dst[pos..pos + literals.len()].copy_from_slice(literals),
+        Some(offset) => {
+            let from = pos
+                .checked_add_signed(offset as isize)
+                .filter(|&from| from <= pos)
+                .ok_or(Error("literal offset out of bounds"))?;
+            for (i, &literal) in literals.iter().enumerate() {
+                dst[pos + i] = literal.wrapping_add(dst[from + i]);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Takes the next `n` bytes from a stream.
+pub fn take<'a>(stream: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    let Some((taken, rest)) = stream.split_at_checked(n) else {
+        return Err(Error("stream is exhausted"));
+    };
+    *stream = rest;
+    Ok(taken)
+}
+
+/// Offsets and lengths of matches and long literal runs.
+pub struct Offsets {
+    /// Negative offsets of new matches.
+    pub offsets: Vec<i32>,
+    pub lengths: Vec<u32>,
+}
+
+/// Offsets of new matches before they are unpacked.
+pub struct PackedOffsets {
+    packed: Vec<u8>,
+    /// Offsets are either coded by themselves or are scaled and combined with low bits.
+    scaling: Option<(i32, Vec<u8>)>,
+}
+
+impl PackedOffsets {
+    /// Reads the packed offset streams and returns the remaining source,
+    /// which contains the length stream.
+    pub fn read(src: &[u8], capacity: usize) -> Result<(Self, &[u8])> {
+        let &first = src.first().ok_or(Error("truncated offsets"))?;
+        if first & 0x80 == 0 {
+            let (packed, n) = decode_bytes(src, capacity)?;
+            let offsets = Self {
+                packed,
+                scaling: None,
+            };
+            return Ok((offsets, &src[n..]));
+        }
+
+        let scale = first as i32 - 127;
+        let src = &src[1..];
+        let (packed, n) = decode_bytes(src, capacity)?;
+        let mut src = &src[n..];
+        let mut low_bits = Vec::new();
+        if scale != 1 {
+            let n;
+            (low_bits, n) = decode_bytes(src, capacity)?;
+            if low_bits.len() != packed.len() {
+                return Err(Error("offset stream size mismatch"));
+            }
+            src = &src[n..];
+        }
+
+        let offsets = Self {
+            packed,
+            scaling: Some((scale, low_bits)),
+        };
+        Ok((offsets, src))
+    }
+
+    pub fn len(&self) -> usize {
+        self.packed.len()
+    }
+}
+
+/// Unpacks the offsets of new matches and the lengths of long matches and literal runs,
+/// the extra bits of both are read from a stream at the front and one at the back of `src`.
+pub fn unpack_offsets(
+    src: &[u8],
+    packed_offsets: &PackedOffsets,
+    packed_lengths: &[u8],
+) -> Result<Offsets> {
+    let invalid = Error("invalid offsets");
+    let mut streams = [MsbBits::new(src), MsbBits::backward(src)];
+
+    let [_, back] = &mut streams;
+    if back.peek() < 0x2000 {
+        return Err(invalid);
+    }
+    let n = back.leading_zeros();
+    back.skip(n);
+    let long_lengths = back.read(n + 1) as usize - 1;
+
+    let mut offsets = Vec::with_capacity(packed_offsets.len());
+    match &packed_offsets.scaling {
+        None => {
+            for (i, &packed) in packed_offsets.packed.iter().enumerate() {
+                offsets.push(-(read_distance(&mut streams[i % 2], packed.into()) as i32));
+            }
+        }
+        Some((scale, low_bits)) => {
+            for (i, &packed) in packed_offsets.packed.iter().enumerate() {
+                let bits = (packed >> 3) as u32;
+                if bits > 26 {
+                    return Err(invalid);
+                }
+                let offset = ((8 + (packed & 7) as u32) << bits) | streams[i % 2].read(bits);
+                offsets.push(8 - offset as i32);
+            }
+            if *scale != 1 {
+                for (offset, &low) in offsets.iter_mut().zip(low_bits) {
+                    *offset = scale.wrapping_mul(*offset).wrapping_sub(low.into());
+                }
+            }
+        }
+    }
+
+    // A chunk is at most 128 KiB and long lengths are at least 255 bytes.
+    if long_lengths > 512 {
+        return Err(invalid);
+    }
+    let long_lengths = (0..long_lengths)
+        .map(|i| read_length(&mut streams[i % 2]).ok_or(invalid))
+        .collect::<Result<Vec<_>>>()?;
+
+    let [front, back] = &streams;
+    if front.bytes() + back.bytes() != src.len() {
+        return Err(Error("offset stream size mismatch"));
+    }
+
+    let mut long_lengths = long_lengths.into_iter();
+    let lengths = packed_lengths
+        .iter()
+        .map(|&v| match v {
+            255 => long_lengths.next().map(|v| v + 255 + 3).ok_or(invalid),
+            v => Ok(v as u32 + 3),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if long_lengths.len() != 0 {
+        return Err(invalid);
+    }
+
+    Ok(Offsets { offsets, lengths })
+}
+
+/// Reads the distance of a match, `v` contains the amount of extra bits and the low bits.
+fn read_distance(bits: &mut MsbBits, v: u32) -> u32 {
+    if v < 0xf0 {
+        let n = (v >> 4) + 4;
+        (((1 << n) | bits.read(n)) << 4)
+            .wrapping_add(v & 0xf)
+            .wrapping_sub(248)
+    } else {
+        let n = v - 0xf0 + 4;
+        let high = ((1 << n) | bits.read(n)) << 12;
+        8322816u32.wrapping_add(high).wrapping_add(bits.read(12))
+    }
+}
+
+/// Reads a gamma coded length with at most 12 leading zeros.
+fn read_length(bits: &mut MsbBits) -> Option<u32> {
+    let n = bits.leading_zeros();
+    if n > 12 {
+        return None;
+    }
+    bits.skip(n);
+    Some(bits.read(n + 7) - 64)
+}
@@ -0,0 +1,226 @@
+//! Mermaid and Selkie chunks.
+//!
+//! A chunk is processed in two halves of 64 KiB, each with its own command stream and far
+//! offsets. Near offsets are 16 bit and short commands reuse the most recent offset.
+use super::entropy::decode_bytes;
+use super::lz::{self, INITIAL_OFFSET};
+use super::{Error, Result};
+
+const HALF_SIZE: usize = 0x10000;
+
+/// Decodes a chunk into `dst[pos..]`.
+///
+/// In `mode` 0 literals are added to the bytes at the last offset, in `mode` 1 they are copied.
+pub fn decode(mode: u32, src: &[u8], dst: &mut [u8], pos: usize) -> Result<()> {
+    if mode > 1 {
+        return Err(Error("unknown mermaid mode"));
+    }
+    if src.len() < 10 {
+        return Err(Error("truncated mermaid chunk"));
+    }
+    let len = dst.len() - pos;
+    let chunk_start = pos;
+    let (_, mut src) = lz::copy_initial_bytes(src, dst, pos)?;
+
+    let (literals, n) = decode_bytes(src, len)?;
+    src = &src[n..];
+    let (commands, n) = decode_bytes(src, len)?;
+    src = &src[n..];
+
+    let split = if len <= HALF_SIZE {
+        commands.len()
+    } else {
+        read_u16(&mut src)? as usize
+    };
+    let (first, second) = commands
+        .split_at_checked(split)
+        .ok_or(Error("invalid mermaid command split"))?;
+
+    let near_offsets = match read_u16(&mut src)? {
+        0xffff => {
+            let (high, n) = decode_bytes(src, len / 2)?;
+            src = &src[n..];
+            let (low, n) = decode_bytes(src, len / 2)?;
+            src = &src[n..];
+            if low.len() != high.len() {
+                return Err(Error("mermaid offset stream size mismatch"));
+            }
+            low.iter()
+                .zip(&high)
+                .map(|(&low, &high)| u16::from_le_bytes([low, high]))
+                .collect()
+        }
+        count => lz::take(&mut src, count as usize * 2)?
+            .chunks_exact(2)
+            .map(|v| u16::from_le_bytes([v[0], v[1]]))
+            .collect::<Vec<_>>(),
+    };
+
+    let sizes = lz::take(&mut src, 3)?;
+    let sizes = u32::from_le_bytes([sizes[0], sizes[1], sizes[2], 0]);
+    let (far_first, far_second) = if sizes == 0 {
+        (Vec::new(), Vec::new())
+    } else {
+        let mut first = (sizes >> 12) as usize;
+        let mut second = (sizes & 0xfff) as usize;
+        if first == 0xfff {
+            first = read_u16(&mut src)? as usize;
+        }
+        if second == 0xfff {
+            second = read_u16(&mut src)? as usize;
+        }
+        (
+            read_far_offsets(&mut src, first, chunk_start)?,
+            read_far_offsets(&mut src, second, chunk_start + HALF_SIZE)?,
+        )
+    };
+
+    let mut streams = Streams {
+        literals: &literals,
+        near_offsets: &near_offsets,
+        lengths: src,
+    };
+    let mut recent = INITIAL_OFFSET;
+    let mut begin = chunk_start;
+    for (commands, far_offsets) in [(first, far_first), (second, far_second)] {
+        let end = (begin + HALF_SIZE).min(dst.len());
+        let half = Half {
+            delta: mode == 0,
+            begin,
+            commands,
+            far_offsets: &far_offsets,
+        };
+        half.run(&mut dst[..end], &mut streams, &mut recent)?;
+        begin = end;
+        if begin == dst.len() {
+            break;
+        }
+    }
+
+    if !streams.lengths.is_empty() {
+        return Err(Error("mermaid length stream size mismatch"));
+    }
+    Ok(())
+}
+
+/// Streams which continue from the first half into the second.
+struct Streams<'a> {
+    literals: &'a [u8],
+    near_offsets: &'a [u16],
+    lengths: &'a [u8],
+}
+
+impl Streams<'_> {
+    fn near_offset(&mut self) -> Result<i32> {
+        let (&offset, rest) = self
+            .near_offsets
+            .split_first()
+            .ok_or(Error("mermaid offset stream is exhausted"))?;
+        self.near_offsets = rest;
+        Ok(-(offset as i32))
+    }
+
+    /// Reads a length, which is either a byte or for larger values three bytes.
+    fn length(&mut self) -> Result<usize> {
+        let exhausted = Error("mermaid length stream is exhausted");
+        let &first = self.lengths.first().ok_or(exhausted)?;
+        if first <= 251 {
+            self.lengths = &self.lengths[1..];
+            return Ok(first.into());
+        }
+        let v = lz::take(&mut self.lengths, 3).map_err(|_| exhausted)?;
+        Ok(first as usize + u16::from_le_bytes([v[1], v[2]]) as usize * 4)
+    }
+}
+
+struct Half<'a> {
+    delta: bool,
+    /// Position of the half, far offsets are relative to it.
+    begin: usize,
+    commands: &'a [u8],
+    far_offsets: &'a [u32],
+}
+
+impl Half<'_> {
+    /// Decodes the half, `dst` ends at its end.
+    fn run(&self, dst: &mut [u8], streams: &mut Streams, recent: &mut i32) -> Result<()> {
+        let mut far_offsets = self.far_offsets.iter();
+        let mut far_offset = |pos: usize| -> Result<i32> {
+            let &offset = far_offsets
+                .next()
+                .ok_or(Error("mermaid far offset stream is exhausted"))?;
+            let from = self.begin - offset as usize;
+            Ok((from as isize - pos as isize) as i32)
+        };
+        // The first 8 bytes of the data are stored in front of the chunk.
+        let mut pos = self.begin.max(8);
+
+        for &cmd in self.commands {
+            let (offset, len) = match cmd {
+                24.. => {
+                    let literal_len = (cmd & 7) as usize;
+                    self.copy_literals(dst, pos, streams, literal_len, *recent)?;
+                    pos += literal_len;
+                    if cmd < 128 {
+                        *recent = streams.near_offset()?;
+                    }
+                    (*recent, ((cmd >> 3) & 0xf) as usize)
+                }
+                3.. => (far_offset(pos)?, cmd as usize + 5),
+                0 => {
+                    let literal_len = streams.length()? + 64;
+                    self.copy_literals(dst, pos, streams, literal_len, *recent)?;
+                    pos += literal_len;
+                    continue;
+                }
+                1 => {
+                    let len = streams.length()? + 91;
+                    (streams.near_offset()?, len)
+                }
+                2 => {
+                    let len = streams.length()? + 29;
+                    (far_offset(pos)?, len)
+                }
+            };
+            *recent = offset;
+            lz::copy_match(dst, pos, offset, len)?;
+            pos += len;
+        }
+
+        self.copy_literals(dst, pos, streams, dst.len() - pos, *recent)
+    }
+
+    fn copy_literals(
+        &self,
+        dst: &mut [u8],
+        pos: usize,
+        streams: &mut Streams,
+        len: usize,
+        recent: i32,
+    ) -> Result<()> {
+        let literals = lz::take(&mut streams.literals, len)?;
+        lz::copy_literals(dst, pos, literals, self.delta.then_some(recent))
+    }
+}
+
+fn read_u16(src: &mut &[u8]) -> Result<u16> {
+    let v = lz::take(src, 2)?;
+    Ok(u16::from_le_bytes([v[0], v[1]]))
+}
+
+/// Reads `count` offsets relative to `pos`, beyond 12 MiB they take an additional byte.
+fn read_far_offsets(src: &mut &[u8], count: usize, pos: usize) -> Result<Vec<u32>> {
+    (0..count)
+        .map(|_| {
+            let v = lz::take(src, 3)?;
+            let mut offset = u32::from_le_bytes([v[0], v[1], v[2], 0]);
+            if pos >= 0xbfffff && offset >= 0xc00000 {
+                offset += (lz::take(src, 1)?[0] as u32) << 22;
+            }
+            if offset as usize > pos {
+                return Err(Error("mermaid far offset out of bounds"));
+            }
+            Ok(offset)
+        })
+        .collect()
+}
@@ -0,0 +1,291 @@
+//! Decoder for Oodle compressed chunks without the C++ library.
+//!
+//! This is a port of the decoder in ooz, which `libooz-sys` builds, limited to the codecs
+//! used by game bundles: Kraken, Mermaid (and Selkie, which shares its format) and Leviathan.
+//!
+//! Compressed data consists of blocks of 256 KiB, every block starts with a two byte header
+//! which selects the codec. A block is either stored or compressed as a single quantum,
+//! see [`lz::decode_quantum`].
+mod bits;
+mod entropy;
+mod kraken;
+mod leviathan;
+mod lz;
+mod mermaid;
+
+use lz::Codec;
+
+const BLOCK_SIZE: usize = 0x40000;
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Invalid Oodle stream: {0}")]
+pub struct Error(&'static str);
+
+type Result<T> = std::result::Result<T, Error>;
+
+struct BlockHeader {
+    codec: Codec,
+    uncompressed: bool,
+    checksums: bool,
+}
+
+impl BlockHeader {
+    fn parse(src: &[u8]) -> Result<Self> {
+        let &[flags, byte, ..] = src else {
+            return Err(Error("truncated block header"));
+        };
+        if flags & 0xf != 0xc || (flags >> 4) & 3 != 0 {
+            return Err(Error("invalid block header"));
+        }
+
+        let codec = match byte & 0x7f {
+            6 => Codec::Kraken,
+            10 => Codec::Mermaid,
+            12 => Codec::Leviathan,
+            // LZNA and BitKnit, which are not used by game bundles.
+            5 | 11 => return Err(Error("unsupported codec")),
+            _ => return Err(Error("unknown codec")),
+        };
+
+        Ok(Self {
+            codec,
+            uncompressed: flags & 0x40 != 0,
+            checksums: byte & 0x80 != 0,
+        })
+    }
+}
+
+/// Decompresses `src` into `dst` and returns the amount of decompressed bytes.
+///
+/// Like the C++ decoder, exactly `dst.len()` bytes are decompressed
+/// and all of `src` has to be consumed.
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let mut header = None;
+    let mut s = 0;
+    let mut pos = 0;
+
+    while pos < dst.len() {
+        if pos % BLOCK_SIZE == 0 {
+            header = Some(BlockHeader::parse(&src[s..])?);
+            s += 2;
+        }
+        let Some(header) = &header else {
+            unreachable!("blocks start with a header");
+        };
+
+        let len = (dst.len() - pos).min(BLOCK_SIZE);
+        let out = &mut dst[..pos + len];
+        s += if header.uncompressed {
+            let stored = src.get(s..s + len).ok_or(Error("truncated block"))?;
+            out[pos..].copy_from_slice(stored);
+            len
+        } else {
+            decode_block(header, &src[s..], out, pos)?
+        };
+        pos += len;
+    }
+
+    if s != src.len() {
+        return Err(Error("trailing data"));
+    }
+    Ok(pos)
+}
+
+/// Decodes a compressed block into `dst[pos..]` and returns the amount of consumed bytes.
+fn decode_block(header: &BlockHeader, src: &[u8], dst: &mut [u8], pos: usize) -> Result<usize> {
+    let &[a, b, c, ..] = src else {
+        return Err(Error("truncated quantum header"));
+    };
+    let v = u32::from_be_bytes([0, a, b, c]);
+
+    let size = v & 0x3ffff;
+    if size == 0x3ffff {
+        // The whole block is a single repeated byte.
+        if v >> 18 != 1 {
+            return Err(Error("invalid quantum header"));
+        }
+        let &byte = src.get(3).ok_or(Error("truncated quantum header"))?;
+        dst[pos..].fill(byte);
+        return Ok(4);
+    }
+
+    let header_size = if header.checksums { 6 } else { 3 };
+    let size = size as usize + 1;
+    let compressed = src
+        .get(header_size..header_size + size)
+        .ok_or(Error("truncated quantum"))?;
+
+    let len = dst.len() - pos;
+    if size > len {
+        return Err(Error("quantum is larger than its contents"));
+    }
+    if size == len {
+        dst[pos..].copy_from_slice(compressed);
+    } else if lz::decode_quantum(header.codec, compressed, dst, pos)? != size {
+        return Err(Error("quantum size mismatch"));
+    }
+
+    Ok(header_size + size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libooz_sys::Compressor;
+
+    /// Deterministic pseudo random bytes.
+    fn noise(seed: u32, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn samples() -> Vec<Vec<u8>> {
+        let text = (0..20_000)
+            .map(|i| format!("Metadata/Items/Weapon{}.it {} ", i % 97, i * 31 % 1000))
+            .collect::<String>()
+            .into_bytes();
+        let runs = (0..300_000).map(|i| (i / 1000) as u8).collect();
+        let noisy_text = text
+            .iter()
+            .zip(noise(1, text.len()))
+            .map(|(&t, n)| if n < 8 { n } else { t })
+            .collect();
+        let mut mixed = noise(2, 70_000);
+        mixed.extend(&text[..100_000]);
+        mixed.extend(vec![0; 50_000]);
+        mixed.extend(noise(3, 30_000));
+
+        vec![
+            b"0123456789abcdef0123456789abcdef".repeat(3),
+            text,
+            runs,
+            noisy_text,
+            mixed,
+            noise(4, 100_000),
+        ]
+    }
+
+    fn compress(compressor: Compressor, level: i32, data: &[u8]) -> Vec<u8> {
+        let mut compressed = vec![0; libooz_sys::compress_bound(data.len())];
+        let n = libooz_sys::compress(compressor, level, data, &mut compressed);
+        assert!(n > 0, "compression failed with {n}");
+        compressed.truncate(n as usize);
+        compressed
+    }
+
+    #[test]
+    fn matches_libooz() {
+        let compressors = [
+            Compressor::Kraken,
+            Compressor::Mermaid,
+            Compressor::Leviathan,
+        ];
+        for data in samples() {
+            for compressor in compressors {
+                for level in [1, 4, 8] {
+                    let compressed = compress(compressor, level, &data);
+
+                    let mut expected = vec![0; data.len()];
+                    let n = libooz_sys::decompress(&compressed, &mut expected);
+                    assert_eq!(n, data.len() as i32);
+
+                    let mut actual = vec![0; data.len()];
+                    let n = decompress(&compressed, &mut actual).unwrap_or_else(|err| {
+                        panic!(
+                            "{compressor:?} level {level} of {} bytes: {err}",
+                            data.len()
+                        )
+                    });
+                    assert_eq!(n, data.len());
+                    assert!(actual == expected, "{compressor:?} level {level} differs");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_corrupted_chunks_like_libooz() {
+        let data = &samples()[1];
+        for compressor in [
+            Compressor::Kraken,
+            Compressor::Mermaid,
+            Compressor::Leviathan,
+        ] {
+            let compressed = compress(compressor, 4, data);
+            let mut out = vec![0; data.len()];
+
+            assert!(decompress(&compressed[..compressed.len() - 1], &mut out).is_err());
+            assert!(libooz_sys::decompress(&compressed[..compressed.len() - 1], &mut out) < 0);
+
+            // Corrupted chunks have to fail or decode to something, but never panic.
+            for (i, flip) in noise(5, 200).into_iter().enumerate() {
+                let mut corrupted = compressed.clone();
+                corrupted[(i * 7919) % compressed.len()] ^= flip | 1;
+                let _ = decompress(&corrupted, &mut out);
+            }
+        }
+    }
+
+    #[test]
+    fn uncompressed_blocks() {
+        let data = noise(6, BLOCK_SIZE + 100);
+        let mut src = vec![0x4c, 0x06];
+        src.extend(&data[..BLOCK_SIZE]);
+        src.extend([0x4c, 0x06]);
+        src.extend(&data[BLOCK_SIZE..]);
+
+        let mut out = vec![0; data.len()];
+        assert_eq!(decompress(&src, &mut out).unwrap(), data.len());
+        assert_eq!(out, data);
+
+        src.push(0);
+        assert!(decompress(&src, &mut out).is_err());
+    }
+
+    #[test]
+    fn repeated_byte_and_stored_quanta() {
+        let mut out = vec![0; 100];
+        decompress(&[0x0c, 0x0c, 0x07, 0xff, 0xff, 0xab], &mut out).unwrap();
+        assert!(out.iter().all(|&b| b == 0xab));
+
+        let data = noise(7, 100);
+        let mut src = vec![0x0c, 0x0a, 0x00, 0x00, 99];
+        src.extend(&data);
+        decompress(&src, &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn rejects_unsupported_headers() {
+        let mut out = vec![0; 16];
+        for header in [
+            [0x0c, 0x05],
+            [0x0c, 0x0b],
+            [0x0c, 0x01],
+            [0x0d, 0x06],
+            [0x3c, 0x06],
+        ] {
+            assert!(decompress(&header, &mut out).is_err());
+        }
+        assert!(decompress(&[], &mut out).is_err());
+    }
+
+    #[test]
+    fn garbage_does_not_panic() {
+        for seed in 0..2000 {
+            let mut src = noise(seed, 20 + seed as usize % 300);
+            src[0] = 0x0c;
+            src[1] = [0x06, 0x0a, 0x0c][seed as usize % 3];
+            // Mostly small quanta with a compressed chunk.
+            src[2] = 0;
+            src[5] |= 0x80;
+            let mut out = vec![0; 1 + seed as usize % 1000];
+            let _ = decompress(&src, &mut out);
+        }
+    }
+}
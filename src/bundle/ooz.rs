@@ -1,11 +1,46 @@
 use std::io::Read;
+use std::mem::MaybeUninit;
 
 #[derive(Debug, thiserror::Error)]
 pub enum DecompressionError {
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error("Failed to decompress with error {0}")]
+    #[cfg_attr(feature = "pure-rust", allow(dead_code))]
     Ooz(i32),
+    #[cfg(feature = "pure-rust")]
+    #[error(transparent)]
+    Oodle(#[from] super::oodle::Error),
+    #[error("Chunk @ {offset} decompressed to {actual} bytes instead of {expected}")]
+    Size {
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+/// Decompresses a single chunk and returns the decompressed size.
+#[cfg(not(feature = "pure-rust"))]
+fn decompress_chunk(
+    chunk: &[u8],
+    out: &mut [MaybeUninit<u8>],
+) -> Result<usize, DecompressionError> {
+    match libooz_sys::decompress_uninit(chunk, out) {
+        n if n < 0 => Err(DecompressionError::Ooz(n)),
+        n => Ok(n as usize),
+    }
+}
+
+/// Decompresses a single chunk with the pure-Rust decoder and returns the decompressed size.
+#[cfg(feature = "pure-rust")]
+fn decompress_chunk(
+    chunk: &[u8],
+    out: &mut [MaybeUninit<u8>],
+) -> Result<usize, DecompressionError> {
+    out.fill(MaybeUninit::new(0));
+    // SAFETY: every byte was just initialized and `MaybeUninit<u8>` has the layout of `u8`.
+    let out = unsafe { &mut *(out as *mut [MaybeUninit<u8>] as *mut [u8]) };
+    Ok(super::oodle::decompress(chunk, out)?)
 }
 
 /// Decompresses a subset of chunks from an already advanced reader.
@@ -22,36 +57,87 @@ pub fn decompress(
     chunk_sizes: &[u32],
     chunk_start: usize,
     uncompressed_size: usize,
+) -> Result<Vec<u8>, DecompressionError> {
+    let mut compressed = vec![0; chunk_sizes.iter().map(|&s| s as usize).sum()];
+    reader.read_exact(&mut compressed)?;
+
+    decompress_slice(
+        &compressed,
+        chunk_unpacked_size,
+        chunk_sizes,
+        chunk_start,
+        uncompressed_size,
+    )
+}
+
+/// Decompresses a subset of chunks which are already in memory, see [`decompress`].
+///
+/// The chunks are decompressed straight from `compressed` without copying them first.
+/// With the `parallel` feature enabled, multiple chunks are decompressed in parallel.
+pub fn decompress_slice(
+    compressed: &[u8],
+    chunk_unpacked_size: usize,
+    chunk_sizes: &[u32],
+    chunk_start: usize,
+    uncompressed_size: usize,
+) -> Result<Vec<u8>, DecompressionError> {
+    let chunks = split_chunks(compressed, chunk_sizes)?;
+
+    #[cfg(feature = "parallel")]
+    if chunks.len() > 1 {
+        return decompress_parallel(&chunks, chunk_unpacked_size, chunk_start, uncompressed_size);
+    }
+
+    decompress_sequential(&chunks, chunk_unpacked_size, chunk_start, uncompressed_size)
+}
+
+/// Splits consecutive compressed chunks into the individual chunks.
+pub fn split_chunks<'a>(
+    mut compressed: &'a [u8],
+    chunk_sizes: &[u32],
+) -> Result<Vec<&'a [u8]>, DecompressionError> {
+    let mut chunks = Vec::with_capacity(chunk_sizes.len());
+    for &chunk_size in chunk_sizes {
+        let Some((chunk, rest)) = compressed.split_at_checked(chunk_size as usize) else {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        };
+        chunks.push(chunk);
+        compressed = rest;
+    }
+    Ok(chunks)
+}
+
+/// Decompresses chunks one after another, see [`decompress_slice`].
+pub fn decompress_sequential(
+    chunks: &[&[u8]],
+    chunk_unpacked_size: usize,
+    chunk_start: usize,
+    uncompressed_size: usize,
 ) -> Result<Vec<u8>, DecompressionError> {
     let uncompressed_offset = chunk_start * chunk_unpacked_size;
 
     // +64 because there is a rumour that libooz can write past the buffer ...
     // and I am afraid, I am petrified.
-    let mut content = Vec::with_capacity(chunk_unpacked_size * chunk_sizes.len() + 64);
+    let mut content = Vec::with_capacity(chunk_unpacked_size * chunks.len() + 64);
     let content_uninit = content.spare_capacity_mut();
 
-    let mut buffer = Vec::new();
-
     let mut current_size = 0usize;
-    for &chunk_size in chunk_sizes {
-        let chunk_size = chunk_size as usize;
-
+    for chunk in chunks {
         // Last chunk uncompressed size might be smaller than chunk_unpacked_size.
         let this_chunk_unpacked_size =
             chunk_unpacked_size.min(uncompressed_size - uncompressed_offset - current_size);
 
-        buffer.resize(chunk_size, 0);
-        reader.read_exact(&mut buffer)?;
-
-        let n = libooz_sys::decompress_uninit(
-            &buffer,
+        let n = decompress_chunk(
+            chunk,
             &mut content_uninit[current_size..current_size + this_chunk_unpacked_size],
-        );
-
-        if n < 0 {
-            return Err(DecompressionError::Ooz(n));
+        )?;
+        if n != this_chunk_unpacked_size {
+            return Err(DecompressionError::Size {
+                offset: uncompressed_offset + current_size,
+                expected: this_chunk_unpacked_size,
+                actual: n,
+            });
         }
-        debug_assert_eq!(n as usize, this_chunk_unpacked_size);
 
         current_size += this_chunk_unpacked_size;
     }
@@ -59,9 +145,152 @@ pub fn decompress(
     // SAFETY: current_size can't be bigger than the capacity,
     // we only slice over uninit, if it would exceed the capacity it would have already paniced.
     unsafe {
-        debug_assert!(chunk_unpacked_size * chunk_sizes.len() >= current_size);
+        debug_assert!(chunk_unpacked_size * chunks.len() >= current_size);
         content.set_len(current_size);
     };
 
     Ok(content)
 }
+
+/// Decompresses chunks on the rayon thread pool, see [`decompress_slice`].
+#[cfg(feature = "parallel")]
+pub fn decompress_parallel(
+    chunks: &[&[u8]],
+    chunk_unpacked_size: usize,
+    chunk_start: usize,
+    uncompressed_size: usize,
+) -> Result<Vec<u8>, DecompressionError> {
+    use rayon::prelude::*;
+
+    let uncompressed_offset = chunk_start * chunk_unpacked_size;
+    // Last chunk uncompressed size might be smaller than chunk_unpacked_size.
+    let unpacked_size =
+        (chunk_unpacked_size * chunks.len()).min(uncompressed_size - uncompressed_offset);
+
+    let mut content = vec![0; unpacked_size];
+    content
+        .par_chunks_mut(chunk_unpacked_size)
+        .zip(chunks)
+        .enumerate()
+        .try_for_each_init(
+            // libooz may write up to 64 bytes past the output buffer,
+            // which would race with the neighbouring chunk when decompressing in place.
+            || Vec::with_capacity(chunk_unpacked_size + 64),
+            |buffer: &mut Vec<u8>, (i, (out, chunk))| {
+                buffer.clear();
+                let n = decompress_chunk(chunk, &mut buffer.spare_capacity_mut()[..out.len()])?;
+                if n != out.len() {
+                    return Err(DecompressionError::Size {
+                        offset: uncompressed_offset + i * chunk_unpacked_size,
+                        expected: out.len(),
+                        actual: n,
+                    });
+                }
+
+                // SAFETY: the first `n` bytes were written and are within the capacity.
+                unsafe { buffer.set_len(n) };
+                out.copy_from_slice(buffer);
+                Ok::<_, DecompressionError>(())
+            },
+        )?;
+
+    Ok(content)
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to compress with error {0}")]
+pub struct CompressionError(pub i32);
+
+/// Compresses a single chunk.
+pub fn compress(
+    chunk: &[u8],
+    compressor: libooz_sys::Compressor,
+    level: i32,
+) -> Result<Vec<u8>, CompressionError> {
+    let mut compressed = vec![0; libooz_sys::compress_bound(chunk.len())];
+
+    let n = libooz_sys::compress(compressor, level, chunk, &mut compressed);
+    if n < 0 {
+        return Err(CompressionError(n));
+    }
+    compressed.truncate(n as usize);
+
+    Ok(compressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // This module is also compiled into the decompression benchmark, which has no test utilities.
+    const CHUNK_SIZE: usize = 0x1000;
+
+    fn contents(seed: u8, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i / 7) as u8 ^ seed).collect()
+    }
+
+    /// Compresses `data` into chunks of [`CHUNK_SIZE`] and returns the chunks.
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        data.chunks(CHUNK_SIZE)
+            .map(|chunk| compress(chunk, libooz_sys::Compressor::Kraken, 4).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn sequential_decompresses_a_short_last_chunk() {
+        let data = contents(1, CHUNK_SIZE * 5 + 123);
+        let chunks = chunks(&data);
+        let chunks = chunks.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        let all = decompress_sequential(&chunks, CHUNK_SIZE, 0, data.len()).unwrap();
+        assert_eq!(all, data);
+
+        let tail = decompress_sequential(&chunks[3..], CHUNK_SIZE, 3, data.len()).unwrap();
+        assert_eq!(tail, data[CHUNK_SIZE * 3..]);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_matches_sequential() {
+        let data = contents(1, CHUNK_SIZE * 37 + 123);
+        let chunks = chunks(&data);
+        let chunks = chunks.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        for range in [0..chunks.len(), 0..2, 5..chunks.len(), 36..38, 10..20] {
+            let start = range.start;
+            let sequential =
+                decompress_sequential(&chunks[range.clone()], CHUNK_SIZE, start, data.len())
+                    .unwrap();
+            let parallel =
+                decompress_parallel(&chunks[range.clone()], CHUNK_SIZE, start, data.len()).unwrap();
+
+            let end = (range.end * CHUNK_SIZE).min(data.len());
+            assert_eq!(sequential, data[start * CHUNK_SIZE..end], "{range:?}");
+            assert_eq!(parallel, sequential, "{range:?}");
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_and_sequential_reject_wrong_sizes() {
+        let data = contents(1, CHUNK_SIZE * 3 + 123);
+        let chunks = chunks(&data);
+        let chunks = chunks.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        // The bundle claims to be one byte larger than the last chunk decompresses to.
+        for result in [
+            decompress_sequential(&chunks, CHUNK_SIZE, 0, data.len() + 1),
+            decompress_parallel(&chunks, CHUNK_SIZE, 0, data.len() + 1),
+        ] {
+            match result {
+                Err(DecompressionError::Size {
+                    offset, expected, ..
+                }) => assert_eq!((offset, expected), (CHUNK_SIZE * 3, 124)),
+                Err(DecompressionError::Ooz(_)) => {}
+                #[cfg(feature = "pure-rust")]
+                Err(DecompressionError::Oodle(_)) => {}
+                result => panic!("unexpected result {result:?}"),
+            }
+        }
+    }
+}
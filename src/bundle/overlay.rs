@@ -0,0 +1,463 @@
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use super::tree::glob_match;
+use super::{
+    BundleError, BundleFile, BundleFs, BundleResult, BundledFileReader, DirEntry, IndexBundle,
+};
+
+/// Index which reads loose files from overlay directories before falling back to the bundles.
+///
+/// A file `Data/BaseItemTypes.dat64` is overridden by `<overlay>/Data/BaseItemTypes.dat64`,
+/// this allows testing modified files without repacking the bundles.
+///
+/// Loose files are looked up by their exact path. Bundled files are looked up by their path
+/// hash, indices using [`PathHasher::Fnv1a`](crate::PathHasher::Fnv1a) hash the lower-cased
+/// path and find files regardless of case, indices using
+/// [`PathHasher::Murmur64A`](crate::PathHasher::Murmur64A) only find the exact path.
+/// On a case sensitive filesystem an overlay only overrides a file if the case of its path
+/// matches the name which is read.
+pub struct OverlayIndex<F> {
+    index: IndexBundle<F>,
+    overlays: Vec<PathBuf>,
+}
+
+impl<F> OverlayIndex<F> {
+    pub fn new(index: IndexBundle<F>) -> Self {
+        Self {
+            index,
+            overlays: Vec::new(),
+        }
+    }
+
+    /// Adds an overlay directory, later overlays take precedence over earlier ones.
+    pub fn with_overlay(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.overlays.push(dir.into());
+        self
+    }
+
+    /// Returns the underlying index, reads from it ignore the overlays.
+    pub fn bundled(&self) -> &IndexBundle<F> {
+        &self.index
+    }
+
+    /// Returns the path of the loose file overriding `name`, if there is one.
+    ///
+    /// Absolute names and names containing `..` never resolve to a path outside of the overlays,
+    /// they are not overridden.
+    pub fn overlay_path(&self, name: &str) -> Option<PathBuf> {
+        let relative = Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !relative {
+            tracing::warn!("not looking up '{name}' in the overlays, it is not a relative path");
+            return None;
+        }
+
+        self.overlays
+            .iter()
+            .rev()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Returns the names of all loose files of the overlays, sorted and without duplicates.
+    ///
+    /// Files with a name which is not valid UTF-8 are skipped.
+    pub fn overlay_files(&self) -> BundleResult<Vec<String>> {
+        let mut files = BTreeSet::new();
+        for dir in &self.overlays {
+            collect_files(dir, "", &mut files)?;
+        }
+        Ok(files.into_iter().collect())
+    }
+
+    /// Lists a directory of the index together with the loose files of the overlays,
+    /// see [`IndexBundle::list`].
+    ///
+    /// Returns `None` if the directory exists neither in the index nor in an overlay.
+    pub fn list(&self, dir: &str) -> BundleResult<Option<Vec<OverlayDirEntry>>> {
+        let dir = dir.trim_end_matches('/');
+
+        let bundled = self.index.list(dir);
+        let mut found = bundled.is_some();
+        let mut entries = bundled
+            .into_iter()
+            .flatten()
+            .map(OverlayDirEntry::from)
+            .collect::<BTreeSet<_>>();
+
+        for file in self.overlay_files()? {
+            let rest = match dir.is_empty() {
+                true => Some(file.as_str()),
+                false => file
+                    .strip_prefix(dir)
+                    .and_then(|rest| rest.strip_prefix('/')),
+            };
+            let Some(rest) = rest else {
+                continue;
+            };
+
+            found = true;
+            let entry = match rest.split_once('/') {
+                Some((sub, _)) if dir.is_empty() => OverlayDirEntry::Directory(sub.to_owned()),
+                Some((sub, _)) => OverlayDirEntry::Directory(format!("{dir}/{sub}")),
+                None => OverlayDirEntry::File(file),
+            };
+            entries.insert(entry);
+        }
+
+        Ok(found.then(|| entries.into_iter().collect()))
+    }
+
+    /// Finds all bundled and loose files matching a glob pattern, see [`IndexBundle::glob`].
+    ///
+    /// Files are sorted and reported once, even if they are overridden.
+    pub fn glob(&self, pattern: &str) -> BundleResult<Vec<String>> {
+        let mut files = self
+            .index
+            .glob(pattern)
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<BTreeSet<_>>();
+
+        let loose = self.overlay_files()?;
+        files.extend(loose.into_iter().filter(|file| glob_match(pattern, file)));
+
+        Ok(files.into_iter().collect())
+    }
+}
+
+/// An entry of a directory of an [`OverlayIndex`], see [`DirEntry`].
+///
+/// Directories are ordered before files.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OverlayDirEntry {
+    /// Full path of a sub directory, without a trailing slash.
+    Directory(String),
+    /// Full path of a file.
+    File(String),
+}
+
+impl From<DirEntry<'_>> for OverlayDirEntry {
+    fn from(entry: DirEntry<'_>) -> Self {
+        match entry {
+            DirEntry::Directory(dir) => Self::Directory(dir.to_owned()),
+            DirEntry::File(file) => Self::File(file.to_owned()),
+        }
+    }
+}
+
+impl<F: BundleFs> OverlayIndex<F> {
+    pub fn read<T: BundleFile>(&self) -> BundleResult<Option<T::Output>> {
+        let Some(data) = self.read_by_name(T::NAME)? else {
+            return Ok(None);
+        };
+
+        T::from(data).map(Some).map_err(|source| BundleError::Dat {
+            name: T::NAME.to_owned(),
+            source,
+        })
+    }
+
+    pub fn read_by_name(&self, name: &str) -> BundleResult<Option<Vec<u8>>> {
+        match self.overlay_path(name) {
+            Some(path) => read_overlay(name, &path).map(Some),
+            None => self.index.read_by_name(name),
+        }
+    }
+
+    /// Opens a file for streaming, see [`IndexBundle::open`].
+    pub fn open(&self, name: &str) -> BundleResult<Option<OverlayFileReader<'_, F>>> {
+        if let Some(path) = self.overlay_path(name) {
+            tracing::debug!("opening file '{name}' from overlay {path:?}");
+            let file = std::fs::File::open(path)?;
+            return Ok(Some(OverlayFileReader::Overlay(file)));
+        }
+
+        let reader = self.index.open(name)?;
+        Ok(reader.map(OverlayFileReader::Bundled))
+    }
+
+    /// Reads multiple files at once, see [`IndexBundle::read_many`].
+    ///
+    /// Files read from the overlays come first, in the order of `names`.
+    pub fn read_many<'a, N>(
+        &'a self,
+        names: impl IntoIterator<Item = N>,
+    ) -> impl Iterator<Item = BundleResult<(N, Vec<u8>)>> + 'a
+    where
+        N: AsRef<str> + 'a,
+    {
+        let mut overridden = Vec::new();
+        let mut bundled = Vec::new();
+        for name in names {
+            match self.overlay_path(name.as_ref()) {
+                Some(path) => overridden.push((name, path)),
+                None => bundled.push(name),
+            }
+        }
+
+        let overridden = overridden.into_iter().map(|(name, path)| {
+            let data = read_overlay(name.as_ref(), &path)?;
+            Ok((name, data))
+        });
+
+        overridden.chain(self.index.read_many(bundled))
+    }
+}
+
+/// Streaming reader of a loose or a bundled file, see [`OverlayIndex::open`].
+pub enum OverlayFileReader<'a, F: BundleFs> {
+    Overlay(std::fs::File),
+    Bundled(BundledFileReader<'a, F>),
+}
+
+impl<'a, F: BundleFs> Read for OverlayFileReader<'a, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Overlay(file) => file.read(buf),
+            Self::Bundled(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Adds the names of all files below `dir` to `files`, prefixed with `prefix`.
+fn collect_files(dir: &Path, prefix: &str, files: &mut BTreeSet<String>) -> BundleResult<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let name = format!("{prefix}{name}");
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), &format!("{name}/"), files)?;
+        } else if entry.path().is_file() {
+            files.insert(name);
+        }
+    }
+
+    Ok(())
+}
+
+fn read_overlay(name: &str, path: &Path) -> BundleResult<Vec<u8>> {
+    tracing::debug!("reading file '{name}' from overlay {path:?}");
+    Ok(std::fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::testutil::{contents, Fixture};
+    use crate::bundle::{Bundle, LocalBundleFs};
+    use crate::PathHasher;
+
+    /// A bundled file which is read as is, to test [`OverlayIndex::read`].
+    struct Words;
+
+    impl BundleFile for Words {
+        const NAME: &'static str = "Data/Words.dat64";
+
+        type Output = Vec<u8>;
+
+        fn from(data: Vec<u8>) -> Result<Self::Output, crate::DatError> {
+            Ok(data)
+        }
+    }
+
+    fn bundles() -> tempfile::TempDir {
+        Fixture::new()
+            .bundle(
+                "Data",
+                &[
+                    ("Data/Words.dat64", &contents(1, 100)),
+                    ("Data/Mods.dat64", &contents(2, 100)),
+                ],
+            )
+            .bundle("Art", &[("Art/Items/Ring.dds", &contents(3, 100))])
+            .tempdir()
+    }
+
+    fn write(dir: &Path, name: &str, data: &[u8]) {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+
+    fn load(dir: &Path) -> IndexBundle<LocalBundleFs> {
+        Bundle::new(LocalBundleFs::new(dir)).into_index().unwrap()
+    }
+
+    #[test]
+    fn bundled_file_case_depends_on_the_path_hash() {
+        for (hasher, found) in [(PathHasher::Fnv1a, true), (PathHasher::Murmur64A, false)] {
+            let dir = Fixture::new()
+                .hasher(hasher)
+                .bundle("Data", &[("Data/Words.dat64", &contents(1, 100))])
+                .tempdir();
+            let overlay = tempfile::tempdir().unwrap();
+            write(overlay.path(), "Data/Words.dat64", b"overlay");
+
+            let index = OverlayIndex::new(load(dir.path())).with_overlay(overlay.path());
+            let data = index.read_by_name("data/words.dat64").unwrap();
+            assert_eq!(data.is_some(), found, "{hasher:?}");
+            if let Some(data) = data {
+                assert_eq!(data, contents(1, 100));
+            }
+            assert_eq!(
+                index.read_by_name("Data/Words.dat64").unwrap().as_deref(),
+                Some(&b"overlay"[..])
+            );
+        }
+    }
+
+    #[test]
+    fn later_overlays_take_precedence() {
+        let dir = bundles();
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write(first.path(), "Data/Words.dat64", b"first");
+        write(first.path(), "Data/Mods.dat64", b"first");
+        write(second.path(), "Data/Words.dat64", b"second");
+
+        let index = OverlayIndex::new(load(dir.path()))
+            .with_overlay(first.path())
+            .with_overlay(second.path());
+
+        let read = |name| index.read_by_name(name).unwrap().unwrap();
+        assert_eq!(read("Data/Words.dat64"), b"second");
+        assert_eq!(read("Data/Mods.dat64"), b"first");
+        assert_eq!(read("Art/Items/Ring.dds"), contents(3, 100));
+
+        let mut data = Vec::new();
+        let mut reader = index.open("Data/Words.dat64").unwrap().unwrap();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"second");
+
+        let mut files = index
+            .read_many(["Art/Items/Ring.dds", "Data/Mods.dat64", "Data/Words.dat64"])
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                ("Art/Items/Ring.dds", contents(3, 100)),
+                ("Data/Mods.dat64", b"first".to_vec()),
+                ("Data/Words.dat64", b"second".to_vec()),
+            ]
+        );
+
+        assert_eq!(
+            index.bundled().read_by_name("Data/Words.dat64").unwrap(),
+            Some(contents(1, 100))
+        );
+    }
+
+    #[test]
+    fn read_falls_back_to_the_bundles() {
+        let dir = bundles();
+        let overlay = tempfile::tempdir().unwrap();
+        write(overlay.path(), "Data/Mods.dat64", b"overlay");
+
+        let index = OverlayIndex::new(load(dir.path())).with_overlay(overlay.path());
+        assert_eq!(index.read::<Words>().unwrap(), Some(contents(1, 100)));
+
+        write(overlay.path(), "Data/Words.dat64", b"overlay");
+        assert_eq!(index.read::<Words>().unwrap(), Some(b"overlay".to_vec()));
+
+        let empty = Fixture::new().bundle("Art", &[("a.bin", b"")]).tempdir();
+        let index = OverlayIndex::new(load(empty.path()));
+        assert_eq!(index.read::<Words>().unwrap(), None);
+    }
+
+    #[test]
+    fn names_outside_of_the_overlay_are_not_overridden() {
+        let dir = bundles();
+        let root = tempfile::tempdir().unwrap();
+        let overlay = root.path().join("overlay");
+        write(&overlay, "Data/Words.dat64", b"overlay");
+        write(root.path(), "secret.txt", b"secret");
+
+        let index = OverlayIndex::new(load(dir.path())).with_overlay(&overlay);
+        assert!(index.overlay_path("Data/Words.dat64").is_some());
+        assert_eq!(index.overlay_path("../secret.txt"), None);
+        assert_eq!(index.overlay_path("Data/../../secret.txt"), None);
+        let absolute = root.path().join("secret.txt");
+        assert_eq!(index.overlay_path(absolute.to_str().unwrap()), None);
+
+        assert_eq!(index.read_by_name("../secret.txt").unwrap(), None);
+    }
+
+    #[test]
+    fn list_and_glob_include_overlays() {
+        let dir = bundles();
+        let overlay = tempfile::tempdir().unwrap();
+        write(overlay.path(), "Data/Words.dat64", b"overlay");
+        write(overlay.path(), "Data/New.dat64", b"overlay");
+        write(overlay.path(), "Data/Sub/Deep.dat64", b"overlay");
+        write(overlay.path(), "Loose/File.txt", b"overlay");
+
+        let index = OverlayIndex::new(load(dir.path()))
+            .with_overlay(overlay.path())
+            .with_overlay(overlay.path().join("missing"));
+
+        use OverlayDirEntry::{Directory, File};
+        let list = |dir| index.list(dir).unwrap();
+        assert_eq!(
+            list("Data"),
+            Some(vec![
+                Directory("Data/Sub".to_owned()),
+                File("Data/Mods.dat64".to_owned()),
+                File("Data/New.dat64".to_owned()),
+                File("Data/Words.dat64".to_owned()),
+            ])
+        );
+        assert_eq!(
+            list(""),
+            Some(vec![
+                Directory("Art".to_owned()),
+                Directory("Data".to_owned()),
+                Directory("Loose".to_owned()),
+            ])
+        );
+        assert_eq!(
+            list("Loose/"),
+            Some(vec![File("Loose/File.txt".to_owned())])
+        );
+        assert_eq!(list("Missing"), None);
+
+        assert_eq!(
+            index.glob("Data/*.dat64").unwrap(),
+            ["Data/Mods.dat64", "Data/New.dat64", "Data/Words.dat64"]
+        );
+        assert_eq!(
+            index.glob("**/*.dat64").unwrap(),
+            [
+                "Data/Mods.dat64",
+                "Data/New.dat64",
+                "Data/Sub/Deep.dat64",
+                "Data/Words.dat64"
+            ]
+        );
+        assert_eq!(
+            index.glob("*/**").unwrap(),
+            [
+                "Art/Items/Ring.dds",
+                "Data/Mods.dat64",
+                "Data/New.dat64",
+                "Data/Sub/Deep.dat64",
+                "Data/Words.dat64",
+                "Loose/File.txt"
+            ]
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use std::io::Read;
 
-use nom::bytes::streaming::take;
+use nom::bytes::streaming::{tag, take, take_till};
+use nom::combinator::{map_res, verify};
 use nom::multi::{count, length_count};
 use nom::number::streaming::{le_u32, le_u64};
-use nom::sequence::Tuple;
+use nom::sequence::{terminated, Tuple};
 use nom::{IResult, Parser};
 
 #[allow(dead_code)]
@@ -26,6 +27,11 @@ impl Head {
         nom_read(parse, 60, reader)
     }
 
+    /// Size of the encoded header in bytes.
+    pub fn size(&self) -> usize {
+        60 + self.payload.chunk_sizes.len() * std::mem::size_of::<u32>()
+    }
+
     pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
         let (input, (uncompressed_size, total_payload_size, _, payload)) =
             (le_u32, le_u32, le_u32, HeadPayload::parse).parse(input)?;
@@ -109,14 +115,13 @@ pub struct BundleEntry<'a> {
 
 impl<'a> BundleEntry<'a> {
     pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
-        let (input, (data, size)) = (le_u32.flat_map(take), le_u32).parse(input)?;
+        let name = map_res(le_u32.flat_map(take), std::str::from_utf8);
+        let (input, (name, size)) = (name, le_u32).parse(input)?;
 
         Ok((
             input,
             Self {
-                // TODO: actually handle errors
-                // I hate nom sometimes, this needs a complete custom error type?
-                name: std::str::from_utf8(data).expect("invalid string"),
+                name,
                 size: size as usize,
             },
         ))
@@ -173,28 +178,81 @@ impl PathRep {
     }
 }
 
+/// Reconstructs the file paths of a single path representation payload.
+///
+/// The payload is a sequence of `u32` commands each followed by a nul terminated string.
+/// A `0` command toggles between collecting base paths and emitting file paths,
+/// any other command is a 1-based index of the base path to prefix the string with.
+pub fn parse_paths(mut input: &[u8]) -> IResult<&[u8], Vec<String>> {
+    let mut bases = Vec::<String>::new();
+    let mut paths = Vec::new();
+    let mut is_base = false;
+
+    while !input.is_empty() {
+        let (rem, cmd) = le_u32(input)?;
+        input = rem;
+
+        if cmd == 0 {
+            is_base = !is_base;
+            if is_base {
+                bases.clear();
+            }
+            continue;
+        }
+
+        let (rem, part) = map_res(
+            terminated(take_till(|b| b == 0), tag([0])),
+            std::str::from_utf8,
+        )(input)?;
+        input = rem;
+
+        let path = match bases.get(cmd as usize - 1) {
+            Some(base) => format!("{base}{part}"),
+            None => part.to_owned(),
+        };
+
+        if is_base {
+            bases.push(path);
+        } else {
+            paths.push(path);
+        }
+    }
+
+    Ok((input, paths))
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct IndexBundle<'a> {
     pub bundles: Vec<BundleEntry<'a>>,
     pub files: Vec<FileInfo>,
+    pub paths: Vec<PathRep>,
+    /// Head of the compressed path representation payload.
     pub head: Head,
+    /// Compressed chunks of the path representation payload.
+    pub path_data: &'a [u8],
 }
 
 impl<'a> IndexBundle<'a> {
     pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
         let (input, bundles) = length_count(le_u32, BundleEntry::parse)(input)?;
-        let (input, files) = length_count(le_u32, FileInfo::parse)(input)?;
+        let file = verify(FileInfo::parse, |file| {
+            (file.bundle_index as usize) < bundles.len()
+        });
+        let (input, files) = length_count(le_u32, file)(input)?;
 
-        let (input, _reps) = length_count(le_u32, PathRep::parse)(input)?;
+        let (input, paths) = length_count(le_u32, PathRep::parse)(input)?;
         let (input, head) = Head::parse(input)?;
+        let (input, path_data) = take(head.payload.compressed_size)(input)?;
 
         Ok((
             input,
             Self {
                 bundles,
                 files,
+                paths,
                 head,
+                path_data,
             },
         ))
     }
@@ -229,9 +287,12 @@ where
             Err(nom::Err::Failure(e)) => return Err(ReadErr::Parse(nom::Err::Failure(e))),
         };
 
-        (&mut reader)
+        let read = (&mut reader)
             .take(to_read as u64)
             .read_to_end(&mut input)
             .map_err(ReadErr::Io)?;
+        if read == 0 {
+            return Err(ReadErr::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
     }
 }
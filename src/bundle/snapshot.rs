@@ -0,0 +1,236 @@
+//! Persisted snapshot of a parsed index, see [`super::Bundle::index_snapshot`].
+//!
+//! A snapshot contains everything needed to load the index without decompressing it:
+//! file locations, reconstructed paths and directories.
+//! It consists of the magic `PBIX` and the format version as little endian `u32`,
+//! followed by the index in the compact layout of [`super::table`].
+//!
+//! Snapshots are memory mapped, lookups read the snapshot directly.
+use std::io::Write;
+use std::path::Path;
+
+use byteorder::{WriteBytesExt, LE};
+use memmap2::Mmap;
+
+use super::table::Table;
+use super::{BundleError, BundleResult};
+
+const MAGIC: &[u8; 4] = b"PBIX";
+const VERSION: u32 = 2;
+const HEADER_SIZE: usize = 8;
+
+/// Key of a persisted index snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotKey {
+    /// Fingerprint of `_.index.bin` reported by the filesystem,
+    /// see [`BundleFs::fingerprint`](crate::BundleFs::fingerprint).
+    ///
+    /// The index is not read at all if there is a snapshot. Filesystems which can not
+    /// fingerprint files fall back to [`SnapshotKey::IndexHash`].
+    Fingerprint,
+    /// SHA-256 of the compressed `_.index.bin`.
+    ///
+    /// The index has to be read to find the snapshot, but it is only decompressed
+    /// if there is no snapshot yet.
+    IndexHash,
+    /// Patch version of the bundles, the index is not read at all if there is a snapshot.
+    Version(String),
+}
+
+/// Snapshot of a parsed index.
+pub(super) struct Snapshot;
+
+impl Snapshot {
+    /// Memory maps the snapshot file at `path`.
+    ///
+    /// The snapshot is validated once, nothing is copied.
+    pub fn read(path: &Path) -> BundleResult<Table> {
+        let name = path.display().to_string();
+
+        let file = std::fs::File::open(path)?;
+        // SAFETY: snapshots are never modified in place, a new snapshot replaces
+        // the file atomically and existing mappings keep the previous snapshot.
+        let map = unsafe { Mmap::map(&file)? };
+
+        let version = map
+            .get(4..HEADER_SIZE)
+            .map(|v| u32::from_le_bytes(v.try_into().unwrap()));
+        if map.get(..4) != Some(&MAGIC[..]) || version != Some(VERSION) {
+            return Err(BundleError::Invalid {
+                name,
+                offset: 0,
+                reason: "not an index snapshot of this version",
+            });
+        }
+
+        Table::mapped(map, HEADER_SIZE).map_err(|err| err.error(&name))
+    }
+
+    /// Atomically writes a snapshot to `path`.
+    pub fn write(path: &Path, table: &Table) -> BundleResult<()> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir)?;
+
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        let mut writer = std::io::BufWriter::new(&mut tmp);
+        writer.write_all(MAGIC)?;
+        writer.write_u32::<LE>(VERSION)?;
+        writer.write_all(table.as_bytes())?;
+        writer.flush()?;
+        drop(writer);
+        tmp.persist(path).map_err(|err| err.error)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::bundle::testutil::{contents, Fixture};
+    use crate::bundle::{
+        Bundle, BundleFs, BundleFsError, FileContents, IndexBundle, LocalBundleFs,
+    };
+
+    /// Filesystem which counts the reads of the index.
+    struct Counting {
+        fs: LocalBundleFs,
+        index_reads: AtomicUsize,
+    }
+
+    impl BundleFs for Counting {
+        fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
+            if name == "Bundles2/_.index.bin" {
+                self.index_reads.fetch_add(1, Ordering::Relaxed);
+            }
+            self.fs.get(name)
+        }
+
+        fn get_range(
+            &self,
+            name: &str,
+            range: Range<u64>,
+        ) -> Result<Option<FileContents>, BundleFsError> {
+            self.fs.get_range(name, range)
+        }
+
+        fn fingerprint(&self, name: &str) -> Result<Option<String>, BundleFsError> {
+            self.fs.fingerprint(name)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = Fixture::new()
+            .bundle("a", &[("Data/a.bin", &contents(1, 10))])
+            .bundle("b", &[("Data/b.bin", &contents(2, 20)), ("c.txt", b"c")])
+            .tempdir();
+        let index = Bundle::new(LocalBundleFs::new(dir.path()))
+            .into_index()
+            .unwrap();
+
+        let path = dir.path().join("snapshots/index.bin");
+        index.save_snapshot(&path).unwrap();
+        let snapshot = IndexBundle::load_snapshot(LocalBundleFs::new(dir.path()), &path).unwrap();
+
+        let mut paths = snapshot.paths().collect::<Vec<_>>();
+        paths.sort_unstable();
+        assert_eq!(paths, ["Data/a.bin", "Data/b.bin", "c.txt"]);
+        assert_eq!(snapshot.glob("Data/*").len(), 2);
+        assert_eq!(
+            snapshot.read_by_name("Data/b.bin").unwrap(),
+            Some(contents(2, 20))
+        );
+        assert_eq!(snapshot.hasher(), index.hasher());
+        assert!(snapshot.iter().eq(index.iter()));
+        assert!(snapshot
+            .list("Data")
+            .unwrap()
+            .eq(index.list("Data").unwrap()));
+
+        // Every truncated snapshot is rejected.
+        let data = std::fs::read(&path).unwrap();
+        for len in 0..data.len() {
+            std::fs::write(&path, &data[..len]).unwrap();
+            assert!(IndexBundle::load_snapshot(LocalBundleFs::new(dir.path()), &path).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index");
+        std::fs::write(&path, b"PBIX\x01\x00\x00\x00").unwrap();
+
+        let Err(BundleError::Invalid { reason, .. }) = Snapshot::read(&path) else {
+            panic!("expected an invalid snapshot");
+        };
+        assert_eq!(reason, "not an index snapshot of this version");
+    }
+
+    #[test]
+    fn fingerprint_key_skips_reading_the_index() {
+        let dir = Fixture::new()
+            .bundle("a", &[("Data/a.bin", &contents(1, 10))])
+            .tempdir();
+        let snapshots = dir.path().join("snapshots");
+        let fs = Counting {
+            fs: LocalBundleFs::new(dir.path()),
+            index_reads: AtomicUsize::new(0),
+        };
+        let bundle = Bundle::new(&fs);
+        let paths = || {
+            let index = bundle
+                .index_snapshot(&snapshots, SnapshotKey::Fingerprint)
+                .unwrap();
+            index.paths().map(str::to_owned).collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(), ["Data/a.bin"]);
+        assert_eq!(fs.index_reads.load(Ordering::Relaxed), 1);
+        assert_eq!(paths(), ["Data/a.bin"]);
+        assert_eq!(fs.index_reads.load(Ordering::Relaxed), 1);
+
+        // A changed index has a different fingerprint.
+        Fixture::new()
+            .bundle("a", &[("Data/a.bin", &contents(1, 10))])
+            .bundle("b", &[("Data/b.bin", &contents(2, 10))])
+            .write(dir.path());
+        let mut changed = paths();
+        changed.sort_unstable();
+        assert_eq!(changed, ["Data/a.bin", "Data/b.bin"]);
+        assert_eq!(fs.index_reads.load(Ordering::Relaxed), 2);
+        assert_eq!(std::fs::read_dir(&snapshots).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn filesystems_without_fingerprints_use_the_index_hash() {
+        struct NoFingerprint(LocalBundleFs);
+
+        impl BundleFs for NoFingerprint {
+            fn get(&self, name: &str) -> Result<FileContents, BundleFsError> {
+                self.0.get(name)
+            }
+        }
+
+        let dir = Fixture::new()
+            .bundle("a", &[("Data/a.bin", &contents(1, 10))])
+            .tempdir();
+        let snapshots = dir.path().join("snapshots");
+        let bundle = Bundle::new(NoFingerprint(LocalBundleFs::new(dir.path())));
+
+        let index = bundle
+            .index_snapshot(&snapshots, SnapshotKey::Fingerprint)
+            .unwrap();
+        assert_eq!(index.paths().collect::<Vec<_>>(), ["Data/a.bin"]);
+
+        let raw = std::fs::read(dir.path().join("Bundles2/_.index.bin")).unwrap();
+        let hash = format!("{:x}", Sha256::digest(raw));
+        assert!(snapshots.join(format!("{hash}.index")).exists());
+    }
+}
@@ -0,0 +1,696 @@
+//! Compact layout of a parsed index, see [`super::IndexBundle`].
+//!
+//! Files, directories and paths of the index are stored in a single buffer without pointers,
+//! strings are stored back to back and referenced by their position.
+//! The buffer is persisted as is by snapshots and memory mapped when they are loaded.
+//! All integers are stored little endian:
+//!
+//! - Header with the path hash algorithm, the amount of files, directories, children,
+//!   bundles and paths and the total length of all bundle names and of all paths as `u32`.
+//! - Files sorted by hash, as hash `u64`, bundle index `u32`, offset `u32` and size `u32`.
+//! - Directories sorted by hash, as hash `u64`, index of a path which starts with the name of
+//!   the directory `u32`, length of the name `u32`, range of the directory's paths and range
+//!   of its children as pairs of `u32`.
+//! - Children of all directories, as index of the child directory `u32`.
+//! - End offsets of the bundle names `u32`, followed by the names.
+//! - End offsets of the paths `u32`, followed by the paths.
+use std::ops::{Deref, Range};
+
+use memmap2::Mmap;
+
+use super::tree::TreeBuilder;
+use super::{BundleError, FileRef};
+use crate::PathHasher;
+
+const HEADER_SIZE: usize = 8 * 4;
+const FILE_SIZE: usize = 20;
+const DIR_SIZE: usize = 32;
+
+/// Parsed index in the compact layout.
+pub(crate) struct Table {
+    data: Storage,
+    /// Offset of the header in `data`.
+    start: usize,
+    hasher: PathHasher,
+    files: Range<usize>,
+    dirs: Range<usize>,
+    children: Range<usize>,
+    bundles: Arena,
+    paths: Arena,
+}
+
+/// Buffer of a [`Table`].
+enum Storage {
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Storage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Owned(data) => data,
+            Self::Mapped(map) => map,
+        }
+    }
+}
+
+/// Location of a string arena in the table.
+#[derive(Debug, Clone)]
+struct Arena {
+    ends: Range<usize>,
+    data: Range<usize>,
+}
+
+/// Reason why a table was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Invalid {
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+impl Invalid {
+    pub fn error(self, name: &str) -> BundleError {
+        BundleError::Invalid {
+            name: name.to_owned(),
+            offset: self.offset,
+            reason: self.reason,
+        }
+    }
+}
+
+impl Table {
+    /// Validates a table, lookups do not check the contents again.
+    pub fn new(data: Vec<u8>) -> Result<Self, Invalid> {
+        Self::from_storage(Storage::Owned(data), 0)
+    }
+
+    /// Validates a table which is memory mapped, starting at `start`.
+    ///
+    /// Validating the table reads it once, nothing is copied.
+    pub fn mapped(map: Mmap, start: usize) -> Result<Self, Invalid> {
+        Self::from_storage(Storage::Mapped(map), start)
+    }
+
+    fn from_storage(data: Storage, start: usize) -> Result<Self, Invalid> {
+        let invalid = |offset, reason| Invalid { offset, reason };
+
+        if data.len() < start + HEADER_SIZE {
+            return Err(invalid(data.len(), "truncated header"));
+        }
+        let header = |i: usize| u32_at(&data, start + i * 4) as usize;
+        let hasher = match header(0) {
+            0 => PathHasher::Fnv1a,
+            1 => PathHasher::Murmur64A,
+            _ => return Err(invalid(0, "unknown path hash algorithm")),
+        };
+
+        // Every section length fits into a `u64`, sections are only used once they all fit.
+        let mut end = (start + HEADER_SIZE) as u64;
+        let mut section = |len: usize, size: usize| {
+            let start = end;
+            end += len as u64 * size as u64;
+            start as usize..end as usize
+        };
+        let files = section(header(1), FILE_SIZE);
+        let dirs = section(header(2), DIR_SIZE);
+        let children = section(header(3), 4);
+        let bundles = Arena {
+            ends: section(header(4), 4),
+            data: section(header(6), 1),
+        };
+        let paths = Arena {
+            ends: section(header(5), 4),
+            data: section(header(7), 1),
+        };
+        if end != data.len() as u64 {
+            return Err(invalid(data.len(), "size does not match the header"));
+        }
+
+        let table = Self {
+            data,
+            start,
+            hasher,
+            files,
+            dirs,
+            children,
+            bundles,
+            paths,
+        };
+        table.validate()?;
+
+        Ok(table)
+    }
+
+    fn validate(&self) -> Result<(), Invalid> {
+        let invalid = |offset, reason| Err(Invalid { offset, reason });
+
+        for arena in [&self.bundles, &self.paths] {
+            let mut start = 0;
+            for offset in arena.ends.clone().step_by(4) {
+                let end = u32_at(&self.data, offset) as usize;
+                if end < start || end > arena.data.len() {
+                    return invalid(offset, "string out of bounds");
+                }
+                let bytes = &self.data[arena.data.start + start..arena.data.start + end];
+                if std::str::from_utf8(bytes).is_err() {
+                    return invalid(arena.data.start + start, "string is not valid UTF-8");
+                }
+                start = end;
+            }
+        }
+
+        let mut previous = None;
+        for offset in self.files.clone().step_by(FILE_SIZE) {
+            let hash = u64_at(&self.data, offset);
+            if previous.is_some_and(|previous| previous >= hash) {
+                return invalid(offset, "files are not sorted");
+            }
+            previous = Some(hash);
+
+            if u32_at(&self.data, offset + 8) as usize >= self.bundles().len() {
+                return invalid(offset, "bundle index out of bounds");
+            }
+        }
+
+        let (paths, dirs) = (self.paths(), self.dir_count());
+        let mut previous = None;
+        for index in 0..dirs {
+            let offset = self.dirs.start + index * DIR_SIZE;
+            let field = |i| self.dir_field(index, i);
+            let hash = u64_at(&self.data, offset);
+            if previous.is_some_and(|previous| previous >= hash) {
+                return invalid(offset, "directories are not sorted");
+            }
+            previous = Some(hash);
+
+            let (path, name_len) = (field(0), field(1));
+            if path >= paths.len() || !paths.get(path).is_char_boundary(name_len) {
+                return invalid(offset, "directory name out of bounds");
+            }
+            let (files, children) = (field(2)..field(3), field(4)..field(5));
+            if files.start > files.end || files.end > paths.len() {
+                return invalid(offset, "directory files out of bounds");
+            }
+            if children.start > children.end || children.end > self.children.len() / 4 {
+                return invalid(offset, "directory children out of bounds");
+            }
+
+            // Names of children are longer than the name of their parent, there are no cycles.
+            for child in children {
+                let child = u32_at(&self.data, self.children.start + child * 4) as usize;
+                if child >= dirs || self.dir_field(child, 1) <= name_len {
+                    return invalid(offset, "invalid child directory");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the table in the compact layout, see the module documentation.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[self.start..]
+    }
+
+    /// Hash algorithm used for the paths of the index.
+    pub fn hasher(&self) -> PathHasher {
+        self.hasher
+    }
+
+    /// Looks up the location of a file by its hash.
+    pub fn file(&self, hash: u64) -> Option<FileRef> {
+        let index = search(&self.data[self.files.clone()], FILE_SIZE, hash)?;
+        Some(self.file_at(self.files.start + index * FILE_SIZE).1)
+    }
+
+    /// Returns all files sorted by hash.
+    pub fn files(&self) -> impl ExactSizeIterator<Item = (u64, FileRef)> + '_ {
+        self.files
+            .clone()
+            .step_by(FILE_SIZE)
+            .map(|offset| self.file_at(offset))
+    }
+
+    fn file_at(&self, offset: usize) -> (u64, FileRef) {
+        let fref = FileRef {
+            bundle_index: u32_at(&self.data, offset + 8),
+            file_offset: u32_at(&self.data, offset + 12),
+            file_size: u32_at(&self.data, offset + 16),
+        };
+        (u64_at(&self.data, offset), fref)
+    }
+
+    /// Names of all bundles, files reference their bundle by index.
+    pub fn bundles(&self) -> Strings<'_> {
+        self.strings(&self.bundles)
+    }
+
+    /// Reconstructed paths of all files, grouped by directory.
+    pub fn paths(&self) -> Strings<'_> {
+        self.strings(&self.paths)
+    }
+
+    fn strings(&self, arena: &Arena) -> Strings<'_> {
+        Strings {
+            ends: &self.data[arena.ends.clone()],
+            data: &self.data[arena.data.clone()],
+        }
+    }
+
+    /// Looks up a directory by its hash.
+    pub fn dir(&self, hash: u64) -> Option<Directory<'_>> {
+        let index = search(&self.data[self.dirs.clone()], DIR_SIZE, hash)?;
+        Some(self.dir_at(index))
+    }
+
+    /// Returns the direct sub directories of a directory.
+    pub fn children<'a>(&'a self, dir: &Directory<'a>) -> impl Iterator<Item = Directory<'a>> + 'a {
+        dir.children.clone().map(|child| {
+            let index = u32_at(&self.data, self.children.start + child * 4);
+            self.dir_at(index as usize)
+        })
+    }
+
+    fn dir_count(&self) -> usize {
+        self.dirs.len() / DIR_SIZE
+    }
+
+    /// Reads a `u32` field of a directory, following its hash.
+    fn dir_field(&self, index: usize, field: usize) -> usize {
+        u32_at(
+            &self.data,
+            self.dirs.start + index * DIR_SIZE + 8 + field * 4,
+        ) as usize
+    }
+
+    fn dir_at(&self, index: usize) -> Directory<'_> {
+        let field = |i| self.dir_field(index, i);
+        Directory {
+            name: &self.paths().get(field(0))[..field(1)],
+            files: field(2)..field(3),
+            children: field(4)..field(5),
+        }
+    }
+}
+
+/// Directory of a [`Table`].
+#[derive(Debug, Clone)]
+pub(crate) struct Directory<'a> {
+    /// Full path of the directory, without a trailing slash.
+    pub name: &'a str,
+    /// Range of the directory's files in the paths of the table.
+    pub files: Range<usize>,
+    children: Range<usize>,
+}
+
+/// Strings of a [`Table`], stored back to back.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Strings<'a> {
+    ends: &'a [u8],
+    data: &'a [u8],
+}
+
+impl<'a> Strings<'a> {
+    pub fn len(&self) -> usize {
+        self.ends.len() / 4
+    }
+
+    /// Returns the string at `index`, panics if the index is out of bounds.
+    pub fn get(&self, index: usize) -> &'a str {
+        let start = match index {
+            0 => 0,
+            _ => u32_at(self.ends, (index - 1) * 4) as usize,
+        };
+        let end = u32_at(self.ends, index * 4) as usize;
+        // Strings are validated when they are added to the table.
+        std::str::from_utf8(&self.data[start..end]).unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &'a str> + 'a {
+        self.range(0..self.len())
+    }
+
+    pub fn range(&self, range: Range<usize>) -> impl ExactSizeIterator<Item = &'a str> + 'a {
+        let strings = *self;
+        range.map(move |index| strings.get(index))
+    }
+}
+
+/// Collects the contents of a [`Table`].
+#[derive(Debug, Default)]
+pub(crate) struct TableBuilder {
+    files: Vec<(u64, FileRef)>,
+    dirs: Vec<(u64, Range<usize>)>,
+    bundles: ArenaBuilder,
+    paths: ArenaBuilder,
+}
+
+#[derive(Debug, Default)]
+struct ArenaBuilder {
+    ends: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl ArenaBuilder {
+    fn push(&mut self, s: &str) {
+        self.data.extend_from_slice(s.as_bytes());
+        self.ends
+            .extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+    }
+
+    fn strings(&self) -> Strings<'_> {
+        Strings {
+            ends: &self.ends,
+            data: &self.data,
+        }
+    }
+}
+
+impl TableBuilder {
+    pub fn with_capacity(files: usize) -> Self {
+        Self {
+            files: Vec::with_capacity(files),
+            ..Default::default()
+        }
+    }
+
+    pub fn bundle(&mut self, name: &str) {
+        self.bundles.push(name);
+    }
+
+    /// Adds a file, a later file with the same hash replaces an earlier one.
+    pub fn file(&mut self, hash: u64, fref: FileRef) {
+        self.files.push((hash, fref));
+    }
+
+    /// Adds the path of a file and returns its index.
+    pub fn path(&mut self, path: &str) -> usize {
+        self.paths.push(path);
+        self.paths.strings().len() - 1
+    }
+
+    /// Amount of paths added so far.
+    pub fn path_count(&self) -> usize {
+        self.paths.strings().len()
+    }
+
+    /// Adds the directory of a path representation.
+    ///
+    /// All files of the directory must be added continuously, `files` is their range
+    /// of indices returned by [`TableBuilder::path`].
+    pub fn dir(&mut self, hash: u64, files: Range<usize>) {
+        self.dirs.push((hash, files));
+    }
+
+    pub fn finish(mut self) -> Result<Table, Invalid> {
+        let paths = self.paths.strings();
+        let hasher = detect_hasher(&self.dirs, paths);
+        tracing::trace!("detected path hasher {hasher:?}");
+
+        let mut tree = TreeBuilder::new(hasher, paths);
+        for (hash, files) in &self.dirs {
+            tree.insert(*hash, files.clone());
+        }
+        let dirs = tree.finish();
+
+        // Sorting is stable, after reversing the last of multiple files with the same hash
+        // comes first and is kept.
+        self.files.reverse();
+        self.files.sort_by_key(|(hash, _)| *hash);
+        self.files.dedup_by_key(|(hash, _)| *hash);
+
+        let children = dirs.iter().map(|dir| dir.children.len()).sum::<usize>();
+        let header = [
+            match hasher {
+                PathHasher::Fnv1a => 0,
+                PathHasher::Murmur64A => 1,
+            },
+            self.files.len(),
+            dirs.len(),
+            children,
+            self.bundles.strings().len(),
+            self.paths.strings().len(),
+            self.bundles.data.len(),
+            self.paths.data.len(),
+        ];
+
+        let size = HEADER_SIZE
+            + self.files.len() * FILE_SIZE
+            + dirs.len() * DIR_SIZE
+            + children * 4
+            + self.bundles.ends.len()
+            + self.bundles.data.len()
+            + self.paths.ends.len()
+            + self.paths.data.len();
+        let mut data = Vec::with_capacity(size);
+
+        for value in header {
+            data.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        for (hash, fref) in &self.files {
+            data.extend_from_slice(&hash.to_le_bytes());
+            for value in [fref.bundle_index, fref.file_offset, fref.file_size] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        let mut first_child = 0;
+        for dir in &dirs {
+            data.extend_from_slice(&dir.hash.to_le_bytes());
+            let children = first_child..first_child + dir.children.len();
+            first_child = children.end;
+            let fields = [
+                dir.path,
+                dir.name_len,
+                dir.files.start,
+                dir.files.end,
+                children.start,
+                children.end,
+            ];
+            for value in fields {
+                data.extend_from_slice(&(value as u32).to_le_bytes());
+            }
+        }
+        for child in dirs.iter().flat_map(|dir| &dir.children) {
+            data.extend_from_slice(&(*child as u32).to_le_bytes());
+        }
+        for arena in [self.bundles, self.paths] {
+            data.extend_from_slice(&arena.ends);
+            data.extend_from_slice(&arena.data);
+        }
+
+        Table::new(data)
+    }
+}
+
+/// Detects the path hash algorithm by checking the hash of a known directory.
+///
+/// The name of a directory is derived from the paths of its files,
+/// the hasher which reproduces the hash of the path representation wins.
+fn detect_hasher(dirs: &[(u64, Range<usize>)], paths: Strings<'_>) -> PathHasher {
+    let known = dirs.iter().find_map(|(hash, files)| {
+        if files.is_empty() || files.start >= paths.len() {
+            return None;
+        }
+        let path = paths.get(files.start);
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        Some((*hash, dir))
+    });
+
+    let Some((hash, dir)) = known else {
+        return PathHasher::default();
+    };
+
+    PathHasher::ALL
+        .into_iter()
+        .find(|hasher| hasher.directory(dir) == hash)
+        .unwrap_or_else(|| {
+            tracing::warn!("unknown path hash algorithm for directory '{dir}'");
+            PathHasher::default()
+        })
+}
+
+/// Binary search for a hash in records of `size` bytes starting with the hash.
+fn search(records: &[u8], size: usize, hash: u64) -> Option<usize> {
+    let (mut low, mut high) = (0, records.len() / size);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match u64_at(records, mid * size).cmp(&hash) {
+            std::cmp::Ordering::Less => low = mid + 1,
+            std::cmp::Ordering::Greater => high = mid,
+            std::cmp::Ordering::Equal => return Some(mid),
+        }
+    }
+    None
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fref(bundle_index: u32, file_offset: u32) -> FileRef {
+        FileRef {
+            bundle_index,
+            file_offset,
+            file_size: 10,
+        }
+    }
+
+    /// Table of two bundles with files in `Data/` and `Data/Simplified/`.
+    fn table() -> Table {
+        let hasher = PathHasher::Murmur64A;
+        let mut table = TableBuilder::default();
+        table.bundle("a");
+        table.bundle("b");
+
+        let paths = [
+            "Data/Mods.dat64",
+            "Data/Words.dat64",
+            "Data/Simplified/Mods.dat64",
+        ];
+        for (i, path) in paths.iter().enumerate() {
+            table.file(hasher.file(path), fref(i as u32 % 2, i as u32 * 10));
+        }
+
+        table.path(paths[0]);
+        table.path(paths[1]);
+        table.dir(hasher.directory("Data"), 0..2);
+        let first = table.path(paths[2]);
+        table.dir(hasher.directory("Data/Simplified"), first..first + 1);
+
+        table.finish().unwrap()
+    }
+
+    #[test]
+    fn lookups() {
+        let table = table();
+        let hasher = table.hasher();
+        assert_eq!(hasher, PathHasher::Murmur64A);
+
+        assert_eq!(table.bundles().iter().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(table.paths().len(), 3);
+        assert_eq!(table.paths().get(2), "Data/Simplified/Mods.dat64");
+
+        assert_eq!(
+            table.file(hasher.file("Data/Words.dat64")),
+            Some(fref(1, 10))
+        );
+        assert_eq!(
+            table.file(hasher.file("Data/Simplified/Mods.dat64")),
+            Some(fref(0, 20))
+        );
+        assert_eq!(table.file(hasher.file("Data/Missing.dat64")), None);
+        assert_eq!(table.files().len(), 3);
+
+        let root = table.dir(hasher.directory("")).unwrap();
+        assert_eq!(root.name, "");
+        let [data] = &table.children(&root).collect::<Vec<_>>()[..] else {
+            panic!("expected a single child of the root");
+        };
+        assert_eq!(data.name, "Data");
+        assert_eq!(data.files, 0..2);
+        let children = table.children(data).map(|dir| dir.name).collect::<Vec<_>>();
+        assert_eq!(children, ["Data/Simplified"]);
+        assert_eq!(table.dir_count(), 3);
+    }
+
+    #[test]
+    fn later_files_replace_earlier_files() {
+        let mut table = TableBuilder::default();
+        table.bundle("a");
+        table.file(1, fref(0, 0));
+        table.file(2, fref(0, 10));
+        table.file(1, fref(0, 20));
+        let table = table.finish().unwrap();
+
+        assert_eq!(table.files().len(), 2);
+        assert_eq!(table.file(1), Some(fref(0, 20)));
+    }
+
+    #[test]
+    fn rejects_invalid_tables() {
+        let data = table().as_bytes().to_vec();
+        let patched = |offset: usize, value: u32| {
+            let mut data = data.clone();
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            Table::new(data).err().map(|err| err.reason)
+        };
+
+        for len in 0..data.len() {
+            assert!(Table::new(data[..len].to_vec()).is_err());
+        }
+
+        let files = HEADER_SIZE;
+        let dirs = files + 3 * FILE_SIZE;
+        assert_eq!(patched(0, 2), Some("unknown path hash algorithm"));
+        assert_eq!(patched(files + 8, 2), Some("bundle index out of bounds"));
+        assert_eq!(patched(dirs + 8, 3), Some("directory name out of bounds"));
+        assert_eq!(patched(dirs + 20, 4), Some("directory files out of bounds"));
+        assert_eq!(
+            patched(dirs + 28, 3),
+            Some("directory children out of bounds")
+        );
+
+        // A directory which is its own child.
+        let root = search(
+            &data[dirs..dirs + 3 * DIR_SIZE],
+            DIR_SIZE,
+            PathHasher::Murmur64A.directory(""),
+        )
+        .unwrap();
+        let children = dirs + 3 * DIR_SIZE;
+        assert_eq!(
+            patched(children, root as u32),
+            Some("invalid child directory")
+        );
+
+        let mut unsorted = data.clone();
+        unsorted.copy_within(files..files + 8, files + FILE_SIZE);
+        assert_eq!(
+            Table::new(unsorted).err().map(|err| err.reason),
+            Some("files are not sorted")
+        );
+
+        let mut data = data.clone();
+        let bundles = children + 2 * 4;
+        data[bundles + 8] = 0xff;
+        assert_eq!(
+            Table::new(data).err().map(|err| err.reason),
+            Some("string is not valid UTF-8")
+        );
+    }
+
+    #[test]
+    fn detect_path_hasher_falls_back_to_fnv1a() {
+        let detect = |dirs: &[(u64, Range<usize>)], paths: &[&str]| {
+            let mut arena = ArenaBuilder::default();
+            for path in paths {
+                arena.push(path);
+            }
+            detect_hasher(dirs, arena.strings())
+        };
+
+        assert_eq!(detect(&[], &[]), PathHasher::Fnv1a);
+
+        let paths = ["Data/Mods.dat64"];
+        assert_eq!(detect(&[(0, 0..0)], &paths), PathHasher::Fnv1a);
+        assert_eq!(detect(&[(1234, 0..1)], &paths), PathHasher::Fnv1a);
+        assert_eq!(
+            detect(&[(PathHasher::Murmur64A.directory("Data"), 0..1)], &paths),
+            PathHasher::Murmur64A
+        );
+        assert_eq!(
+            detect(&[(PathHasher::Fnv1a.directory("Data"), 0..1)], &paths),
+            PathHasher::Fnv1a
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Helpers to create bundles for tests and to serve them over HTTP.
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::{BundleWriter, IndexWriter};
+use crate::PathHasher;
+
+/// Uncompressed chunk size of test bundles, small enough to have multiple chunks per file.
+pub const CHUNK_SIZE: usize = 0x1000;
+
+/// Deterministic file contents which differ for every seed.
+pub fn contents(seed: u8, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u32).wrapping_mul(31).wrapping_add(seed as u32 * 7) as u8)
+        .collect()
+}
+
+/// A set of bundles and their index.
+pub struct Fixture {
+    hasher: PathHasher,
+    bundles: Vec<(String, BundleWriter)>,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self {
+            hasher: PathHasher::Murmur64A,
+            bundles: Vec::new(),
+        }
+    }
+
+    /// Sets the path hash algorithm of the index, defaults to Murmur64A.
+    pub fn hasher(mut self, hasher: PathHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Adds a bundle with the given files.
+    pub fn bundle(mut self, name: &str, files: &[(&str, &[u8])]) -> Self {
+        let mut bundle = BundleWriter::new().chunk_size(CHUNK_SIZE);
+        for (path, data) in files {
+            bundle.add(*path, data);
+        }
+        self.bundles.push((name.to_owned(), bundle));
+        self
+    }
+
+    /// Encodes the index and all bundles, keyed by their path relative to the game directory.
+    pub fn files(&self) -> HashMap<String, Vec<u8>> {
+        let mut files = HashMap::new();
+        let mut index = IndexWriter::new(self.hasher);
+
+        for (name, bundle) in &self.bundles {
+            let mut data = Vec::new();
+            bundle.write(&mut data).unwrap();
+            files.insert(format!("Bundles2/{name}.bundle.bin"), data);
+            index.add_bundle(name, bundle);
+        }
+
+        let mut data = Vec::new();
+        index.write(&mut data).unwrap();
+        files.insert("Bundles2/_.index.bin".to_owned(), data);
+
+        files
+    }
+
+    /// Writes the index and all bundles to `dir`.
+    pub fn write(&self, dir: &Path) {
+        for (name, data) in self.files() {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+    }
+
+    /// Writes the index and all bundles to a new temporary directory.
+    pub fn tempdir(&self) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        self.write(dir.path());
+        dir
+    }
+}
+
+#[cfg(feature = "web")]
+pub use self::server::*;
+
+#[cfg(feature = "web")]
+mod server {
+    use std::collections::{HashMap, VecDeque};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// A request received by the [`Server`].
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Request {
+        pub path: String,
+        /// Requested byte range, the end is exclusive.
+        pub range: Option<(u64, Option<u64>)>,
+    }
+
+    /// Misbehaviour of the [`Server`] for a single request.
+    #[derive(Debug, Clone, Copy)]
+    pub enum Fault {
+        /// Closes the connection after sending this many bytes of the body.
+        Drop(usize),
+        /// Responds with an empty body and this status.
+        Status(u16),
+        /// Accepts the connection and never responds.
+        Hang,
+    }
+
+    #[derive(Default)]
+    struct State {
+        files: HashMap<String, Vec<u8>>,
+        requests: Vec<Request>,
+        faults: VecDeque<Fault>,
+        ranges: bool,
+        content_length: bool,
+    }
+
+    /// Minimal HTTP server standing in for the CDN.
+    pub struct Server {
+        pub url: String,
+        state: Arc<Mutex<State>>,
+    }
+
+    impl Server {
+        /// Serves `files` by their path, with support for range requests.
+        pub fn new(files: HashMap<String, Vec<u8>>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/", listener.local_addr().unwrap());
+
+            let state = Arc::new(Mutex::new(State {
+                files,
+                ranges: true,
+                content_length: true,
+                ..Default::default()
+            }));
+
+            let server = Arc::clone(&state);
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let state = Arc::clone(&server);
+                    std::thread::spawn(move || handle(&state, stream));
+                }
+            });
+
+            Self { url, state }
+        }
+
+        /// Ignores `Range` headers and always sends the entire file with status `200`.
+        pub fn without_ranges(self) -> Self {
+            self.state.lock().unwrap().ranges = false;
+            self
+        }
+
+        /// Omits the `Content-Length`, the end of the body is only marked by closing the connection.
+        pub fn without_content_length(self) -> Self {
+            self.state.lock().unwrap().content_length = false;
+            self
+        }
+
+        /// Misbehaves for the next request, faults are applied in order.
+        pub fn fault(&self, fault: Fault) {
+            self.state.lock().unwrap().faults.push_back(fault);
+        }
+
+        /// All requests received so far.
+        pub fn requests(&self) -> Vec<Request> {
+            self.state.lock().unwrap().requests.clone()
+        }
+    }
+
+    fn handle(state: &Mutex<State>, mut stream: TcpStream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() {
+            return;
+        }
+        let path = line.split(' ').nth(1).unwrap_or_default();
+        let path = path.trim_start_matches('/').to_owned();
+
+        let mut range = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+                break;
+            }
+            let Some((name, value)) = header.split_once(':') else {
+                continue;
+            };
+            if name.eq_ignore_ascii_case("range") {
+                range = value.trim().strip_prefix("bytes=").and_then(parse_range);
+            }
+        }
+
+        let (data, fault, ranges, content_length) = {
+            let mut state = state.lock().unwrap();
+            state.requests.push(Request {
+                path: path.clone(),
+                range,
+            });
+            let fault = state.faults.pop_front();
+            (
+                state.files.get(&path).cloned(),
+                fault,
+                state.ranges,
+                state.content_length,
+            )
+        };
+
+        let status = match (fault, &data) {
+            (Some(Fault::Hang), _) => {
+                std::thread::sleep(Duration::from_secs(5));
+                return;
+            }
+            (Some(Fault::Status(status)), _) => status,
+            (_, None) => 404,
+            _ => 200,
+        };
+        let Some(data) = data.filter(|_| status == 200) else {
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {status} Error\r\nContent-Length: 0\r\n\r\n"
+            );
+            return;
+        };
+
+        let len = data.len() as u64;
+        let (status, body, content_range) = match range.filter(|_| ranges) {
+            Some((start, end)) => {
+                let end = end.unwrap_or(len).min(len);
+                let start = start.min(end);
+                let content_range = format!("Content-Range: bytes {start}-{}/{len}\r\n", end - 1);
+                (
+                    "206 Partial Content",
+                    &data[start as usize..end as usize],
+                    content_range,
+                )
+            }
+            None => ("200 OK", &data[..], String::new()),
+        };
+
+        let _ = write!(stream, "HTTP/1.1 {status}\r\n{content_range}");
+        if content_length {
+            let _ = write!(stream, "Content-Length: {}\r\n", body.len());
+        }
+        let _ = write!(stream, "Connection: close\r\n\r\n");
+
+        let body = match fault {
+            Some(Fault::Drop(n)) => &body[..n.min(body.len())],
+            _ => body,
+        };
+        let _ = stream.write_all(body);
+        let _ = stream.flush();
+    }
+
+    /// Parses the value of a `Range` header without the `bytes=` prefix.
+    fn parse_range(range: &str) -> Option<(u64, Option<u64>)> {
+        let (start, end) = range.split_once('-')?;
+        let end = match end {
+            "" => None,
+            end => Some(end.parse::<u64>().ok()? + 1),
+        };
+        Some((start.parse().ok()?, end))
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::table::{Directory, Strings, Table};
+use crate::PathHasher;
+
+/// An entry of a bundled directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirEntry<'a> {
+    /// Full path of a sub directory, without a trailing slash.
+    Directory(&'a str),
+    /// Full path of a file.
+    File(&'a str),
+}
+
+/// Directory tree of the index, stored in its [`Table`].
+pub(crate) struct Tree<'a> {
+    table: &'a Table,
+}
+
+impl<'a> Tree<'a> {
+    pub fn new(table: &'a Table) -> Self {
+        Self { table }
+    }
+
+    /// Lists the direct children of a directory.
+    pub fn list(&self, dir: &str) -> Option<impl Iterator<Item = DirEntry<'a>> + 'a> {
+        let dir = self.get(dir)?;
+
+        let dirs = self
+            .table
+            .children(&dir)
+            .map(|child| DirEntry::Directory(child.name));
+        let files = self.table.paths().range(dir.files).map(DirEntry::File);
+
+        Some(dirs.chain(files))
+    }
+
+    /// Finds all files matching a glob pattern.
+    ///
+    /// Patterns are matched per path segment, `*` matches any amount of characters
+    /// and `?` exactly one character within a segment, `**` matches any amount of directories.
+    pub fn glob(&self, pattern: &str) -> Vec<&'a str> {
+        let segments = pattern.split('/').collect::<Vec<_>>();
+
+        // Skip ahead to the longest literal prefix, it can be looked up directly.
+        let literal = segments[..segments.len() - 1]
+            .iter()
+            .take_while(|s| !is_pattern(s))
+            .count();
+        let (prefix, segments) = segments.split_at(literal);
+
+        let mut result = Vec::new();
+        if let Some(dir) = self.get(&prefix.join("/")) {
+            self.glob_dir(&dir, segments, &mut result);
+        }
+        result
+    }
+
+    fn glob_dir(&self, dir: &Directory<'a>, segments: &[&str], result: &mut Vec<&'a str>) {
+        let paths = self.table.paths();
+        let children = self.table.children(dir);
+
+        match segments {
+            [] => {}
+            ["**"] => {
+                result.extend(paths.range(dir.files.clone()));
+                for child in children {
+                    self.glob_dir(&child, segments, result);
+                }
+            }
+            [last] => {
+                let files = paths.range(dir.files.clone());
+                result.extend(files.filter(|path| wildcard_match(last, file_name(path))));
+            }
+            ["**", rest @ ..] => {
+                self.glob_dir(dir, rest, result);
+                for child in children {
+                    self.glob_dir(&child, segments, result);
+                }
+            }
+            [segment, rest @ ..] => {
+                for child in children.filter(|c| wildcard_match(segment, file_name(c.name))) {
+                    self.glob_dir(&child, rest, result);
+                }
+            }
+        }
+    }
+
+    fn get(&self, dir: &str) -> Option<Directory<'a>> {
+        self.table.dir(self.table.hasher().directory(dir))
+    }
+}
+
+/// Directory while building the tree, see [`TreeBuilder`].
+#[derive(Debug)]
+pub(crate) struct Node {
+    pub hash: u64,
+    /// Index of a path which starts with the name of the directory.
+    pub path: usize,
+    pub name_len: usize,
+    /// Range of the directory's files in the reconstructed path list.
+    pub files: Range<usize>,
+    pub children: Vec<usize>,
+}
+
+/// Builds the directory tree from the directory hashes of the path representations.
+pub(crate) struct TreeBuilder<'a> {
+    hasher: PathHasher,
+    paths: Strings<'a>,
+    nodes: Vec<Node>,
+    by_hash: HashMap<u64, usize>,
+}
+
+impl<'a> TreeBuilder<'a> {
+    pub fn new(hasher: PathHasher, paths: Strings<'a>) -> Self {
+        Self {
+            hasher,
+            paths,
+            nodes: Vec::new(),
+            by_hash: HashMap::new(),
+        }
+    }
+
+    /// Inserts the directory of a path representation.
+    ///
+    /// All files of the directory must be stored continuously in `files` of the path list,
+    /// the name of the directory is derived from the first file.
+    pub fn insert(&mut self, hash: u64, files: Range<usize>) {
+        if files.start >= self.paths.len() {
+            return;
+        }
+        let path = files.start;
+
+        let name = parent(self.paths.get(path));
+        let idx = self.get_or_insert(hash, path, name);
+        self.nodes[idx].files = files;
+
+        let mut name = name;
+        let mut idx = idx;
+        while !name.is_empty() {
+            name = parent(name);
+
+            let hash = self.hasher.directory(name);
+            let existed = self.by_hash.contains_key(&hash);
+            let parent_idx = self.get_or_insert(hash, path, name);
+            // Children always have longer names than their parent, even if hashes collide.
+            let (parent, child) = (&self.nodes[parent_idx], &self.nodes[idx]);
+            if parent.name_len < child.name_len && !parent.children.contains(&idx) {
+                self.nodes[parent_idx].children.push(idx);
+            }
+
+            if existed {
+                break;
+            }
+            idx = parent_idx;
+        }
+    }
+
+    /// Returns all directories sorted by hash, children are positions in the returned list.
+    pub fn finish(self) -> Vec<Node> {
+        let mut order = (0..self.nodes.len()).collect::<Vec<_>>();
+        order.sort_unstable_by_key(|&idx| self.nodes[idx].hash);
+        let mut position = vec![0; order.len()];
+        for (pos, &idx) in order.iter().enumerate() {
+            position[idx] = pos;
+        }
+
+        let mut nodes = self.nodes;
+        for node in &mut nodes {
+            for child in &mut node.children {
+                *child = position[*child];
+            }
+        }
+        // Hashes are unique, sorting results in the same order.
+        nodes.sort_unstable_by_key(|node| node.hash);
+        nodes
+    }
+
+    fn get_or_insert(&mut self, hash: u64, path: usize, name: &str) -> usize {
+        *self.by_hash.entry(hash).or_insert_with(|| {
+            self.nodes.push(Node {
+                hash,
+                path,
+                name_len: name.len(),
+                files: 0..0,
+                children: Vec::new(),
+            });
+            self.nodes.len() - 1
+        })
+    }
+}
+
+/// Matches a full path against a glob pattern, with the same rules as [`Tree::glob`].
+pub(crate) fn glob_match(pattern: &str, path: &str) -> bool {
+    let segments = pattern.split('/').collect::<Vec<_>>();
+    let parts = path.split('/').collect::<Vec<_>>();
+    match_segments(&segments, &parts)
+}
+
+fn match_segments(segments: &[&str], parts: &[&str]) -> bool {
+    match (segments, parts) {
+        ([], []) => true,
+        (["**"], parts) => !parts.is_empty(),
+        (["**", rest @ ..], parts) => {
+            match_segments(rest, parts)
+                || (parts.len() > 1 && match_segments(segments, &parts[1..]))
+        }
+        ([segment, rest @ ..], [part, parts @ ..]) => {
+            wildcard_match(segment, part) && match_segments(rest, parts)
+        }
+        _ => false,
+    }
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit_once('/').map_or(path, |(_, name)| name)
+}
+
+fn is_pattern(segment: &str) -> bool {
+    segment.contains(['*', '?'])
+}
+
+/// Matches a single path segment against a pattern containing `*` and `?` wildcards.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern and the text position it was matched at.
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp + 1;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::testutil::Fixture;
+    use crate::bundle::{Bundle, IndexBundle, LocalBundleFs};
+
+    fn index(dir: &std::path::Path) -> IndexBundle<LocalBundleFs> {
+        Bundle::new(LocalBundleFs::new(dir)).into_index().unwrap()
+    }
+
+    fn fixture() -> tempfile::TempDir {
+        Fixture::new()
+            .bundle(
+                "Data",
+                &[
+                    ("Data/Mods.dat64", b"mods"),
+                    ("Data/Words.dat64", b"words"),
+                    ("Data/Simplified/Mods.dat64", b"mods"),
+                ],
+            )
+            .bundle(
+                "Art",
+                &[
+                    ("Art/2DItems/Rings/Ring1.dds", b"ring1"),
+                    ("Art/2DItems/Rings/Ring2.dds", b"ring2"),
+                    ("Art/2DItems/Rings/Ring10.dds", b"ring10"),
+                    ("Art/2DItems/Amulets/Amulet1.dds", b"amulet1"),
+                    ("Art/2DArt/Ring1.dds", b"ring1"),
+                ],
+            )
+            .bundle("Root", &[("README.txt", b"readme")])
+            .tempdir()
+    }
+
+    fn list(index: &IndexBundle<LocalBundleFs>, dir: &str) -> Option<Vec<String>> {
+        let mut entries = index
+            .list(dir)?
+            .map(|entry| match entry {
+                DirEntry::Directory(dir) => format!("{dir}/"),
+                DirEntry::File(file) => file.to_owned(),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_unstable();
+        Some(entries)
+    }
+
+    fn glob<'a>(index: &'a IndexBundle<LocalBundleFs>, pattern: &str) -> Vec<&'a str> {
+        let mut files = index.glob(pattern);
+        files.sort_unstable();
+        files
+    }
+
+    #[test]
+    fn list_root() {
+        let dir = fixture();
+        let index = index(dir.path());
+
+        assert_eq!(list(&index, "").unwrap(), ["Art/", "Data/", "README.txt"]);
+    }
+
+    #[test]
+    fn list_nested_directories() {
+        let dir = fixture();
+        let index = index(dir.path());
+
+        assert_eq!(list(&index, "Art").unwrap(), ["Art/2DArt/", "Art/2DItems/"]);
+        assert_eq!(
+            list(&index, "Art/2DItems/").unwrap(),
+            ["Art/2DItems/Amulets/", "Art/2DItems/Rings/"]
+        );
+        assert_eq!(
+            list(&index, "Data").unwrap(),
+            ["Data/Mods.dat64", "Data/Simplified/", "Data/Words.dat64"]
+        );
+        assert_eq!(
+            list(&index, "Art/2DItems/Rings").unwrap(),
+            [
+                "Art/2DItems/Rings/Ring1.dds",
+                "Art/2DItems/Rings/Ring10.dds",
+                "Art/2DItems/Rings/Ring2.dds",
+            ]
+        );
+    }
+
+    #[test]
+    fn list_missing_directory() {
+        let dir = fixture();
+        let index = index(dir.path());
+
+        assert_eq!(list(&index, "Missing"), None);
+        assert_eq!(list(&index, "Art/2DItems/Rings/Ring1.dds"), None);
+        assert_eq!(list(&index, "Art/2DItems/Missing"), None);
+    }
+
+    #[test]
+    fn glob_star_stays_within_a_segment() {
+        let dir = fixture();
+        let index = index(dir.path());
+
+        assert_eq!(
+            glob(&index, "Data/*"),
+            ["Data/Mods.dat64", "Data/Words.dat64"]
+        );
+        assert_eq!(glob(&index, "*.txt"), ["README.txt"]);
+        assert_eq!(glob(&index, "Art/*.dds"), Vec::<&str>::new());
+        assert_eq!(glob(&index, "Art/*/Ring1.dds"), ["Art/2DArt/Ring1.dds"]);
+        assert_eq!(
+            glob(&index, "Art/2D*/*/*1.dds"),
+            [
+                "Art/2DItems/Amulets/Amulet1.dds",
+                "Art/2DItems/Rings/Ring1.dds"
+            ]
+        );
+    }
+
+    #[test]
+    fn glob_question_mark_matches_one_character() {
+        let dir = fixture();
+        let index = index(dir.path());
+
+        assert_eq!(
+            glob(&index, "Art/2DItems/Rings/Ring?.dds"),
+            ["Art/2DItems/Rings/Ring1.dds", "Art/2DItems/Rings/Ring2.dds"]
+        );
+        assert_eq!(
+            glob(&index, "Art/2DItems/Rings/Ring??.dds"),
+            ["Art/2DItems/Rings/Ring10.dds"]
+        );
+        assert_eq!(glob(&index, "Data/?ods.dat64"), ["Data/Mods.dat64"]);
+    }
+
+    #[test]
+    fn glob_double_star_matches_directories() {
+        let dir = fixture();
+        let index = index(dir.path());
+
+        assert_eq!(
+            glob(&index, "**/Mods.dat64"),
+            ["Data/Mods.dat64", "Data/Simplified/Mods.dat64"]
+        );
+        assert_eq!(
+            glob(&index, "Art/**/Ring1.dds"),
+            ["Art/2DArt/Ring1.dds", "Art/2DItems/Rings/Ring1.dds"]
+        );
+        assert_eq!(
+            glob(&index, "Data/**"),
+            [
+                "Data/Mods.dat64",
+                "Data/Simplified/Mods.dat64",
+                "Data/Words.dat64"
+            ]
+        );
+        assert_eq!(glob(&index, "**").len(), 9);
+        assert_eq!(glob(&index, "Missing/**"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn glob_match_agrees_with_the_tree() {
+        let dir = fixture();
+        let index = index(dir.path());
+
+        for pattern in [
+            "Data/*",
+            "*.txt",
+            "Art/*.dds",
+            "Art/*/Ring1.dds",
+            "Art/2DItems/Rings/Ring?.dds",
+            "**/Mods.dat64",
+            "Art/**/Ring1.dds",
+            "Data/**",
+            "**",
+            "**/*1.dds",
+        ] {
+            let mut expected = index
+                .paths()
+                .filter(|path| glob_match(pattern, path))
+                .collect::<Vec<_>>();
+            expected.sort_unstable();
+            assert_eq!(glob(&index, pattern), expected, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "abc"));
+        assert!(wildcard_match("a*c", "abbbc"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(!wildcard_match("a*c", "abcd"));
+        assert!(wildcard_match("?", "a"));
+        assert!(!wildcard_match("?", ""));
+        assert!(!wildcard_match("?", "ab"));
+        assert!(wildcard_match("abc", "abc"));
+        assert!(!wildcard_match("abc", "abd"));
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use byteorder::{WriteBytesExt, LE};
+use libooz_sys::Compressor;
+
+use super::{ooz, BundleResult};
+use crate::PathHasher;
+
+/// Uncompressed size of a chunk in bundles of the game.
+const CHUNK_UNPACKED_SIZE: usize = 0x40000;
+
+/// Creates `.bundle.bin` files.
+///
+/// Files are appended to the uncompressed contents of the bundle,
+/// which are split into chunks and compressed when the bundle is written.
+#[derive(Debug)]
+pub struct BundleWriter {
+    compressor: Compressor,
+    level: i32,
+    chunk_unpacked_size: usize,
+    data: Vec<u8>,
+    /// Path, offset and size of every file in the bundle.
+    files: Vec<(String, usize, usize)>,
+}
+
+impl Default for BundleWriter {
+    fn default() -> Self {
+        Self {
+            compressor: Compressor::default(),
+            level: 4,
+            chunk_unpacked_size: CHUNK_UNPACKED_SIZE,
+            data: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+}
+
+impl BundleWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the compressor, defaults to Leviathan which is also used by the game.
+    pub fn compressor(mut self, compressor: Compressor) -> Self {
+        self.compressor = compressor;
+        self
+    }
+
+    /// Sets the compression level, higher levels compress better but slower. Defaults to `4`.
+    pub fn level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Sets the uncompressed size of a chunk, defaults to 256 KiB.
+    pub fn chunk_size(mut self, chunk_unpacked_size: usize) -> Self {
+        assert!(chunk_unpacked_size > 0, "chunk size must not be zero");
+        self.chunk_unpacked_size = chunk_unpacked_size;
+        self
+    }
+
+    /// Appends a file to the bundle and returns its offset in the uncompressed bundle.
+    pub fn add(&mut self, path: impl Into<String>, contents: &[u8]) -> usize {
+        let offset = self.data.len();
+        self.data.extend_from_slice(contents);
+        self.files.push((path.into(), offset, contents.len()));
+        offset
+    }
+
+    /// Uncompressed size of the bundle.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Compresses the bundle and writes the header followed by all compressed chunks.
+    pub fn write(&self, writer: &mut impl Write) -> BundleResult<()> {
+        let chunks = self
+            .data
+            .chunks(self.chunk_unpacked_size)
+            .map(|chunk| ooz::compress(chunk, self.compressor, self.level))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let compressed_size = chunks.iter().map(|chunk| chunk.len()).sum::<usize>();
+        let head_payload_size = 48 + chunks.len() * std::mem::size_of::<u32>();
+
+        // See `parse::Head` and `parse::HeadPayload`.
+        writer.write_u32::<LE>(self.data.len() as u32)?;
+        writer.write_u32::<LE>(compressed_size as u32)?;
+        writer.write_u32::<LE>(head_payload_size as u32)?;
+        writer.write_u32::<LE>(self.compressor as u32)?;
+        writer.write_u32::<LE>(1)?;
+        writer.write_u64::<LE>(self.data.len() as u64)?;
+        writer.write_u64::<LE>(compressed_size as u64)?;
+        writer.write_u32::<LE>(chunks.len() as u32)?;
+        writer.write_u32::<LE>(self.chunk_unpacked_size as u32)?;
+        writer.write_all(&[0; 16])?;
+        for chunk in &chunks {
+            writer.write_u32::<LE>(chunk.len() as u32)?;
+        }
+
+        for chunk in &chunks {
+            writer.write_all(chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates a `_.index.bin` for a set of bundles.
+#[derive(Debug)]
+pub struct IndexWriter {
+    hasher: PathHasher,
+    /// Name and uncompressed size of every bundle.
+    bundles: Vec<(String, usize)>,
+    /// Path, bundle index, offset and size of every file.
+    files: Vec<(String, usize, usize, usize)>,
+}
+
+impl IndexWriter {
+    /// Creates an empty index, paths are hashed with `hasher`.
+    pub fn new(hasher: PathHasher) -> Self {
+        Self {
+            hasher,
+            bundles: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Adds all files of a bundle to the index.
+    ///
+    /// The name of the bundle is its path relative to `Bundles2/` without the `.bundle.bin` suffix.
+    pub fn add_bundle(&mut self, name: impl Into<String>, bundle: &BundleWriter) {
+        let index = self.bundles.len();
+        self.bundles.push((name.into(), bundle.len()));
+
+        for (path, offset, size) in &bundle.files {
+            self.files.push((path.clone(), index, *offset, *size));
+        }
+    }
+
+    /// Writes the index, it is stored as a bundle itself.
+    pub fn write(&self, writer: &mut impl Write) -> BundleResult<()> {
+        let mut data = Vec::new();
+
+        data.write_u32::<LE>(self.bundles.len() as u32)?;
+        for (name, size) in &self.bundles {
+            data.write_u32::<LE>(name.len() as u32)?;
+            data.write_all(name.as_bytes())?;
+            data.write_u32::<LE>(*size as u32)?;
+        }
+
+        data.write_u32::<LE>(self.files.len() as u32)?;
+        for (path, bundle, offset, size) in &self.files {
+            data.write_u64::<LE>(self.hasher.file(path))?;
+            data.write_u32::<LE>(*bundle as u32)?;
+            data.write_u32::<LE>(*offset as u32)?;
+            data.write_u32::<LE>(*size as u32)?;
+        }
+
+        let mut dirs = BTreeMap::<&str, Vec<&str>>::new();
+        for (path, ..) in &self.files {
+            let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+            dirs.entry(dir).or_default().push(name);
+        }
+
+        // Every directory is stored with its path as the only base, see `parse::parse_paths`.
+        let mut payload = Vec::new();
+        data.write_u32::<LE>(dirs.len() as u32)?;
+        for (dir, names) in dirs {
+            let offset = payload.len();
+
+            payload.write_u32::<LE>(0)?;
+            payload.write_u32::<LE>(1)?;
+            match dir.is_empty() {
+                true => payload.write_u8(0)?,
+                false => write!(payload, "{dir}/\0")?,
+            }
+            payload.write_u32::<LE>(0)?;
+            for name in names {
+                payload.write_u32::<LE>(1)?;
+                write!(payload, "{name}\0")?;
+            }
+
+            let size = payload.len() - offset;
+            data.write_u64::<LE>(self.hasher.directory(dir))?;
+            data.write_u32::<LE>(offset as u32)?;
+            data.write_u32::<LE>(size as u32)?;
+            data.write_u32::<LE>(size as u32)?;
+        }
+
+        let mut paths = BundleWriter::new();
+        paths.add("", &payload);
+        paths.write(&mut data)?;
+
+        let mut index = BundleWriter::new();
+        index.add("", &data);
+        index.write(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::testutil::{contents, CHUNK_SIZE};
+    use crate::bundle::{parse, Bundle, LocalBundleFs};
+
+    #[test]
+    fn bundle_round_trip() {
+        let files = [
+            contents(1, CHUNK_SIZE * 3 + 17),
+            contents(2, 0),
+            contents(3, 100),
+            vec![0; CHUNK_SIZE * 2],
+        ];
+
+        for compressor in [
+            Compressor::Kraken,
+            Compressor::Mermaid,
+            Compressor::Leviathan,
+        ] {
+            let mut writer = BundleWriter::new()
+                .compressor(compressor)
+                .chunk_size(CHUNK_SIZE);
+            for (i, file) in files.iter().enumerate() {
+                writer.add(format!("{i}.bin"), file);
+            }
+            let mut bundle = Vec::new();
+            writer.write(&mut bundle).unwrap();
+
+            let (chunks, head) = parse::Head::parse(&bundle).unwrap();
+            let expected = files.concat();
+            let payload = &head.payload;
+            assert_eq!(payload.uncompressed_size, expected.len() as u64);
+            assert_eq!(payload.chunk_unpacked_size, CHUNK_SIZE as u32);
+            assert_eq!(
+                payload.chunk_count,
+                expected.len().div_ceil(CHUNK_SIZE) as u32
+            );
+            assert_eq!(payload.compressed_size, chunks.len() as u64);
+
+            let data =
+                ooz::decompress_slice(chunks, CHUNK_SIZE, &payload.chunk_sizes, 0, expected.len())
+                    .unwrap();
+            assert_eq!(data, expected, "{compressor:?}");
+        }
+    }
+
+    #[test]
+    fn index_round_trip() {
+        let files = [
+            ("Data/BaseItemTypes.dat64", contents(1, 5000)),
+            ("Data/Words.dat64", contents(2, 300)),
+            ("Art/2DItems/Rings/Ring1.dds", contents(3, CHUNK_SIZE * 2)),
+            ("README.txt", contents(4, 10)),
+        ];
+
+        for hasher in PathHasher::ALL {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::create_dir(dir.path().join("Bundles2")).unwrap();
+
+            let mut index = IndexWriter::new(hasher);
+            for (name, files) in [("Data", &files[..2]), ("Art/Items", &files[2..])] {
+                let mut bundle = BundleWriter::new().chunk_size(CHUNK_SIZE);
+                for (path, data) in files {
+                    bundle.add(*path, data);
+                }
+
+                let path = dir.path().join(format!("Bundles2/{name}.bundle.bin"));
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                bundle
+                    .write(&mut std::fs::File::create(path).unwrap())
+                    .unwrap();
+                index.add_bundle(name, &bundle);
+            }
+            let path = dir.path().join("Bundles2/_.index.bin");
+            index
+                .write(&mut std::fs::File::create(path).unwrap())
+                .unwrap();
+
+            let bundle = Bundle::new(LocalBundleFs::new(dir.path()));
+            let index = bundle.index().unwrap();
+            assert_eq!(index.hasher(), hasher);
+
+            let mut paths = index.paths().collect::<Vec<_>>();
+            paths.sort_unstable();
+            let mut expected = files.iter().map(|(path, _)| *path).collect::<Vec<_>>();
+            expected.sort_unstable();
+            assert_eq!(paths, expected);
+
+            for (path, data) in &files {
+                assert_eq!(index.read_by_name(path).unwrap().as_ref(), Some(data));
+            }
+            assert_eq!(index.glob("Data/*.dat64").len(), 2);
+        }
+    }
+}
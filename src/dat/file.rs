@@ -4,23 +4,47 @@ use super::Row;
 
 const VDATA_MAGIC: &[u8] = &[0xbb, 0xbb, 0xbb, 0xbb, 0xbb, 0xbb, 0xbb, 0xbb];
 
+#[derive(Debug, thiserror::Error)]
+pub enum DatError {
+    #[error("dat file is too small for the row count, expected 4 bytes, got {actual}")]
+    MissingRowCount { actual: usize },
+    #[error("dat file has no variable data section")]
+    MissingVarData,
+    #[error("dat file is truncated, expected {expected} bytes of rows, got {actual}")]
+    Truncated { expected: usize, actual: usize },
+    #[error("string @ {offset} is out of bounds of the variable data section with {size} bytes")]
+    StringOutOfBounds { offset: u64, size: usize },
+    #[error("row {index} of '{table}' does not exist, the table has {row_count} rows")]
+    MissingRow {
+        table: &'static str,
+        index: usize,
+        row_count: usize,
+    },
+}
+
 #[derive(Copy, Clone)]
 pub struct VarDataReader<'a>(&'a [u8]);
 
 impl<'a> VarDataReader<'a> {
-    pub fn get_string_from(&self, loc: &[u8]) -> DatString<'a> {
+    pub fn get_string_from(&self, loc: &[u8]) -> Result<DatString<'a>, DatError> {
         let loc = u64::from_le_bytes(loc[0..8].try_into().unwrap());
         self.get_string(loc)
     }
 
-    pub fn get_string(&self, offset: u64) -> DatString<'a> {
-        let offset = offset as usize;
-        let idx = self.0[offset..]
+    pub fn get_string(&self, offset: u64) -> Result<DatString<'a>, DatError> {
+        let Some(data) = usize::try_from(offset).ok().and_then(|o| self.0.get(o..)) else {
+            return Err(DatError::StringOutOfBounds {
+                offset,
+                size: self.0.len(),
+            });
+        };
+
+        let idx = data
             .chunks_exact(2)
             .position(|a| a == [0, 0])
             .map(|idx| idx * 2)
-            .unwrap_or(self.0.len());
-        DatString(&self.0[offset..offset + idx])
+            .unwrap_or(data.len());
+        Ok(DatString(&data[..idx]))
     }
 }
 
@@ -64,26 +88,35 @@ pub struct DatFile<'a, R: Row> {
 }
 
 impl<'a, R: Row> DatFile<'a, R> {
-    pub fn new(data: impl Into<Cow<'a, [u8]>>) -> Self {
+    pub fn new(data: impl Into<Cow<'a, [u8]>>) -> Result<Self, DatError> {
         let data = data.into();
-        // TODO: this can panic
-        let row_count = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
 
-        // TODO: errors
+        let Some(&[a, b, c, d]) = data.first_chunk::<4>() else {
+            return Err(DatError::MissingRowCount { actual: data.len() });
+        };
+        let row_count = u32::from_le_bytes([a, b, c, d]) as usize;
+
         let boundary = data
             .windows(VDATA_MAGIC.len())
             .position(|window| window == VDATA_MAGIC)
-            .expect("magic");
+            .ok_or(DatError::MissingVarData)?;
 
-        Self {
+        // All rows are stored between the row count and the variable data.
+        let expected = row_count.saturating_mul(R::SIZE);
+        let actual = boundary.saturating_sub(4);
+        if expected > actual {
+            return Err(DatError::Truncated { expected, actual });
+        }
+
+        Ok(Self {
             row_count,
             data,
             boundary,
             _row: Default::default(),
-        }
+        })
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = R::Item<'_>> + '_ {
+    pub fn iter(&self) -> impl Iterator<Item = Result<R::Item<'_>, DatError>> + '_ {
         let vdr = VarDataReader(&self.data[self.boundary..]);
         self.data[4..]
             .chunks_exact(R::SIZE)
@@ -91,11 +124,24 @@ impl<'a, R: Row> DatFile<'a, R> {
             .map(move |row| R::parse(row, vdr))
     }
 
-    pub fn get(&self, index: usize) -> Option<R::Item<'_>> {
+    /// Returns the row at `index` or `None` if the row does not exist.
+    pub fn get(&self, index: usize) -> Result<Option<R::Item<'_>>, DatError> {
+        if index >= self.row_count {
+            return Ok(None);
+        }
+
         let start = 4 + index * R::SIZE;
-        let end = start + R::SIZE;
         let vdr = VarDataReader(&self.data[self.boundary..]);
-        self.data.get(start..end).map(|row| R::parse(row, vdr))
+        R::parse(&self.data[start..start + R::SIZE], vdr).map(Some)
+    }
+
+    /// Returns the row at `index` or an error if the row does not exist.
+    pub fn row(&self, index: usize) -> Result<R::Item<'_>, DatError> {
+        self.get(index)?.ok_or(DatError::MissingRow {
+            table: R::FILE,
+            index,
+            row_count: self.row_count,
+        })
     }
 }
 
@@ -106,3 +152,105 @@ impl<'a, R: Row> std::fmt::Debug for DatFile<'a, R> {
             .finish_non_exhaustive()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Words;
+
+    /// Creates a `Words` table with a row for every string offset.
+    fn words(offsets: &[u64], var_data: &[u8]) -> Vec<u8> {
+        let mut data = (offsets.len() as u32).to_le_bytes().to_vec();
+        for offset in offsets {
+            let mut row = [0; Words::SIZE];
+            row[4..12].copy_from_slice(&offset.to_le_bytes());
+            data.extend(row);
+        }
+        data.extend(VDATA_MAGIC);
+        data.extend(var_data);
+        data
+    }
+
+    fn text(words: Words<'_>) -> String {
+        String::try_from(&words.text).unwrap()
+    }
+
+    #[test]
+    fn parse_rows() {
+        // Offsets are relative to the start of the magic.
+        let data = words(&[8, 12], &[b'a', 0, 0, 0, b'b', 0, 0, 0]);
+        let file = DatFile::<Words>::new(data).unwrap();
+
+        let rows = file
+            .iter()
+            .map(|row| text(row.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(rows, ["a", "b"]);
+        assert_eq!(text(file.row(1).unwrap()), "b");
+    }
+
+    #[test]
+    fn missing_row_count() {
+        for data in [&[][..], &[1, 0, 0]] {
+            assert!(matches!(
+                DatFile::<Words>::new(data),
+                Err(DatError::MissingRowCount { actual }) if actual == data.len()
+            ));
+        }
+    }
+
+    #[test]
+    fn missing_var_data() {
+        let mut data = words(&[0], &[]);
+        data.truncate(data.len() - 1);
+        assert!(matches!(
+            DatFile::<Words>::new(data),
+            Err(DatError::MissingVarData)
+        ));
+    }
+
+    #[test]
+    fn truncated_rows() {
+        let mut data = words(&[0, 0], &[]);
+        data[..4].copy_from_slice(&3u32.to_le_bytes());
+        assert!(matches!(
+            DatFile::<Words>::new(&data),
+            Err(DatError::Truncated { expected, actual }) if expected == 3 * Words::SIZE && actual == 2 * Words::SIZE
+        ));
+
+        data[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            DatFile::<Words>::new(&data),
+            Err(DatError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn string_out_of_bounds() {
+        for offset in [17, 1 << 32, u64::MAX] {
+            let file = DatFile::<Words>::new(words(&[0, offset], &[0; 8])).unwrap();
+
+            assert!(file.row(0).is_ok());
+            let size = VDATA_MAGIC.len() + 8;
+            assert!(matches!(
+                file.row(1),
+                Err(DatError::StringOutOfBounds { offset: o, size: s }) if o == offset && s == size
+            ));
+            assert_eq!(file.iter().filter(Result::is_err).count(), 1);
+        }
+    }
+
+    #[test]
+    fn missing_row() {
+        let file = DatFile::<Words>::new(words(&[0], &[])).unwrap();
+        assert!(file.get(1).unwrap().is_none());
+        assert!(matches!(
+            file.row(1),
+            Err(DatError::MissingRow {
+                index: 1,
+                row_count: 1,
+                ..
+            })
+        ));
+    }
+}
@@ -4,6 +4,6 @@ mod tables;
 mod utils;
 
 pub(crate) use self::file::VarDataReader;
-pub use self::file::{DatFile, DatString};
+pub use self::file::{DatError, DatFile, DatString};
 pub(crate) use self::row::Row;
 pub use self::tables::*;
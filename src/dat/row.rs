@@ -1,4 +1,5 @@
 use super::file::VarDataReader;
+use super::DatError;
 use crate::BundleFile;
 
 pub trait Row {
@@ -7,7 +8,7 @@ pub trait Row {
 
     type Item<'a>;
 
-    fn parse<'a>(data: &'a [u8], var_data: VarDataReader<'a>) -> Self::Item<'a>;
+    fn parse<'a>(data: &'a [u8], var_data: VarDataReader<'a>) -> Result<Self::Item<'a>, DatError>;
 }
 
 impl<T: Row> BundleFile for T {
@@ -15,7 +16,7 @@ impl<T: Row> BundleFile for T {
 
     type Output = super::DatFile<'static, Self>;
 
-    fn from(data: Vec<u8>) -> Self::Output {
+    fn from(data: Vec<u8>) -> Result<Self::Output, DatError> {
         Self::Output::new(std::borrow::Cow::Owned(data))
     }
 }
@@ -1,4 +1,4 @@
-use super::{utils::parse_u64, DatString, Row, VarDataReader};
+use super::{utils::parse_u64, DatError, DatString, Row, VarDataReader};
 
 #[derive(Debug)]
 pub struct BaseItemTypes<'a> {
@@ -13,16 +13,16 @@ impl<'ty> Row for BaseItemTypes<'ty> {
 
     type Item<'a> = BaseItemTypes<'a>;
 
-    fn parse<'a>(data: &'a [u8], var_data: VarDataReader<'a>) -> Self::Item<'a> {
-        let id = var_data.get_string_from(&data[0..]);
-        let name = var_data.get_string_from(&data[32..]);
+    fn parse<'a>(data: &'a [u8], var_data: VarDataReader<'a>) -> Result<Self::Item<'a>, DatError> {
+        let id = var_data.get_string_from(&data[0..])?;
+        let name = var_data.get_string_from(&data[32..])?;
         let item_visual_identity = parse_u64(&data[128..]);
 
-        BaseItemTypes {
+        Ok(BaseItemTypes {
             id,
             name,
             item_visual_identity,
-        }
+        })
     }
 }
 
@@ -39,16 +39,16 @@ impl<'ty> Row for ItemVisualIdentity<'ty> {
 
     type Item<'a> = ItemVisualIdentity<'a>;
 
-    fn parse<'a>(data: &'a [u8], var_data: VarDataReader<'a>) -> ItemVisualIdentity<'a> {
-        let id = var_data.get_string_from(&data[0..]);
-        let dds_file = var_data.get_string_from(&data[8..]);
+    fn parse<'a>(data: &'a [u8], var_data: VarDataReader<'a>) -> Result<Self::Item<'a>, DatError> {
+        let id = var_data.get_string_from(&data[0..])?;
+        let dds_file = var_data.get_string_from(&data[8..])?;
         let is_alternate_art = data[300] == 1;
 
-        ItemVisualIdentity {
+        Ok(ItemVisualIdentity {
             id,
             dds_file,
             is_alternate_art,
-        }
+        })
     }
 }
 
@@ -64,14 +64,14 @@ impl Row for UniqueStashLayout {
 
     type Item<'a> = UniqueStashLayout;
 
-    fn parse<'a>(data: &'a [u8], _var_data: VarDataReader<'a>) -> Self::Item<'a> {
+    fn parse<'a>(data: &'a [u8], _var_data: VarDataReader<'a>) -> Result<Self::Item<'a>, DatError> {
         let words = parse_u64(&data[0..]);
         let item_visual_identity = parse_u64(&data[16..]);
 
-        UniqueStashLayout {
+        Ok(UniqueStashLayout {
             words,
             item_visual_identity,
-        }
+        })
     }
 }
 
@@ -86,9 +86,9 @@ impl<'ty> Row for Words<'ty> {
 
     type Item<'a> = Words<'a>;
 
-    fn parse<'a>(data: &'a [u8], var_data: VarDataReader<'a>) -> Self::Item<'a> {
-        let text = var_data.get_string_from(&data[4..]);
+    fn parse<'a>(data: &'a [u8], var_data: VarDataReader<'a>) -> Result<Self::Item<'a>, DatError> {
+        let text = var_data.get_string_from(&data[4..])?;
 
-        Words { text }
+        Ok(Words { text })
     }
 }
@@ -14,4 +14,4 @@ pub use self::image::{Dds as Image, ImageError};
 pub use self::pipeline::{File, Kind, Pipeline};
 #[cfg(feature = "web")]
 pub use self::utils::latest_patch_version;
-pub use self::utils::{filepath_hash, Fnv1a64};
+pub use self::utils::{dirpath_hash, filepath_hash, murmur_hash64a, Fnv1a64, PathHasher};
@@ -10,6 +10,22 @@ struct Args {
     #[bpaf(external, optional)]
     cache: Option<Cache>,
 
+    /// Directory for pre-parsed snapshots of the index, speeds up loading the index.
+    #[bpaf(argument("PATH"), optional)]
+    index_snapshot: Option<std::path::PathBuf>,
+
+    /// Directory for decompressed files, speeds up repeated runs of the asset pipeline.
+    #[bpaf(argument("PATH"), optional)]
+    file_cache: Option<std::path::PathBuf>,
+
+    /// Maximum size of the file cache, least recently used files are evicted after every run.
+    #[bpaf(argument("BYTES"), optional)]
+    file_cache_budget: Option<u64>,
+
+    /// Directory of loose files which override bundled files, can be repeated.
+    #[bpaf(argument("PATH"), many)]
+    overlay: Vec<std::path::PathBuf>,
+
     #[bpaf(external)]
     action: Action,
 }
@@ -30,6 +46,13 @@ enum Fs {
         /// Local path to bundle.
         #[bpaf(argument("PATH"))]
         path: String,
+        /// Memory map the local bundles instead of reading them.
+        mmap: bool,
+    },
+    Ggpk {
+        /// Local path to a legacy Content.ggpk archive.
+        #[bpaf(argument("PATH"))]
+        ggpk: std::path::PathBuf,
     },
 }
 
@@ -41,6 +64,30 @@ enum Cache {
         /// Local filesystem cache.
         #[bpaf(argument("PATH"))]
         local_cache: std::path::PathBuf,
+        /// Maximum size of the local cache, least recently used files are evicted first.
+        #[bpaf(argument("BYTES"), optional)]
+        cache_budget: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Bpaf)]
+enum CacheAction {
+    /// Evict least recently used files until the cache fits into the budget.
+    #[bpaf(command)]
+    Prune {
+        /// Maximum size of the cache after pruning.
+        #[bpaf(argument("BYTES"))]
+        budget: u64,
+    },
+    /// Print the amount and total size of cached files.
+    #[bpaf(command)]
+    Stats,
+    /// Remove all cached patch versions except the latest ones.
+    #[bpaf(command)]
+    Retain {
+        /// Amount of patch versions to keep.
+        #[bpaf(argument("N"))]
+        latest: usize,
     },
 }
 
@@ -52,6 +99,31 @@ enum Action {
     /// Extract a file to the current directory.
     #[bpaf(command)]
     Extract(String),
+    /// List the contents of a bundled directory.
+    #[bpaf(command)]
+    Ls(#[bpaf(positional("DIR"), fallback(String::new()))] String),
+    /// Find bundled files matching a glob pattern.
+    #[bpaf(command)]
+    Find(#[bpaf(positional("PATTERN"))] String),
+    /// Verify the integrity of all bundles.
+    #[bpaf(command)]
+    Verify,
+    /// Manage the local filesystem cache.
+    #[bpaf(command)]
+    Cache(#[bpaf(external(cache_action))] CacheAction),
+    /// Compare the files of a patch version with newer bundles, prints the differences as JSON.
+    #[bpaf(command)]
+    Diff {
+        /// Old patch version.
+        #[bpaf(argument("PATCH"))]
+        from: String,
+        /// New patch version, defaults to the selected bundles.
+        #[bpaf(argument("PATCH"), optional)]
+        to: Option<String>,
+        /// Only compare files matching a glob pattern.
+        #[bpaf(positional("PATTERN"), optional)]
+        pattern: Option<String>,
+    },
     /// Runs the asset pipeline.
     #[bpaf(command)]
     Assets {
@@ -62,49 +134,215 @@ enum Action {
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = args().run();
+    let Args {
+        fs,
+        cache,
+        index_snapshot,
+        file_cache,
+        file_cache_budget,
+        overlay,
+        action,
+    } = args().run();
 
     tracing_subscriber::fmt::init();
 
-    let fs: Box<dyn pobbin_assets::BundleFs> = match args.fs {
-        Some(Fs::Patch { patch }) => Box::new(pobbin_assets::WebBundleFs::cdn(&patch)),
-        Some(Fs::Web { web }) => Box::new(pobbin_assets::WebBundleFs::new(web)),
-        Some(Fs::Local { path }) => Box::new(pobbin_assets::LocalBundleFs::new(path)),
-        None => Box::new(pobbin_assets::WebBundleFs::cdn(
-            &pobbin_assets::latest_patch_version()?,
-        )),
+    let source = || open(fs.clone(), cache.as_ref(), index_snapshot.as_deref());
+
+    match action {
+        Action::Sha(file) => {
+            let source = source()?;
+            sha(source.fs, source.snapshot, &overlay, &file)
+        }
+        Action::Extract(file) => {
+            let source = source()?;
+            extract(source.fs, source.snapshot, &overlay, &file)
+        }
+        Action::Ls(dir) => {
+            let source = source()?;
+            ls(source.fs, source.snapshot, &overlay, &dir)
+        }
+        Action::Find(pattern) => {
+            let source = source()?;
+            find(source.fs, source.snapshot, &overlay, &pattern)
+        }
+        Action::Verify => verify(source()?.fs),
+        Action::Assets { out } => {
+            let file_cache = file_cache.map(pobbin_assets::LocalCache::new);
+            assets(source()?.fs, out, file_cache.clone(), overlay)?;
+
+            if let (Some(file_cache), Some(budget)) = (file_cache, file_cache_budget) {
+                let removed = file_cache.prune(budget)?;
+                tracing::info!(
+                    "pruned the file cache, removed {} files ({} bytes)",
+                    removed.files,
+                    removed.bytes
+                );
+            }
+            Ok(())
+        }
+        // Cache maintenance does not need a filesystem.
+        Action::Cache(action) => {
+            let Some(Cache::LocalCache { local_cache, .. }) = &cache else {
+                anyhow::bail!("cache commands require --local-cache");
+            };
+            manage_cache(pobbin_assets::LocalCache::new(local_cache), &action)
+        }
+        // The old version is always fetched from the patch CDN,
+        // the new version is read from the selected filesystem unless it is a patch version as well.
+        Action::Diff { from, to, pattern } => {
+            let new = match to {
+                Some(_) if fs.is_some() => {
+                    anyhow::bail!("--to can not be combined with --patch, --web, --path or --ggpk")
+                }
+                Some(patch) => open(
+                    Some(Fs::Patch { patch }),
+                    cache.as_ref(),
+                    index_snapshot.as_deref(),
+                )?,
+                None => source()?,
+            };
+            let old = open(
+                Some(Fs::Patch { patch: from }),
+                cache.as_ref(),
+                index_snapshot.as_deref(),
+            )?;
+            diff(old, new, pattern.as_deref())
+        }
+    }
+}
+
+/// Filesystem selected on the command line, the index can be shared between threads.
+type DynBundleFs = Box<dyn pobbin_assets::BundleFs + Send + Sync>;
+
+/// Bundles selected on the command line and the index snapshot to load them with.
+struct Source {
+    fs: DynBundleFs,
+    snapshot: Option<Snapshot>,
+}
+
+/// Opens the filesystem of the bundles and wraps it in the selected cache.
+///
+/// Without a filesystem, the latest patch version is fetched from the patch CDN.
+fn open(
+    fs: Option<Fs>,
+    cache: Option<&Cache>,
+    index_snapshot: Option<&std::path::Path>,
+) -> anyhow::Result<Source> {
+    // Snapshots are found by the patch version or by the fingerprint of the index,
+    // cached files are stored separately for every patch version or source.
+    let (fs, version, namespace): (DynBundleFs, _, _) = match fs {
+        Some(Fs::Patch { patch }) => (
+            Box::new(pobbin_assets::WebBundleFs::cdn(&patch)),
+            Some(patch.clone()),
+            patch,
+        ),
+        Some(Fs::Web { web }) => {
+            let namespace = source_namespace(&web);
+            (
+                Box::new(pobbin_assets::WebBundleFs::new(web)),
+                None,
+                namespace,
+            )
+        }
+        Some(Fs::Local { path, mmap }) => {
+            let namespace = source_namespace(&path);
+            let fs = pobbin_assets::LocalBundleFs::new(path);
+            let fs = if mmap { fs.with_mmap() } else { fs };
+            (Box::new(fs), None, namespace)
+        }
+        Some(Fs::Ggpk { ggpk }) => {
+            let namespace = source_namespace(&ggpk.display().to_string());
+            let fs = pobbin_assets::GgpkBundleFs::open(ggpk)?;
+            (Box::new(fs), None, namespace)
+        }
+        None => {
+            let patch = pobbin_assets::latest_patch_version()?;
+            (
+                Box::new(pobbin_assets::WebBundleFs::cdn(&patch)),
+                Some(patch.clone()),
+                patch,
+            )
+        }
     };
 
-    let fs: Box<dyn pobbin_assets::BundleFs> = match args.cache {
+    let snapshot = index_snapshot.map(|dir| {
+        let key = match version {
+            Some(version) => pobbin_assets::SnapshotKey::Version(version),
+            None => pobbin_assets::SnapshotKey::Fingerprint,
+        };
+        (dir.to_owned(), key)
+    });
+
+    let fs: DynBundleFs = match cache {
         Some(Cache::InMemoryCache) => Box::new(pobbin_assets::CacheBundleFs::new(
             fs,
             pobbin_assets::InMemoryCache::new(),
         )),
-        Some(Cache::LocalCache { local_cache }) => Box::new(pobbin_assets::CacheBundleFs::new(
-            fs,
-            pobbin_assets::LocalCache::new(local_cache),
-        )),
+        Some(Cache::LocalCache {
+            local_cache,
+            cache_budget,
+        }) => {
+            let cache = pobbin_assets::LocalCache::new(local_cache).with_namespace(namespace);
+            let cache = match cache_budget {
+                Some(budget) => cache.with_budget(*budget),
+                None => cache,
+            };
+            Box::new(pobbin_assets::CacheBundleFs::new(fs, cache))
+        }
         None => fs,
     };
 
-    match args.action {
-        Action::Sha(file) => sha(fs, &file),
-        Action::Extract(file) => extract(fs, &file),
-        Action::Assets { out } => assets(fs, out),
-    }
+    Ok(Source { fs, snapshot })
+}
+
+/// Cache namespace for bundles without a patch version, derived from their location.
+fn source_namespace(source: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(source.as_bytes()));
+    format!("source-{}", &hash[..16])
+}
+
+/// Directory and key of an index snapshot.
+type Snapshot = (std::path::PathBuf, pobbin_assets::SnapshotKey);
+
+fn index<F: pobbin_assets::BundleFs>(
+    bundle: &pobbin_assets::Bundle<F>,
+    snapshot: Option<Snapshot>,
+) -> anyhow::Result<pobbin_assets::IndexBundle<&F>> {
+    let index = match snapshot {
+        Some((dir, key)) => bundle.index_snapshot(dir, key)?,
+        None => bundle.index()?,
+    };
+    Ok(index)
+}
+
+/// Layers the overlay directories over the index, later directories take precedence.
+fn overlay<F>(
+    index: pobbin_assets::IndexBundle<F>,
+    overlays: &[std::path::PathBuf],
+) -> pobbin_assets::OverlayIndex<F> {
+    overlays
+        .iter()
+        .fold(pobbin_assets::OverlayIndex::new(index), |index, dir| {
+            index.with_overlay(dir)
+        })
 }
 
-fn sha<F: pobbin_assets::BundleFs>(fs: F, file: &str) -> anyhow::Result<()> {
+fn sha<F: pobbin_assets::BundleFs>(
+    fs: F,
+    snapshot: Option<Snapshot>,
+    overlays: &[std::path::PathBuf],
+    file: &str,
+) -> anyhow::Result<()> {
     let bundle = pobbin_assets::Bundle::new(fs);
-    let index = bundle.index()?;
+    let index = overlay(index(&bundle, snapshot)?, overlays);
 
-    let contents = index
-        .read_by_name(file)?
+    let mut contents = index
+        .open(file)?
         .ok_or_else(|| anyhow::anyhow!("file {file} can not be found"))?;
 
     let sha256 = {
         let mut hasher = Sha256::new();
-        hasher.update(contents);
+        std::io::copy(&mut contents, &mut hasher)?;
         hasher.finalize()
     };
     println!("{sha256:x}");
@@ -112,28 +350,157 @@ fn sha<F: pobbin_assets::BundleFs>(fs: F, file: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn extract<F: pobbin_assets::BundleFs>(fs: F, file: &str) -> anyhow::Result<()> {
+fn extract<F: pobbin_assets::BundleFs>(
+    fs: F,
+    snapshot: Option<Snapshot>,
+    overlays: &[std::path::PathBuf],
+    file: &str,
+) -> anyhow::Result<()> {
     let bundle = pobbin_assets::Bundle::new(fs);
-    let index = bundle.index()?;
+    let index = overlay(index(&bundle, snapshot)?, overlays);
 
-    let contents = index
-        .read_by_name(file)?
+    let mut contents = index
+        .open(file)?
         .ok_or_else(|| anyhow::anyhow!("file {file} can not be found"))?;
 
     let path = std::path::PathBuf::from(file);
-    std::fs::write(path.file_name().unwrap(), contents)?;
+    let mut out = std::fs::File::create(path.file_name().unwrap())?;
+    std::io::copy(&mut contents, &mut out)?;
+
+    Ok(())
+}
+
+fn ls<F: pobbin_assets::BundleFs>(
+    fs: F,
+    snapshot: Option<Snapshot>,
+    overlays: &[std::path::PathBuf],
+    dir: &str,
+) -> anyhow::Result<()> {
+    use pobbin_assets::OverlayDirEntry;
+
+    let bundle = pobbin_assets::Bundle::new(fs);
+    let index = overlay(index(&bundle, snapshot)?, overlays);
+
+    let entries = index
+        .list(dir)?
+        .ok_or_else(|| anyhow::anyhow!("directory {dir} can not be found"))?;
+
+    for entry in entries {
+        match entry {
+            OverlayDirEntry::Directory(dir) => println!("{dir}/"),
+            OverlayDirEntry::File(file) => println!("{file}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn find<F: pobbin_assets::BundleFs>(
+    fs: F,
+    snapshot: Option<Snapshot>,
+    overlays: &[std::path::PathBuf],
+    pattern: &str,
+) -> anyhow::Result<()> {
+    let bundle = pobbin_assets::Bundle::new(fs);
+    let index = overlay(index(&bundle, snapshot)?, overlays);
+
+    for file in index.glob(pattern)? {
+        println!("{file}");
+    }
+
+    Ok(())
+}
+
+fn verify<F: pobbin_assets::BundleFs>(fs: F) -> anyhow::Result<()> {
+    let bundle = pobbin_assets::Bundle::new(fs);
+
+    let mut failed = 0;
+    for report in bundle.verify()? {
+        if report.is_ok() {
+            println!("ok      {} ({} files)", report.name, report.files);
+            continue;
+        }
+
+        failed += 1;
+        println!("FAILED  {} ({} files)", report.name, report.files);
+        for problem in &report.problems {
+            println!("        {problem}");
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{failed} bundles failed verification");
+    }
+
+    Ok(())
+}
+
+fn manage_cache(cache: pobbin_assets::LocalCache, action: &CacheAction) -> anyhow::Result<()> {
+    match action {
+        CacheAction::Prune { budget } => {
+            let removed = cache.prune(*budget)?;
+            println!("removed {} files ({} bytes)", removed.files, removed.bytes);
+        }
+        CacheAction::Stats => {
+            let stats = cache.stats()?;
+            println!("{} files ({} bytes)", stats.files, stats.bytes);
+        }
+        CacheAction::Retain { latest } => {
+            let removed = cache.retain_latest(*latest)?;
+            println!("removed {} files ({} bytes)", removed.files, removed.bytes);
+        }
+    }
 
     Ok(())
 }
 
-fn assets<F: pobbin_assets::BundleFs>(fs: F, out: std::path::PathBuf) -> anyhow::Result<()> {
+fn diff(old: Source, new: Source, pattern: Option<&str>) -> anyhow::Result<()> {
+    let from_bundle = pobbin_assets::Bundle::new(old.fs);
+    let from_index = index(&from_bundle, old.snapshot)?;
+    let to_bundle = pobbin_assets::Bundle::new(new.fs);
+    let to_index = index(&to_bundle, new.snapshot)?;
+
+    let diff = match pattern {
+        Some(pattern) => {
+            let matches = from_index
+                .glob(pattern)
+                .into_iter()
+                .chain(to_index.glob(pattern))
+                .collect::<std::collections::HashSet<_>>();
+            pobbin_assets::IndexDiff::filtered(&from_index, &to_index, |path| {
+                matches.contains(path)
+            })?
+        }
+        None => pobbin_assets::IndexDiff::new(&from_index, &to_index)?,
+    };
+
+    serde_json::to_writer_pretty(std::io::stdout().lock(), &diff)?;
+    println!();
+
+    Ok(())
+}
+
+fn assets<F: pobbin_assets::BundleFs>(
+    fs: F,
+    out: std::path::PathBuf,
+    file_cache: Option<pobbin_assets::LocalCache>,
+    overlays: Vec<std::path::PathBuf>,
+) -> anyhow::Result<()> {
     use pobbin_assets::{File, Image, Kind};
 
     if !out.is_dir() {
         anyhow::bail!("out path '{}' is not a directory", out.display());
     }
 
-    pobbin_assets::Pipeline::new(fs, out)
+    let mut pipeline = pobbin_assets::Pipeline::new(fs, out);
+    if let Some(file_cache) = file_cache {
+        pipeline.file_cache(file_cache);
+    }
+    for dir in overlays {
+        pipeline.overlay(dir);
+    }
+
+    pipeline
         .select(|file: &File| file.id.starts_with("Metadata/Items/Gems"))
         .select(|file: &File| file.id.starts_with("Metadata/Items/Belts"))
         .select(|file: &File| file.id.starts_with("Metadata/Items/Rings"))
@@ -1,8 +1,8 @@
-use std::{io::Write, path::PathBuf};
+use std::{collections::BTreeMap, io::Write, path::PathBuf, sync::Arc};
 
 use crate::{
-    image, BaseItemTypes, Bundle, BundleFs, DatString, Image, ImageError, ItemVisualIdentity,
-    UniqueStashLayout, Words,
+    image, BaseItemTypes, Bundle, BundleFs, DatString, FileCache, Image, ImageError,
+    ItemVisualIdentity, OverlayIndex, UniqueStashLayout, Words,
 };
 
 pub struct Pipeline<F: BundleFs> {
@@ -10,6 +10,8 @@ pub struct Pipeline<F: BundleFs> {
     out: PathBuf,
     selectors: Vec<Box<dyn Matcher>>,
     postprocess: Vec<(Box<dyn Matcher>, Box<dyn Postprocess>)>,
+    file_cache: Option<Arc<dyn FileCache>>,
+    overlays: Vec<PathBuf>,
 }
 
 impl<F: BundleFs> Pipeline<F> {
@@ -19,6 +21,8 @@ impl<F: BundleFs> Pipeline<F> {
             out: out.into(),
             selectors: Vec::new(),
             postprocess: Vec::new(),
+            file_cache: None,
+            overlays: Vec::new(),
         }
     }
 
@@ -37,15 +41,37 @@ impl<F: BundleFs> Pipeline<F> {
         self
     }
 
+    /// Caches decompressed files, unchanged files are not decompressed again in later runs.
+    pub fn file_cache(&mut self, cache: impl FileCache + 'static) -> &mut Self {
+        self.file_cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Reads loose files from `dir` instead of the bundled files, see [`OverlayIndex`].
+    pub fn overlay(&mut self, dir: impl Into<PathBuf>) -> &mut Self {
+        self.overlays.push(dir.into());
+        self
+    }
+
     pub fn execute(&self) -> anyhow::Result<()> {
         let bundle = Bundle::new(&self.fs);
         let index = bundle.index()?;
+        let index = match &self.file_cache {
+            Some(cache) => index.with_file_cache(Arc::clone(cache)),
+            None => index,
+        };
+        let index = self
+            .overlays
+            .iter()
+            .fold(OverlayIndex::new(index), |index, dir| {
+                index.with_overlay(dir)
+            });
 
         macro_rules! read {
             ($name:ident, $type:ty) => {
                 let Some($name) = index.read::<$type>()? else {
-                                    anyhow::bail!("{} table does not exist", stringify!($type));
-                                };
+                    anyhow::bail!("{} table does not exist", stringify!($type));
+                };
             };
         }
 
@@ -54,43 +80,39 @@ impl<F: BundleFs> Pipeline<F> {
         read!(words, Words);
         read!(vis, ItemVisualIdentity);
 
-        let bases = bases.iter().map(|base| File {
-            kind: Kind::Base,
-            id: base.id,
-            item_visual_identity: base.item_visual_identity,
-            name: base.name,
-        });
-
-        let uniques = uniques.iter().map(|unique| {
-            // TODO: this is trash, vis gets quereid later again, no error handling
-            let name = words
-                .get(unique.words as usize)
-                .expect("word for unique")
-                .text;
-            let id = vis
-                .get(unique.item_visual_identity as usize)
-                .expect("vis for unique")
-                .id;
-
-            File {
+        let mut files = Vec::new();
+        for base in bases.iter() {
+            let base = base?;
+            files.push(File {
+                kind: Kind::Base,
+                id: base.id,
+                item_visual_identity: base.item_visual_identity,
+                name: base.name,
+            });
+        }
+
+        for unique in uniques.iter() {
+            // TODO: this is trash, vis gets quereid later again
+            let unique = unique?;
+            let name = words.row(unique.words as usize)?.text;
+            let id = vis.row(unique.item_visual_identity as usize)?.id;
+
+            files.push(File {
                 kind: Kind::Unique,
                 id,
                 item_visual_identity: unique.item_visual_identity,
                 name,
-            }
-        });
-
-        let files = bases
-            .chain(uniques)
-            .filter(|f| self.selectors.iter().any(|s| s.matches(f)))
-            .map(|base| {
-                let idx = base.item_visual_identity as usize;
-                (base, vis.get(idx))
             });
+        }
 
-        let mut total = 0usize;
-        for (item, vis) in files {
-            let Some(vis) = vis else {
+        let files = files
+            .into_iter()
+            .filter(|f| self.selectors.iter().any(|s| s.matches(f)));
+
+        // Items grouped by their dds file, multiple items can share the same file.
+        let mut targets = BTreeMap::<String, Vec<(String, File)>>::new();
+        for item in files {
+            let Some(vis) = vis.get(item.item_visual_identity as usize)? else {
                 tracing::warn!("item '{item:?}' has no visual identity");
                 continue;
             };
@@ -109,30 +131,34 @@ impl<F: BundleFs> Pipeline<F> {
                 continue;
             };
 
-            let Some(dds) = index.read_by_name(&dds_file)? else {
-                tracing::warn!("dds file '{dds_file}' does not exist");
-                continue;
-            };
+            targets.entry(dds_file).or_default().push((name, item));
+        }
 
-            let Ok(mut dds) = image::Dds::try_from(&*dds) else {
-                tracing::warn!("unable to read dds {dds_file}");
-                continue;
-            };
+        let mut total = 0usize;
+        for dds in index.read_many(targets.keys()) {
+            let (dds_file, dds) = dds?;
+
+            for (name, item) in &targets[dds_file] {
+                let Ok(mut dds) = image::Dds::try_from(&*dds) else {
+                    tracing::warn!("unable to read dds {dds_file}");
+                    continue;
+                };
+
+                for (m, pp) in &self.postprocess {
+                    if m.matches(item) {
+                        pp.postprocess(&mut dds)?;
+                    }
+                }
 
-            for (m, pp) in &self.postprocess {
-                if m.matches(&item) {
-                    pp.postprocess(&mut dds)?;
+                let out = self.out.join(format!("{name}.webp"));
+                {
+                    let mut out = std::fs::File::create(&out)?;
+                    out.write_all(&dds.write_blob("webp")?)?;
                 }
-            }
 
-            let out = self.out.join(format!("{name}.webp"));
-            {
-                let mut out = std::fs::File::create(&out)?;
-                out.write_all(&dds.write_blob("webp")?)?;
+                tracing::debug!("generated file '{name}' -> {}", out.display());
+                total += 1;
             }
-
-            tracing::debug!("generated file '{name}' -> {}", out.display());
-            total += 1;
         }
 
         tracing::info!("extracted a total of {total} assets");
@@ -28,6 +28,72 @@ impl Default for Fnv1a64 {
     }
 }
 
+/// MurmurHash64A as used by the game for path hashes of newer patches.
+pub fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut hash = seed ^ (data.len() as u64).wrapping_mul(M);
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+
+        hash ^= k;
+        hash = hash.wrapping_mul(M);
+    }
+
+    let remainder = chunks.remainder();
+    if !remainder.is_empty() {
+        let mut tail = [0u8; 8];
+        tail[..remainder.len()].copy_from_slice(remainder);
+        hash ^= u64::from_le_bytes(tail);
+        hash = hash.wrapping_mul(M);
+    }
+
+    hash ^= hash >> R;
+    hash = hash.wrapping_mul(M);
+    hash ^= hash >> R;
+    hash
+}
+
+/// Hash algorithm used for file and directory paths in the bundle index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathHasher {
+    /// FNV1a of the lower-cased path with a `++` suffix, used by older patches.
+    #[default]
+    Fnv1a,
+    /// MurmurHash64A of the path with a fixed seed, used by newer patches.
+    Murmur64A,
+}
+
+impl PathHasher {
+    const MURMUR_SEED: u64 = 0x1337b33f;
+
+    /// All supported hash algorithms.
+    pub const ALL: [PathHasher; 2] = [PathHasher::Murmur64A, PathHasher::Fnv1a];
+
+    /// Hashes the path of a file.
+    pub fn file(self, name: &str) -> u64 {
+        match self {
+            Self::Fnv1a => filepath_hash(name),
+            Self::Murmur64A => murmur_hash64a(name.as_bytes(), Self::MURMUR_SEED),
+        }
+    }
+
+    /// Hashes the path of a directory, a trailing slash is ignored.
+    pub fn directory(self, name: &str) -> u64 {
+        let name = name.trim_end_matches('/');
+        match self {
+            Self::Fnv1a => dirpath_hash(name),
+            Self::Murmur64A => murmur_hash64a(name.as_bytes(), Self::MURMUR_SEED),
+        }
+    }
+}
+
 pub fn filepath_hash(name: &str) -> u64 {
     let mut hasher = Fnv1a64::new();
     hasher.update(name.to_lowercase().as_bytes());
@@ -35,6 +101,13 @@ pub fn filepath_hash(name: &str) -> u64 {
     hasher.finalize()
 }
 
+pub fn dirpath_hash(name: &str) -> u64 {
+    let mut hasher = Fnv1a64::new();
+    hasher.update(name.as_bytes());
+    hasher.update(b"++");
+    hasher.finalize()
+}
+
 #[allow(clippy::result_large_err)]
 pub fn latest_patch_version() -> Result<String, ureq::Error> {
     let r = ureq::get(
@@ -45,3 +118,68 @@ pub fn latest_patch_version() -> Result<String, ureq::Error> {
     tracing::info!("latest patch version: {ver}");
     Ok(ver)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fnv1a64(data: &[u8]) -> u64 {
+        let mut hasher = Fnv1a64::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    #[test]
+    fn fnv1a64_test_vectors() {
+        // Test vectors of the FNV reference implementation.
+        assert_eq!(fnv1a64(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a64(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a64(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn murmur_hash64a_verification_value() {
+        // Verification test of SMHasher: keys `[0]`, `[0, 1]`, ... are hashed with the seed
+        // `256 - len`, the hash of all hashes with seed `0` starts with the verification value.
+        let key = (0..=255).collect::<Vec<u8>>();
+        let hashes = (0..256)
+            .flat_map(|len| murmur_hash64a(&key[..len], 256 - len as u64).to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let verification = murmur_hash64a(&hashes, 0) as u32;
+        assert_eq!(verification, 0x1F0D3804);
+    }
+
+    #[test]
+    fn murmur_hash64a_handles_every_tail_length() {
+        assert_eq!(murmur_hash64a(b"", 0), 0);
+
+        // Bytes after the last full block must change the hash, whatever their count.
+        let data = (1..=24).collect::<Vec<u8>>();
+        let hashes = (0..=data.len())
+            .map(|len| murmur_hash64a(&data[..len], PathHasher::MURMUR_SEED))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(hashes.len(), data.len() + 1);
+    }
+
+    #[test]
+    fn path_hashers() {
+        // FNV1a hashes of files ignore case, directories and Murmur64A hashes do not.
+        let fnv = PathHasher::Fnv1a;
+        assert_eq!(fnv.file("Data/Mods.dat64"), fnv.file("data/mods.dat64"));
+        assert_eq!(fnv.file("Data/Mods.dat64"), fnv1a64(b"data/mods.dat64++"));
+        assert_eq!(fnv.directory("Data/"), fnv1a64(b"Data++"));
+        assert_ne!(fnv.directory("Data"), fnv.directory("data"));
+
+        let murmur = PathHasher::Murmur64A;
+        assert_ne!(
+            murmur.file("Data/Mods.dat64"),
+            murmur.file("data/mods.dat64")
+        );
+        assert_eq!(
+            murmur.file("Data/Mods.dat64"),
+            murmur_hash64a(b"Data/Mods.dat64", 0x1337b33f)
+        );
+        assert_eq!(murmur.directory("Data/"), murmur.directory("Data"));
+    }
+}